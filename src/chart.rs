@@ -0,0 +1,358 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::PathBuf,
+};
+
+use crate::{
+    diagnostic::{Code, Diagnostic, Span},
+    ledger::{Account, AccountType, LedgerIndexItem},
+    note::Transaction,
+    tr,
+};
+
+/// Chart of accounts
+///
+/// Accounts form a tree, sub-accounts are written as `parent/sub`,
+/// numeric accounts belong to their longest declared prefix, e.g.
+/// 3841 belongs to 38. Sub-accounts of declared accounts are created
+/// on first use.
+#[derive(Debug, Default)]
+pub struct Chart {
+    accounts: BTreeMap<String, ChartAccount>,
+    /// Postings are allowed only to accounts without sub-accounts
+    pub leaf_only: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChartAccount {
+    pub id: String,
+    pub name: String,
+    pub parent: Option<String>,
+    pub account_type: Option<AccountType>,
+    /// Declared in the accounts file, or created on first use
+    pub declared: bool,
+    /// Location of the declaration
+    pub path: PathBuf,
+    pub span: Span,
+}
+
+impl Chart {
+    /// Add declared account
+    ///
+    /// Parents are checked by link, when all the accounts are added.
+    pub fn add(&mut self, account: &Account) {
+        let parent = account.parent.as_deref().or_else(|| parent_id(&account.id));
+        self.accounts.insert(
+            account.id.clone(),
+            ChartAccount {
+                id: account.id.clone(),
+                name: account.name.clone(),
+                parent: parent.map(|p| p.to_string()),
+                account_type: account.account_type,
+                declared: true,
+                path: account.path.clone(),
+                span: account.span,
+            },
+        );
+    }
+    /// Link declared accounts into a tree
+    ///
+    /// Accounts without explicit or path parent get their longest declared
+    /// numeric prefix as parent. Unknown parents and cycles are returned
+    /// as errors, and those accounts become roots.
+    pub fn link(&mut self) -> Vec<Diagnostic> {
+        let mut errors = Vec::new();
+        let error = |account: &ChartAccount, code: Code, message: String| {
+            Diagnostic::error(code, message)
+                .with_path(&account.path)
+                .with_span(account.span)
+        };
+        let ids = self.accounts.keys().cloned().collect::<Vec<String>>();
+        for id in &ids {
+            let account = &self.accounts[id];
+            let parent = match &account.parent {
+                Some(parent) if self.contains(parent) => continue,
+                Some(parent) => {
+                    errors.push(error(
+                        account,
+                        Code::UnknownAccount,
+                        tr!("Parent account {} of {} is not declared", parent, id),
+                    ));
+                    None
+                }
+                None => self.prefix_parent(id),
+            };
+            self.accounts.get_mut(id).unwrap().parent = parent;
+        }
+        for id in &ids {
+            if self.has_cycle(id) {
+                errors.push(
+                    error(
+                        &self.accounts[id],
+                        Code::AccountCycle,
+                        tr!("Account {} is its own ancestor", id),
+                    )
+                    .with_hint(tr!("Check the PARENT of the accounts in the cycle")),
+                );
+                self.accounts.get_mut(id).unwrap().parent = None;
+            }
+        }
+        errors
+    }
+    // Longest declared prefix of a numeric account
+    fn prefix_parent(&self, id: &str) -> Option<String> {
+        if !id.chars().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+        (1..id.len())
+            .rev()
+            .map(|len| &id[..len])
+            .find(|prefix| self.contains(prefix))
+            .map(|prefix| prefix.to_string())
+    }
+    fn has_cycle(&self, id: &str) -> bool {
+        let mut current = self.get(id).and_then(|a| a.parent.as_deref());
+        for _ in 0..self.accounts.len() {
+            match current {
+                Some(parent) if parent == id => return true,
+                Some(parent) => current = self.get(parent).and_then(|a| a.parent.as_deref()),
+                None => return false,
+            }
+        }
+        false
+    }
+    /// Find account, and create it if it is a sub-account
+    /// of an existing account
+    pub fn resolve(&mut self, id: &str) -> bool {
+        if self.contains(id) {
+            return true;
+        }
+        let parent = match parent_id(id) {
+            Some(parent) if self.resolve(parent) => parent.to_string(),
+            _ => return false,
+        };
+        let name = id[parent.len() + 1..].to_string();
+        self.accounts.insert(
+            id.to_string(),
+            ChartAccount {
+                id: id.to_string(),
+                name,
+                parent: Some(parent),
+                account_type: None,
+                declared: false,
+                path: PathBuf::new(),
+                span: Span::default(),
+            },
+        );
+        true
+    }
+    pub fn contains(&self, id: &str) -> bool {
+        self.accounts.contains_key(id)
+    }
+    pub fn get(&self, id: &str) -> Option<&ChartAccount> {
+        self.accounts.get(id)
+    }
+    /// Account IDs
+    pub fn ids(&self) -> impl Iterator<Item = &String> {
+        self.accounts.keys()
+    }
+    /// Parents of an account, closest first
+    pub fn ancestors(&self, id: &str) -> Vec<&str> {
+        let mut res = Vec::new();
+        let mut current = self.get(id).and_then(|a| a.parent.as_deref());
+        while let Some(parent) = current {
+            res.push(parent);
+            current = self.get(parent).and_then(|a| a.parent.as_deref());
+        }
+        res
+    }
+    /// Own type of an account, or its closest typed ancestor's
+    pub fn account_type(&self, id: &str) -> Option<AccountType> {
+        std::iter::once(id)
+            .chain(self.ancestors(id))
+            .find_map(|id| self.get(id).and_then(|a| a.account_type))
+    }
+    /// Warn on transactions mixing off-balance and balance sheet accounts
+    pub fn check_transaction(&self, transaction: &Transaction) -> Option<Diagnostic> {
+        let types = transaction
+            .legs
+            .iter()
+            .filter_map(|leg| self.account_type(&leg.account))
+            .collect::<Vec<AccountType>>();
+        let off_balance = types
+            .iter()
+            .filter(|t| **t == AccountType::OffBalance)
+            .count();
+        match off_balance > 0 && off_balance < types.len() {
+            true => Some(
+                Diagnostic::warning(
+                    Code::MixedOffBalance,
+                    tr!("Transaction mixes off-balance and balance sheet accounts"),
+                )
+                .with_span(transaction.span),
+            ),
+            false => None,
+        }
+    }
+    /// Account has no sub-accounts
+    pub fn is_leaf(&self, id: &str) -> bool {
+        self.children(id).next().is_none()
+    }
+    /// Number of ancestors, roots have depth 0
+    pub fn depth(&self, id: &str) -> usize {
+        self.ancestors(id).len()
+    }
+    pub fn children(&self, id: &str) -> impl Iterator<Item = &ChartAccount> {
+        let id = id.to_string();
+        self.accounts
+            .values()
+            .filter(move |a| a.parent.as_deref() == Some(id.as_str()))
+    }
+    /// Accounts in tree order with their depth, roots have depth 0
+    pub fn tree(&self) -> Vec<(&ChartAccount, usize)> {
+        let mut res = Vec::new();
+        for root in self.accounts.values().filter(|a| a.parent.is_none()) {
+            self.walk(root, 0, &mut res);
+        }
+        res
+    }
+    fn walk<'a>(
+        &'a self,
+        account: &'a ChartAccount,
+        depth: usize,
+        res: &mut Vec<(&'a ChartAccount, usize)>,
+    ) {
+        res.push((account, depth));
+        for child in self.children(&account.id) {
+            self.walk(child, depth + 1, res);
+        }
+    }
+    /// Roll up balances, parents get the sum of their own and
+    /// all of their descendants balances
+    pub fn rollup(
+        &self,
+        items: &HashMap<String, LedgerIndexItem>,
+    ) -> Result<HashMap<String, LedgerIndexItem>, Diagnostic> {
+        let mut res: HashMap<String, LedgerIndexItem> = self
+            .ids()
+            .map(|id| (id.clone(), LedgerIndexItem::default()))
+            .collect();
+        for (id, item) in items {
+            for account in std::iter::once(id.as_str()).chain(self.ancestors(id)) {
+                let sum = res.entry(account.to_string()).or_default();
+                *sum = sum.combine(item)?;
+            }
+        }
+        Ok(res)
+    }
+}
+
+// Parent part of a sub-account ID
+fn parent_id(id: &str) -> Option<&str> {
+    id.rsplit_once('/').map(|(parent, _)| parent)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chart(ids: &[&str]) -> Chart {
+        let mut chart = Chart::default();
+        for id in ids {
+            chart.add(&Account::new(id.to_string(), String::new()));
+        }
+        chart
+    }
+
+    #[test]
+    fn test_sub_accounts() {
+        let mut chart = chart(&["454", "454/agroker", "38/bank"]);
+        let errors = chart.link();
+        assert_eq!(
+            errors[0].message(),
+            "Parent account 38 of 38/bank is not declared"
+        );
+        assert_eq!(errors[0].code(), Code::UnknownAccount);
+        assert!(chart.resolve("454/agroker/2022"));
+        assert!(chart.resolve("454/other"));
+        assert!(!chart.resolve("455/other"));
+        assert!(!chart.get("454/other").unwrap().declared);
+        assert_eq!(chart.ancestors("454/agroker/2022"), ["454/agroker", "454"]);
+        let tree = chart
+            .tree()
+            .iter()
+            .map(|(a, depth)| (a.id.as_str(), *depth))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            tree,
+            [
+                ("38/bank", 0),
+                ("454", 0),
+                ("454/agroker", 1),
+                ("454/agroker/2022", 2),
+                ("454/other", 1)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_account_types() {
+        let mut chart = chart(&["0", "01", "3", "38"]);
+        let mut asset = Account::new("3".into(), String::new());
+        asset.account_type = Some(AccountType::Asset);
+        chart.add(&asset);
+        let mut off_balance = Account::new("0".into(), String::new());
+        off_balance.account_type = Some(AccountType::OffBalance);
+        chart.add(&off_balance);
+        chart.link();
+        assert!(chart.resolve("38/bank"));
+        assert_eq!(chart.account_type("38/bank"), Some(AccountType::Asset));
+        assert_eq!(chart.account_type("01"), Some(AccountType::OffBalance));
+
+        let money = crate::money::Money::new(1, 0);
+        assert!(chart
+            .check_transaction(&Transaction::simple("01", "0", money))
+            .is_none());
+        let warning = chart
+            .check_transaction(&Transaction::simple("01", "38", money))
+            .unwrap();
+        assert_eq!(warning.code(), Code::MixedOffBalance);
+        assert!(!warning.is_error());
+    }
+
+    #[test]
+    fn test_prefix_hierarchy() {
+        let mut chart = chart(&["3842", "3", "38", "3841", "161", "1", "T5"]);
+        let mut explicit = Account::new("5".into(), String::new());
+        explicit.parent = Some("T5".into());
+        chart.add(&explicit);
+        assert!(chart.link().is_empty());
+        assert_eq!(chart.ancestors("3841"), ["38", "3"]);
+        assert_eq!(chart.get("161").unwrap().parent.as_deref(), Some("1"));
+        assert_eq!(chart.get("5").unwrap().parent.as_deref(), Some("T5"));
+        assert_eq!(chart.depth("3842"), 2);
+        assert!(!chart.is_leaf("38"));
+        assert!(chart.is_leaf("3841"));
+
+        // Explicit parents can not form a cycle
+        let mut a = Account::new("A".into(), String::new());
+        a.parent = Some("B".into());
+        a.path = PathBuf::from("accounts/a.bit");
+        a.span = Span::new(3, 3, 1);
+        let mut b = Account::new("B".into(), String::new());
+        b.parent = Some("A".into());
+        let mut chart = Chart::default();
+        chart.add(&a);
+        chart.add(&b);
+        let errors = chart.link();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].message(), "Account A is its own ancestor");
+        assert_eq!(errors[0].code(), Code::AccountCycle);
+        assert_eq!(
+            errors[0].path(),
+            Some(PathBuf::from("accounts/a.bit").as_path())
+        );
+        assert_eq!(errors[0].span(), Some(Span::new(3, 3, 1)));
+        assert_eq!(chart.ancestors("B"), ["A"]);
+    }
+}
@@ -0,0 +1,410 @@
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+    path::{Path, PathBuf},
+};
+
+use crate::tr;
+
+/// Source location of a token, 1-based line and column
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+pub struct Span {
+    pub line: usize,
+    pub column: usize,
+    /// Length in characters
+    pub len: usize,
+}
+
+impl Span {
+    pub fn new(line: usize, column: usize, len: usize) -> Self {
+        Self { line, column, len }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+/// Machine readable diagnostic codes
+///
+/// Codes are stable, tools can rely on them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Code {
+    /// File cannot be read or found
+    Io,
+    /// Project or config error
+    Config,
+    UnterminatedString,
+    UnknownCommand,
+    ParameterCount,
+    InvalidNumber,
+    InvalidDate,
+    InvalidPaymentKind,
+    AccountOutsideAccountFile,
+    MissingId,
+    InvalidExpression,
+    UndefinedReference,
+    ArithmeticError,
+    UnclosedTitleBlock,
+    UnknownKey,
+    DuplicateKey,
+    MissingKey,
+    DuplicateId,
+    UnknownReference,
+    UnknownEvent,
+    UnterminatedStatement,
+    UnexpectedToken,
+    UnbalancedTransaction,
+    InvalidAccountType,
+    InvalidTag,
+    IncludeCycle,
+    InvalidTemplate,
+    UnknownTemplate,
+    FieldRedefined,
+    InvalidSignature,
+    TotalMismatch,
+    VatMismatch,
+    GrossMismatch,
+    ZeroAmount,
+    InvalidId,
+    UnknownAccount,
+    MissingCompletionDate,
+    DateOutOfRange,
+    AmountOverflow,
+    NonLeafAccount,
+    AccountCycle,
+    MixedOffBalance,
+    UnusualBalance,
+}
+
+// Codes are serialized as written in the rendered diagnostics
+impl Serialize for Code {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl Code {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Code::Io => "E0001",
+            Code::Config => "E0002",
+            Code::UnterminatedString => "E0010",
+            Code::UnknownCommand => "E0100",
+            Code::ParameterCount => "E0101",
+            Code::InvalidNumber => "E0102",
+            Code::InvalidDate => "E0103",
+            Code::InvalidPaymentKind => "E0104",
+            Code::AccountOutsideAccountFile => "E0105",
+            Code::MissingId => "E0106",
+            Code::InvalidExpression => "E0107",
+            Code::UndefinedReference => "E0108",
+            Code::ArithmeticError => "E0109",
+            Code::UnclosedTitleBlock => "E0110",
+            Code::UnknownKey => "E0111",
+            Code::DuplicateKey => "E0112",
+            Code::MissingKey => "E0113",
+            Code::DuplicateId => "E0114",
+            Code::UnknownReference => "E0115",
+            Code::UnknownEvent => "E0116",
+            Code::UnterminatedStatement => "E0117",
+            Code::UnexpectedToken => "E0118",
+            Code::UnbalancedTransaction => "E0119",
+            Code::InvalidAccountType => "E0120",
+            Code::InvalidTag => "E0121",
+            Code::IncludeCycle => "E0122",
+            Code::InvalidTemplate => "E0123",
+            Code::UnknownTemplate => "E0124",
+            Code::FieldRedefined => "E0125",
+            Code::InvalidSignature => "E0126",
+            Code::TotalMismatch => "E0127",
+            Code::VatMismatch => "E0128",
+            Code::GrossMismatch => "E0129",
+            Code::ZeroAmount => "E0130",
+            Code::InvalidId => "E0131",
+            Code::UnknownAccount => "E0200",
+            Code::MissingCompletionDate => "E0201",
+            Code::DateOutOfRange => "E0202",
+            Code::AmountOverflow => "E0203",
+            Code::NonLeafAccount => "E0204",
+            Code::AccountCycle => "E0205",
+            Code::MixedOffBalance => "E0206",
+            Code::UnusualBalance => "E0207",
+        }
+    }
+}
+
+impl Display for Code {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Diagnostic {
+    severity: Severity,
+    code: Code,
+    message: String,
+    path: Option<PathBuf>,
+    span: Option<Span>,
+    hints: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn new(severity: Severity, code: Code, message: impl Into<String>) -> Self {
+        Self {
+            severity,
+            code,
+            message: message.into(),
+            path: None,
+            span: None,
+            hints: Vec::new(),
+        }
+    }
+    pub fn error(code: Code, message: impl Into<String>) -> Self {
+        Self::new(Severity::Error, code, message)
+    }
+    pub fn warning(code: Code, message: impl Into<String>) -> Self {
+        Self::new(Severity::Warning, code, message)
+    }
+    pub fn with_path(mut self, path: &Path) -> Self {
+        self.path = Some(path.to_path_buf());
+        self
+    }
+    pub fn with_span(mut self, span: Span) -> Self {
+        self.span = Some(span);
+        self
+    }
+    pub fn with_hint(mut self, hint: impl Into<String>) -> Self {
+        self.hints.push(hint.into());
+        self
+    }
+    pub fn severity(&self) -> Severity {
+        self.severity
+    }
+    pub fn code(&self) -> Code {
+        self.code
+    }
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+    pub fn span(&self) -> Option<Span> {
+        self.span
+    }
+    pub fn hints(&self) -> &[String] {
+        &self.hints
+    }
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+    /// Render diagnostic with a source snippet and caret underline
+    ///
+    /// Source is the full content of the file the span points into.
+    pub fn render(&self, source: Option<&str>) -> String {
+        let mut res = format!("{}[{}]: {}", self.severity, self.code, self.message);
+        // Location line
+        match (&self.path, self.span) {
+            (Some(path), Some(span)) => res.push_str(&format!(
+                "\n  --> {}:{}:{}",
+                path.display(),
+                span.line,
+                span.column
+            )),
+            (Some(path), None) => res.push_str(&format!("\n  --> {}", path.display())),
+            (None, Some(span)) => res.push_str(&format!("\n  --> {}:{}", span.line, span.column)),
+            (None, None) => (),
+        }
+        // Source snippet
+        let snippet_line = self.span.and_then(|span| {
+            source
+                .and_then(|source| source.lines().nth(span.line.checked_sub(1)?))
+                .map(|line| (span, line))
+        });
+        let gutter = match self.span {
+            Some(span) => span.line.to_string().len(),
+            None => 1,
+        };
+        if let Some((span, line)) = snippet_line {
+            let pad = " ".repeat(gutter);
+            res.push_str(&format!("\n{} |", pad));
+            res.push_str(&format!("\n{} | {}", span.line, line));
+            res.push_str(&format!(
+                "\n{} | {}{}",
+                pad,
+                " ".repeat(span.column.saturating_sub(1)),
+                "^".repeat(span.len.max(1))
+            ));
+        }
+        for hint in &self.hints {
+            res.push_str(&format!("\n{} = hint: {}", " ".repeat(gutter), hint));
+        }
+        res
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Try to load source for the snippet
+        let source = match (&self.path, self.span) {
+            (Some(path), Some(_)) => std::fs::read_to_string(path).ok(),
+            _ => None,
+        };
+        write!(f, "{}", self.render(source.as_deref()))
+    }
+}
+
+impl std::error::Error for Diagnostic {}
+
+/// Collection of diagnostics
+///
+/// Used to report every problem in one pass instead of aborting
+/// on the first error.
+#[derive(Debug, Default, Clone)]
+pub struct Diagnostics {
+    items: Vec<Diagnostic>,
+}
+
+impl Diagnostics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn push(&mut self, diagnostic: Diagnostic) {
+        self.items.push(diagnostic);
+    }
+    pub fn extend(&mut self, diagnostics: Diagnostics) {
+        self.items.extend(diagnostics.items);
+    }
+    pub fn iter(&self) -> std::slice::Iter<'_, Diagnostic> {
+        self.items.iter()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+    pub fn error_count(&self) -> usize {
+        self.items.iter().filter(|d| d.is_error()).count()
+    }
+    pub fn warning_count(&self) -> usize {
+        self.items.len() - self.error_count()
+    }
+    pub fn has_errors(&self) -> bool {
+        self.items.iter().any(|d| d.is_error())
+    }
+    /// Summary with total counts and counts per file
+    pub fn summary(&self) -> String {
+        let mut per_file: BTreeMap<String, (usize, usize)> = BTreeMap::new();
+        for d in &self.items {
+            let file = match d.path() {
+                Some(path) => path.display().to_string(),
+                None => "<project>".to_string(),
+            };
+            let counter = per_file.entry(file).or_default();
+            match d.is_error() {
+                true => counter.0 += 1,
+                false => counter.1 += 1,
+            }
+        }
+        let mut res = tr!(
+            "{} errors, {} warnings",
+            self.error_count(),
+            self.warning_count()
+        );
+        for (file, (errors, warnings)) in per_file {
+            res.push_str(&format!(
+                "\n  {}: {}",
+                file,
+                tr!("{} errors, {} warnings", errors, warnings)
+            ));
+        }
+        res
+    }
+}
+
+impl From<Diagnostic> for Diagnostics {
+    fn from(diagnostic: Diagnostic) -> Self {
+        Self {
+            items: vec![diagnostic],
+        }
+    }
+}
+
+impl IntoIterator for Diagnostics {
+    type Item = Diagnostic;
+    type IntoIter = std::vec::IntoIter<Diagnostic>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.items.into_iter()
+    }
+}
+
+impl Display for Diagnostics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for d in &self.items {
+            writeln!(f, "{}\n", d)?;
+        }
+        write!(f, "{}", self.summary())
+    }
+}
+
+impl std::error::Error for Diagnostics {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_snippet() {
+        let d = Diagnostic::error(Code::InvalidNumber, "NET must be integer number")
+            .with_path(Path::new("notes/a.bit"))
+            .with_span(Span::new(2, 5, 3))
+            .with_hint("Use digits and _ only");
+        let res = d.render(Some("ID 1\nNET 1x2\n"));
+        assert_eq!(
+            res,
+            "error[E0102]: NET must be integer number
+  --> notes/a.bit:2:5
+  |
+2 | NET 1x2
+  |     ^^^
+  = hint: Use digits and _ only"
+        );
+    }
+
+    #[test]
+    fn test_summary() {
+        let mut diagnostics = Diagnostics::new();
+        diagnostics
+            .push(Diagnostic::error(Code::MissingId, "MISSING ID").with_path(Path::new("a.bit")));
+        diagnostics.push(Diagnostic::warning(Code::Config, "x").with_path(Path::new("a.bit")));
+        diagnostics.push(Diagnostic::error(Code::Io, "y").with_path(Path::new("b.bit")));
+        assert!(diagnostics.has_errors());
+        assert_eq!(
+            diagnostics.summary(),
+            "2 errors, 1 warnings\n  a.bit: 1 errors, 1 warnings\n  b.bit: 1 errors, 0 warnings"
+        );
+    }
+
+    #[test]
+    fn test_render_without_source() {
+        let d = Diagnostic::warning(Code::Config, "No config file found!");
+        assert_eq!(d.render(None), "warning[E0002]: No config file found!");
+    }
+}
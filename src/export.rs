@@ -0,0 +1,128 @@
+use std::collections::BTreeMap;
+
+use chrono::NaiveDate;
+use serde::Serialize;
+
+use crate::{
+    diagnostic::{Code, Diagnostic},
+    ledger::{Account, JournalEntry, LedgerIndexItem},
+    note::Note,
+    project::Project,
+    tr,
+};
+
+/// Version of the exported data, raised on every breaking change
+pub const EXPORT_VERSION: u32 = 1;
+
+/// Exported data with its format version
+///
+/// Amounts are decimal text, dates are ISO 8601, tags are written as in
+/// notes. Field names follow the model, e.g. a note has its transactions,
+/// legs and revision history.
+#[derive(Debug, Serialize)]
+pub struct Versioned<T: Serialize> {
+    pub version: u32,
+    pub data: T,
+}
+
+impl<T: Serialize> Versioned<T> {
+    pub fn new(data: T) -> Self {
+        Self {
+            version: EXPORT_VERSION,
+            data,
+        }
+    }
+    pub fn to_json(&self) -> Result<String, Diagnostic> {
+        serde_json::to_string_pretty(self)
+            .map_err(|e| Diagnostic::error(Code::Io, tr!("Cannot serialize to JSON: {}", e)))
+    }
+    pub fn to_cbor(&self) -> Result<Vec<u8>, Diagnostic> {
+        serde_cbor::to_vec(self)
+            .map_err(|e| Diagnostic::error(Code::Io, tr!("Cannot serialize to CBOR: {}", e)))
+    }
+}
+
+/// Project snapshot, accounts, notes, journal and balances at a date
+#[derive(Debug, Serialize)]
+pub struct ProjectExport<'a> {
+    pub name: &'a str,
+    pub currency: &'a str,
+    pub date: NaiveDate,
+    pub accounts: &'a [Account],
+    /// Notes by ID
+    pub notes: BTreeMap<&'a str, &'a Note>,
+    pub journal: &'a [JournalEntry],
+    /// Balances of every account at the date, parents include
+    /// their sub-accounts
+    pub balances: BTreeMap<String, LedgerIndexItem>,
+}
+
+impl<'a> ProjectExport<'a> {
+    pub fn new(
+        project: &'a Project,
+        date: NaiveDate,
+        day_index: usize,
+    ) -> Result<Self, Diagnostic> {
+        let ledger = &project.ledger;
+        let items = ledger
+            .get_ledger_by_date(day_index.min(ledger.last_day()))
+            .unwrap_or_default();
+        Ok(Self {
+            name: &project.settings().name,
+            currency: &project.settings().currency,
+            date,
+            accounts: &project.accounts,
+            notes: project
+                .notes()
+                .iter()
+                .map(|(id, note)| (id.as_str(), note))
+                .collect(),
+            journal: ledger.journal(),
+            balances: project.chart().rollup(&items)?.into_iter().collect(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::note::NoteContext;
+
+    #[test]
+    fn test_note_json() {
+        let raw = crate::parser::parse(
+            "ID 1\nNET 1_000\nCOMPLETION_DATE 2022-04-01\n> 5 454/agroker NET #fuel\nDOCID 1\nDOCID 2\n",
+        );
+        let ctx = NoteContext {
+            money_format: crate::money::MoneyFormat::for_currency("EUR"),
+            ..Default::default()
+        };
+        let note = Note::from_raw_note(raw, false, &ctx).unwrap();
+        let json = Versioned::new(&note).to_json().unwrap();
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value["version"], EXPORT_VERSION);
+        let data = &value["data"];
+        assert_eq!(data["net"], "1000.00");
+        assert_eq!(data["completion_date"], "2022-04-01");
+        let transaction = &data["transactions"][0];
+        assert_eq!(transaction["tags"][0], "#fuel");
+        assert_eq!(transaction["legs"][1]["side"], "credit");
+        assert_eq!(transaction["legs"][1]["account"], "454/agroker");
+        assert_eq!(transaction["legs"][1]["amount"], "1000.00");
+        let change = &data["history"]["revisions"][0]["changes"][0];
+        assert_eq!(change["command"], "ID");
+        assert_eq!(change["kind"], "set");
+        assert_eq!(data["warnings"][0]["code"], Code::FieldRedefined.as_str());
+
+        let raw = crate::parser::parse("ID \"a b\";\n");
+        let json = serde_json::to_value(&raw).unwrap();
+        let tokens = &json["lines"][0]["tokens"];
+        assert_eq!(tokens[0]["token_kind"]["value"], "ID");
+        assert_eq!(tokens[1]["token_kind"]["value"], "a b");
+        assert_eq!(tokens[2]["token_kind"]["kind"], "semicolon");
+
+        let cbor = Versioned::new(&note).to_cbor().unwrap();
+        let value: serde_cbor::Value = serde_cbor::from_slice(&cbor).unwrap();
+        assert!(matches!(value, serde_cbor::Value::Map(_)));
+    }
+}
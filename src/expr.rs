@@ -0,0 +1,381 @@
+use std::fmt::Display;
+
+use crate::{
+    diagnostic::{Code, Diagnostic, Span},
+    money::{Money, MoneyFormat},
+    parser::Token,
+    tr,
+};
+
+/// Note fields an expression can refer to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    Net,
+    Vat,
+    Gross,
+}
+
+impl Field {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "NET" => Some(Self::Net),
+            "VAT" => Some(Self::Vat),
+            "GROSS" => Some(Self::Gross),
+            _ => None,
+        }
+    }
+}
+
+impl Display for Field {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Field::Net => write!(f, "NET"),
+            Field::Vat => write!(f, "VAT"),
+            Field::Gross => write!(f, "GROSS"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+/// Amount expression, e.g. GROSS - NET or (15_000 * 27 / 127)
+#[derive(Debug, Clone)]
+pub enum Expr {
+    Number(Money, Span),
+    Field(Field, Span),
+    Neg(Box<Expr>, Span),
+    Binary(Op, Box<Expr>, Box<Expr>, Span),
+}
+
+impl Expr {
+    /// Parse expression from parameter tokens
+    pub fn parse(tokens: &[Token], format: &MoneyFormat) -> Result<Self, Diagnostic> {
+        let lexemes = lex(tokens, format)?;
+        let mut parser = ExprParser {
+            lexemes,
+            pos: 0,
+            format,
+        };
+        let expr = parser.expr()?;
+        match parser.peek() {
+            Some(lexeme) => Err(expr_error(
+                lexeme.span,
+                tr!("Unexpected token in expression"),
+            )),
+            None => Ok(expr),
+        }
+    }
+    pub fn span(&self) -> Span {
+        match self {
+            Expr::Number(_, span) => *span,
+            Expr::Field(_, span) => *span,
+            Expr::Neg(_, span) => *span,
+            Expr::Binary(_, _, _, span) => *span,
+        }
+    }
+    /// Plain number without any operation
+    pub fn is_literal(&self) -> bool {
+        matches!(self, Expr::Number(..))
+    }
+    /// Evaluate expression with checked money arithmetic
+    ///
+    /// Resolve returns the value of a referenced note field.
+    pub fn eval(&self, resolve: &dyn Fn(Field) -> Option<Money>) -> Result<Money, Diagnostic> {
+        match self {
+            Expr::Number(value, _) => Ok(*value),
+            Expr::Field(field, span) => resolve(*field).ok_or_else(|| {
+                Diagnostic::error(
+                    Code::UndefinedReference,
+                    tr!("{} is not defined in this note", field),
+                )
+                .with_span(*span)
+            }),
+            Expr::Neg(expr, span) => expr
+                .eval(resolve)?
+                .checked_neg()
+                .ok_or_else(|| arithmetic_error(*span, tr!("Amount overflow"))),
+            Expr::Binary(op, lhs, rhs, span) => {
+                let lhs = lhs.eval(resolve)?;
+                let rhs = rhs.eval(resolve)?;
+                let res = match op {
+                    Op::Add => lhs.checked_add(&rhs),
+                    Op::Sub => lhs.checked_sub(&rhs),
+                    Op::Mul => lhs.checked_mul(&rhs),
+                    Op::Div if rhs.is_zero() => {
+                        return Err(arithmetic_error(*span, tr!("Division by zero")))
+                    }
+                    Op::Div => lhs.checked_div(&rhs),
+                };
+                res.ok_or_else(|| arithmetic_error(*span, tr!("Amount overflow")))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum LexemeKind {
+    Number(String),
+    Ident(String),
+    Op(Op),
+    Open,
+    Close,
+}
+
+#[derive(Debug)]
+struct Lexeme {
+    kind: LexemeKind,
+    span: Span,
+}
+
+// Split tokens into expression lexemes, tokens can contain more
+// lexemes e.g. (15_000*27)
+fn lex(tokens: &[Token], format: &MoneyFormat) -> Result<Vec<Lexeme>, Diagnostic> {
+    let is_number_char = |ch: char| {
+        ch.is_ascii_digit()
+            || ch == '_'
+            || ch == format.decimal_separator
+            || Some(ch) == format.thousand_separator
+    };
+    let mut res = Vec::new();
+    for token in tokens {
+        let text = match token.token_kind_ref().take_text_string_ref() {
+            Some(text) if !token.is_quoted() => text,
+            _ => return Err(expr_error(token.span(), tr!("Invalid token in expression"))),
+        };
+        let chars = text.chars().collect::<Vec<char>>();
+        let mut i = 0;
+        while i < chars.len() {
+            let start = i;
+            let kind = match chars[i] {
+                '+' => LexemeKind::Op(Op::Add),
+                '-' => LexemeKind::Op(Op::Sub),
+                '*' => LexemeKind::Op(Op::Mul),
+                '/' => LexemeKind::Op(Op::Div),
+                '(' => LexemeKind::Open,
+                ')' => LexemeKind::Close,
+                x if x.is_ascii_digit() => {
+                    while i + 1 < chars.len() && is_number_char(chars[i + 1]) {
+                        i += 1;
+                    }
+                    LexemeKind::Number(chars[start..=i].iter().collect())
+                }
+                x if x.is_alphabetic() => {
+                    while i + 1 < chars.len()
+                        && (chars[i + 1].is_alphabetic() || chars[i + 1] == '_')
+                    {
+                        i += 1;
+                    }
+                    LexemeKind::Ident(chars[start..=i].iter().collect())
+                }
+                _ => {
+                    let span = token.span();
+                    return Err(expr_error(
+                        Span::new(span.line, span.column + start, 1),
+                        tr!("Invalid character in expression"),
+                    ));
+                }
+            };
+            i += 1;
+            let span = token.span();
+            res.push(Lexeme {
+                kind,
+                span: Span::new(span.line, span.column + start, i - start),
+            });
+        }
+    }
+    Ok(res)
+}
+
+struct ExprParser<'a> {
+    lexemes: Vec<Lexeme>,
+    pos: usize,
+    format: &'a MoneyFormat,
+}
+
+impl<'a> ExprParser<'a> {
+    fn peek(&self) -> Option<&Lexeme> {
+        self.lexemes.get(self.pos)
+    }
+    // Span of the last lexeme, used when expression ends too early
+    fn end_span(&self) -> Span {
+        match self.lexemes.last() {
+            Some(lexeme) => Span::new(lexeme.span.line, lexeme.span.column + lexeme.span.len, 1),
+            None => Span::default(),
+        }
+    }
+    // expr = term (("+" | "-") term)*
+    fn expr(&mut self) -> Result<Expr, Diagnostic> {
+        let mut lhs = self.term()?;
+        while let Some(LexemeKind::Op(op @ (Op::Add | Op::Sub))) =
+            self.peek().map(|l| l.kind.clone())
+        {
+            self.pos += 1;
+            let rhs = self.term()?;
+            let span = join(lhs.span(), rhs.span());
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs), span);
+        }
+        Ok(lhs)
+    }
+    // term = unary (("*" | "/") unary)*
+    fn term(&mut self) -> Result<Expr, Diagnostic> {
+        let mut lhs = self.unary()?;
+        while let Some(LexemeKind::Op(op @ (Op::Mul | Op::Div))) =
+            self.peek().map(|l| l.kind.clone())
+        {
+            self.pos += 1;
+            let rhs = self.unary()?;
+            let span = join(lhs.span(), rhs.span());
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs), span);
+        }
+        Ok(lhs)
+    }
+    // unary = "-" unary | primary
+    fn unary(&mut self) -> Result<Expr, Diagnostic> {
+        match self.peek() {
+            Some(Lexeme {
+                kind: LexemeKind::Op(Op::Sub),
+                span,
+            }) => {
+                let start = *span;
+                self.pos += 1;
+                let expr = self.unary()?;
+                let span = join(start, expr.span());
+                Ok(Expr::Neg(Box::new(expr), span))
+            }
+            _ => self.primary(),
+        }
+    }
+    // primary = number | field | "(" expr ")"
+    fn primary(&mut self) -> Result<Expr, Diagnostic> {
+        let lexeme = match self.lexemes.get(self.pos) {
+            Some(lexeme) => lexeme,
+            None => {
+                return Err(expr_error(
+                    self.end_span(),
+                    tr!("Expression ends unexpectedly"),
+                ))
+            }
+        };
+        let span = lexeme.span;
+        self.pos += 1;
+        match &lexeme.kind {
+            LexemeKind::Number(text) => Money::parse(text, self.format)
+                .map(|value| Expr::Number(value, span))
+                .map_err(|e| {
+                    Diagnostic::error(Code::InvalidNumber, tr!("Invalid amount in expression"))
+                        .with_span(span)
+                        .with_hint(e)
+                }),
+            LexemeKind::Ident(name) => match Field::parse(name) {
+                Some(field) => Ok(Expr::Field(field, span)),
+                None => Err(Diagnostic::error(
+                    Code::UndefinedReference,
+                    tr!("Unknown reference {}", name),
+                )
+                .with_span(span)
+                .with_hint(tr!("Only NET, VAT and GROSS can be used in expressions"))),
+            },
+            LexemeKind::Open => {
+                let expr = self.expr()?;
+                match self.peek() {
+                    Some(Lexeme {
+                        kind: LexemeKind::Close,
+                        span: close,
+                    }) => {
+                        let span = join(span, *close);
+                        self.pos += 1;
+                        // Keep inner expression, but extend its span
+                        Ok(match expr {
+                            Expr::Number(value, _) => Expr::Number(value, span),
+                            Expr::Field(field, _) => Expr::Field(field, span),
+                            Expr::Neg(expr, _) => Expr::Neg(expr, span),
+                            Expr::Binary(op, lhs, rhs, _) => Expr::Binary(op, lhs, rhs, span),
+                        })
+                    }
+                    _ => Err(expr_error(span, tr!("Unclosed parenthesis"))),
+                }
+            }
+            LexemeKind::Close | LexemeKind::Op(_) => Err(expr_error(
+                span,
+                tr!("Expected number, field or parenthesis"),
+            )),
+        }
+    }
+}
+
+// Span covering a and b, on the same line. Expanded templates mix call
+// and argument spans, so b may start before a.
+fn join(a: Span, b: Span) -> Span {
+    match a.line == b.line {
+        true => {
+            let column = a.column.min(b.column);
+            let end = (a.column + a.len).max(b.column + b.len);
+            Span::new(a.line, column, end - column)
+        }
+        false => a,
+    }
+}
+
+fn expr_error(span: Span, message: String) -> Diagnostic {
+    Diagnostic::error(Code::InvalidExpression, message).with_span(span)
+}
+
+fn arithmetic_error(span: Span, message: String) -> Diagnostic {
+    Diagnostic::error(Code::ArithmeticError, message).with_span(span)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Parse expression as NET parameters
+    fn parse(source: &str) -> Result<Expr, Diagnostic> {
+        let raw = crate::parser::parse(&format!("NET {}", source));
+        let tokens = raw.lines_ref()[0].tokens_ref();
+        Expr::parse(&tokens[1..], &MoneyFormat::default())
+    }
+
+    fn resolve(field: Field) -> Option<Money> {
+        match field {
+            Field::Net => Some(Money::new(11811, 0)),
+            Field::Gross => Some(Money::new(15000, 0)),
+            Field::Vat => None,
+        }
+    }
+
+    #[test]
+    fn test_eval() {
+        let eval = |source: &str| parse(source).unwrap().eval(&resolve).unwrap();
+        assert_eq!(eval("GROSS - NET"), Money::new(3189, 0));
+        assert_eq!(eval("(15_000 * 27 / 127)"), Money::new(3189, 0));
+        assert_eq!(eval("2 + 3*4"), Money::new(14, 0));
+        assert_eq!(eval("-(2 + 3) * 4"), Money::new(-20, 0));
+        assert!(parse("12").unwrap().is_literal());
+    }
+
+    #[test]
+    fn test_errors() {
+        let err = parse("1 + ").unwrap_err();
+        assert_eq!(err.code(), Code::InvalidExpression);
+        let err = parse("(1 + 2").unwrap_err();
+        assert_eq!(err.span(), Some(Span::new(1, 5, 1)));
+        let err = parse("1 + FOO").unwrap_err();
+        assert_eq!(err.code(), Code::UndefinedReference);
+        assert_eq!(err.span(), Some(Span::new(1, 9, 3)));
+        let err = parse("NET + VAT").unwrap().eval(&resolve).unwrap_err();
+        assert_eq!(err.code(), Code::UndefinedReference);
+        assert_eq!(err.span(), Some(Span::new(1, 11, 3)));
+        let err = parse("NET / (GROSS-15000)")
+            .unwrap()
+            .eval(&resolve)
+            .unwrap_err();
+        assert_eq!(err.code(), Code::ArithmeticError);
+        assert_eq!(err.span(), Some(Span::new(1, 5, 19)));
+    }
+}
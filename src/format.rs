@@ -0,0 +1,414 @@
+use crate::{
+    money::{Money, MoneyFormat},
+    note::{is_key, Statement, TRANSACTION_TAIL_KEYS},
+    parser::{Command, CommentKind, Line, Token, TokenKind},
+};
+
+/// Value column of single line fields, as in docs/example.bit
+const KEY_WIDTH: usize = 18;
+
+/// Format the unsigned part of a note or account file
+///
+/// Lines till the last signature are kept as they are, so signatures
+/// stay valid. After it fields are aligned to a key column, transaction
+/// and account lines and the lines of multi-line statements are aligned
+/// as tables, and amounts get _ thousand separators. Free text, title
+/// and comments are kept, only their trailing whitespace is removed.
+pub fn format_note(text: &str, format: &MoneyFormat) -> String {
+    let raw = crate::parser::parse(text);
+    let lines = raw.lines_ref();
+    let start = lines
+        .iter()
+        .rposition(Line::is_signature)
+        .map_or(0, |i| i + 1);
+    if start == lines.len() {
+        return text.to_string();
+    }
+    let mut formatter = Formatter {
+        format,
+        out: lines[..start]
+            .iter()
+            .map(|line| line.raw().to_string())
+            .collect(),
+        table: Vec::new(),
+        statement: None,
+        key: String::new(),
+        values: 0,
+        in_title: false,
+    };
+    for line in &lines[start..] {
+        formatter.line(line);
+    }
+    formatter.flush();
+    let mut out = formatter.out;
+    while out.len() > start && out.last().is_some_and(|line| line.is_empty()) {
+        out.pop();
+    }
+    out.join("\n") + "\n"
+}
+
+struct Formatter<'a> {
+    format: &'a MoneyFormat,
+    out: Vec<String>,
+    /// Consecutive lines aligned together
+    table: Vec<Row>,
+    /// Command and keys of the open multi-line statement
+    statement: Option<(Command, &'static [&'static str])>,
+    /// Last key of the open statement and the number of its values
+    key: String,
+    values: usize,
+    in_title: bool,
+}
+
+// Line of a table, every cell but the last is padded to its column
+struct Row {
+    indent: &'static str,
+    /// Rows with the same group are aligned together
+    group: String,
+    cells: Vec<String>,
+    comment: Option<String>,
+}
+
+impl<'a> Formatter<'a> {
+    fn line(&mut self, line: &Line) {
+        let tokens = line.tokens_ref();
+        let first = tokens.first().map(|t| t.token_kind_ref());
+        // Title block is kept as it is
+        if self.in_title || matches!(first, Some(TokenKind::Command(Command::TitleBlock))) {
+            if matches!(first, Some(TokenKind::Command(Command::TitleBlock))) {
+                self.in_title = !self.in_title;
+            }
+            return self.push(line.raw().trim_end().to_string());
+        }
+        // Free text, comment lines and lines with tokenizer error
+        if tokens.is_empty() || line.error().is_some() {
+            return self.push(line.raw().trim_end().to_string());
+        }
+        if let Some((command, keys)) = self.statement {
+            let params = line.param_tokens();
+            if is_key(&params[0], keys) || params[0].is_semicolon() {
+                let cells = self.named(command, &params);
+                let row = table_row("  ", String::new(), cells, 2, line);
+                self.table.push(row);
+                if params.iter().any(Token::is_semicolon) {
+                    self.statement = None;
+                }
+                return;
+            }
+            self.statement = None;
+        }
+        let command = match first {
+            Some(TokenKind::Command(command)) => *command,
+            _ => return self.push(line.raw().trim_end().to_string()),
+        };
+        let keyword = keyword(line);
+        let params = &tokens[1..];
+        let statement = Statement::new(tokens[0].clone(), params.to_vec());
+        if statement.is_named() {
+            self.key.clear();
+            let cells = match command {
+                Command::Template => params.iter().map(|t| self.token(t, false)).collect(),
+                _ => self.named(command, params),
+            };
+            if !statement.is_terminated() {
+                self.statement = Some((command, statement.keys));
+            }
+            return self.single(keyword, 0, cells, line);
+        }
+        match command {
+            Command::Transaction => {
+                let end = params
+                    .iter()
+                    .position(|t| is_key(t, TRANSACTION_TAIL_KEYS))
+                    .unwrap_or(params.len());
+                let cells = params
+                    .iter()
+                    .enumerate()
+                    .map(|(i, t)| self.token(t, (2..end).contains(&i)))
+                    .collect::<Vec<String>>();
+                let row = table_row(
+                    "",
+                    keyword.clone(),
+                    [vec![keyword], cells].concat(),
+                    4,
+                    line,
+                );
+                self.table.push(row);
+            }
+            Command::Account => {
+                let cells = params.iter().map(|t| self.token(t, false)).collect();
+                let row = table_row(
+                    "",
+                    keyword.clone(),
+                    [vec![keyword], cells].concat(),
+                    2,
+                    line,
+                );
+                self.table.push(row);
+            }
+            _ => {
+                let amounts = matches!(command, Command::Net | Command::Gross | Command::Vat);
+                let cells = params.iter().map(|t| self.token(t, amounts)).collect();
+                let width = match is_field(command) {
+                    true => KEY_WIDTH,
+                    false => 0,
+                };
+                self.single(keyword, width, cells, line);
+            }
+        }
+    }
+    // Parameters of a named statement, amounts follow AMOUNT and the
+    // account of DEBIT and CREDIT in transactions
+    fn named(&mut self, command: Command, params: &[Token]) -> Vec<String> {
+        let keys = match command {
+            Command::Transaction | Command::Template => &["DEBIT", "CREDIT", "AMOUNT"][..],
+            _ => &[],
+        };
+        params
+            .iter()
+            .map(|token| {
+                if is_key(token, keys) {
+                    self.key = text(token).to_string();
+                    self.values = 0;
+                } else if !token.is_semicolon() {
+                    self.values += 1;
+                }
+                let amount = match self.key.as_str() {
+                    "AMOUNT" => true,
+                    "DEBIT" | "CREDIT" => self.values > 1,
+                    _ => false,
+                };
+                self.token(token, amount && !is_key(token, keys))
+            })
+            .collect()
+    }
+    fn token(&self, token: &Token, amount: bool) -> String {
+        match token.token_kind_ref() {
+            TokenKind::Semicolon => ";".to_string(),
+            TokenKind::Command(command) => command.keyword().to_string(),
+            TokenKind::Text(text) if token.is_quoted() => {
+                format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+            }
+            TokenKind::Text(text) if amount => {
+                normalize_amount(text, self.format).unwrap_or_else(|| text.clone())
+            }
+            TokenKind::Text(text) => text.clone(),
+        }
+    }
+    // Keyword padded to width and its parameters
+    fn single(&mut self, keyword: String, width: usize, cells: Vec<String>, line: &Line) {
+        let mut res = match cells.is_empty() {
+            true => keyword,
+            false => format!("{: <width$}{}", keyword + " ", join(cells), width = width),
+        };
+        res.push_str(&line_comment(line));
+        self.push(res);
+    }
+    fn push(&mut self, line: String) {
+        self.flush();
+        self.out.push(line);
+    }
+    // Print the open table
+    fn flush(&mut self) {
+        let rows = std::mem::take(&mut self.table);
+        let mut start = 0;
+        while start < rows.len() {
+            let group = &rows[start];
+            let end = rows[start..]
+                .iter()
+                .position(|row| row.indent != group.indent || row.group != group.group)
+                .map_or(rows.len(), |i| start + i);
+            let mut widths: Vec<usize> = Vec::new();
+            for row in &rows[start..end] {
+                for (i, cell) in row.cells.iter().enumerate().take(row.cells.len() - 1) {
+                    match widths.get_mut(i) {
+                        Some(width) => *width = (*width).max(cell.chars().count()),
+                        None => widths.push(cell.chars().count()),
+                    }
+                }
+            }
+            for row in &rows[start..end] {
+                let last = row.cells.len() - 1;
+                let cells = row
+                    .cells
+                    .iter()
+                    .enumerate()
+                    .map(|(i, cell)| match i < last {
+                        true => format!("{: <width$}", cell, width = widths[i]),
+                        false => cell.clone(),
+                    })
+                    .collect::<Vec<String>>();
+                let mut res = format!("{}{}", row.indent, cells.join(" "));
+                if let Some(comment) = &row.comment {
+                    res.push_str(comment);
+                }
+                self.out.push(res.trim_end().to_string());
+            }
+            start = end;
+        }
+    }
+}
+
+// Cells after the first aligned ones are joined into the last cell
+fn table_row(
+    indent: &'static str,
+    group: String,
+    cells: Vec<String>,
+    aligned: usize,
+    line: &Line,
+) -> Row {
+    let mut cells = cells;
+    // Statement terminator belongs to the previous parameter
+    if cells.len() > 1 && cells.last().is_some_and(|cell| cell == ";") {
+        let semicolon = cells.pop().unwrap();
+        cells.last_mut().unwrap().push_str(&semicolon);
+    }
+    if cells.len() > aligned {
+        let rest = join(cells.split_off(aligned));
+        cells.push(rest);
+    }
+    let comment = line_comment(line);
+    Row {
+        indent,
+        group,
+        cells,
+        comment: (!comment.is_empty()).then_some(comment),
+    }
+}
+
+// Parameters separated by space, ; is attached to the previous one
+fn join(cells: Vec<String>) -> String {
+    let mut res = String::new();
+    for cell in cells {
+        if !res.is_empty() && cell != ";" {
+            res.push(' ');
+        }
+        res.push_str(&cell);
+    }
+    res
+}
+
+// Keyword of the line as written, e.g. > or TRANSACTION
+fn keyword(line: &Line) -> String {
+    let span = line.tokens_ref()[0].span();
+    line.raw()
+        .chars()
+        .skip(span.column - 1)
+        .take(span.len)
+        .collect()
+}
+
+fn line_comment(line: &Line) -> String {
+    match line.comment() {
+        Some(comment) if comment.kind() == CommentKind::Line => {
+            format!(" //{}", comment.text().trim_end())
+        }
+        _ => String::new(),
+    }
+}
+
+fn text(token: &Token) -> &str {
+    match token.token_kind_ref() {
+        TokenKind::Text(text) => text,
+        _ => "",
+    }
+}
+
+// Single value fields aligned to the key column
+fn is_field(command: Command) -> bool {
+    matches!(
+        command,
+        Command::Alias
+            | Command::Id
+            | Command::Docid
+            | Command::Author
+            | Command::PaymentKind
+            | Command::Net
+            | Command::Gross
+            | Command::Vat
+            | Command::IssueDate
+            | Command::CompletionDate
+            | Command::DueDate
+            | Command::Title
+            | Command::Date
+            | Command::Source
+            | Command::Type
+    )
+}
+
+// Amount with _ thousand separators, None if text is not an amount
+fn normalize_amount(text: &str, format: &MoneyFormat) -> Option<String> {
+    Money::parse(text, format).ok()?;
+    let (sign, text) = match text.strip_prefix('-') {
+        Some(rest) => ("-", rest),
+        None => ("", text.strip_prefix('+').unwrap_or(text)),
+    };
+    let (integer, fraction) = match text.split_once(format.decimal_separator) {
+        Some((integer, fraction)) => (integer, Some(fraction)),
+        None => (text, None),
+    };
+    let digits = integer
+        .chars()
+        .filter(char::is_ascii_digit)
+        .collect::<String>();
+    let mut res = match digits.is_empty() {
+        true => format!("{}0", sign),
+        false => format!(
+            "{}{}",
+            sign,
+            Money::new(digits.parse().ok()?, 0).to_string_with(Some('_'), '.')
+        ),
+    };
+    if let Some(fraction) = fraction {
+        res.push(format.decimal_separator);
+        res.extend(fraction.chars().filter(char::is_ascii_digit));
+    }
+    Some(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::note::{Note, NoteContext};
+
+    #[test]
+    fn test_format_note() {
+        let text = "---\nTelefon  vásárlása \n---\n\nID  1 // első\nNET 11811\nPAYMENT_KIND \"transfer\"\n\nNettó  szállítóra   \n> 5 454/agroker 11811  #fuel\n> 466   454/agroker 3189 CDATE 2022-01-10\n# block  comment\nTRANSACTION\n  DEBIT 5 1000\n  CREDIT 454 1000;\n";
+        let expected = "---\nTelefon  vásárlása\n---\n\nID                1 // első\nNET               11_811\nPAYMENT_KIND      \"transfer\"\n\nNettó  szállítóra\n> 5   454/agroker 11_811 #fuel\n> 466 454/agroker 3_189  CDATE 2022-01-10\n# block  comment\nTRANSACTION\n  DEBIT  5   1_000\n  CREDIT 454 1_000;\n";
+        let format = MoneyFormat::default();
+        let formatted = format_note(text, &format);
+        assert_eq!(formatted, expected);
+        // Formatting is stable and keeps the meaning of the note
+        assert_eq!(format_note(&formatted, &format), formatted);
+        let ctx = NoteContext::default();
+        let a = Note::from_raw_note(crate::parser::parse(text), false, &ctx).unwrap();
+        let b = Note::from_raw_note(crate::parser::parse(&formatted), false, &ctx).unwrap();
+        assert_eq!(a.title, b.title);
+        assert_eq!(a.id, b.id);
+        assert_eq!(a.transactions.len(), b.transactions.len());
+        assert_eq!(
+            a.transactions[1].legs[0].amount,
+            b.transactions[1].legs[0].amount
+        );
+    }
+
+    #[test]
+    fn test_format_signed() {
+        let format = MoneyFormat {
+            precision: 2,
+            decimal_separator: ',',
+            thousand_separator: Some('.'),
+        };
+        let signed = "ID 1\nNET 1000\n! abc\n";
+        assert_eq!(format_note(signed, &format), signed);
+        assert_eq!(
+            format_note("ID 1\n! abc\nNET  1.000,5\n\n", &format),
+            "ID 1\n! abc\nNET               1_000,5\n"
+        );
+        assert_eq!(
+            normalize_amount("-1234567,25", &format).unwrap(),
+            "-1_234_567,25"
+        );
+        assert_eq!(normalize_amount("12a", &format), None);
+    }
+}
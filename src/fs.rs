@@ -2,6 +2,11 @@ use std::path::{Path, PathBuf};
 
 use walkdir::WalkDir;
 
+use crate::{
+    diagnostic::{Code, Diagnostic},
+    tr,
+};
+
 #[derive(Default, Debug)]
 pub struct Doc {
     name: String,
@@ -24,16 +29,20 @@ impl Doc {
 pub fn get_files_recours(
     root_path: &Path,
     extension: Option<&'static str>,
-) -> Result<Vec<Doc>, String> {
+) -> Result<Vec<Doc>, Diagnostic> {
     if !root_path.exists() {
-        return Err(format!("Path {:?} not exist", &root_path));
+        return Err(Diagnostic::error(Code::Io, tr!("Path not exist")).with_path(root_path));
     }
     if !root_path.is_dir() {
-        return Err(format!("Path {:?} is not a directory", &root_path));
+        return Err(
+            Diagnostic::error(Code::Io, tr!("Path is not a directory")).with_path(root_path)
+        );
     }
     let mut res: Vec<Doc> = Vec::new();
+    // Files in name order, e.g. the first of two notes with the same ID
     for entry in WalkDir::new(root_path)
         .follow_links(true)
+        .sort_by_file_name()
         .into_iter()
         .filter_map(|e| e.ok())
     {
@@ -41,7 +50,7 @@ pub fn get_files_recours(
 
         if entry.metadata().unwrap().is_file() {
             if let Some(ext) = extension {
-                if entry.path().extension().unwrap() != ext {
+                if entry.path().extension().and_then(|e| e.to_str()) != Some(ext) {
                     continue;
                 }
             }
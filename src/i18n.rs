@@ -0,0 +1,760 @@
+use std::{
+    fmt::Display,
+    sync::atomic::{AtomicU8, Ordering},
+};
+
+/// Translated message, arguments replace the {} placeholders
+///
+/// Messages are written in English, the catalog of the selected language
+/// has their translations. Translations can refer arguments by position,
+/// e.g. {1} {0}. Messages without translation are shown in English.
+#[macro_export]
+macro_rules! tr {
+    ($message:literal $(, $arg:expr)* $(,)?) => {
+        $crate::i18n::translate($message, &[$(&$arg as &dyn std::fmt::Display),*])
+    };
+}
+
+/// Language of messages
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Language {
+    En,
+    Hu,
+}
+
+// Selected language, 0 till it is set
+static LANGUAGE: AtomicU8 = AtomicU8::new(0);
+
+impl Language {
+    /// Language of a code like hu, hu_HU.UTF-8 or en-US
+    pub fn parse(code: &str) -> Option<Self> {
+        let lang = code.split(['_', '-', '.']).next().unwrap_or_default();
+        match lang.to_lowercase().as_str() {
+            "en" => Some(Language::En),
+            "hu" => Some(Language::Hu),
+            _ => None,
+        }
+    }
+    pub fn code(&self) -> &'static str {
+        match self {
+            Language::En => "en",
+            Language::Hu => "hu",
+        }
+    }
+    // Language of the environment, tests are always in English
+    fn from_env() -> Self {
+        if cfg!(test) {
+            return Language::En;
+        }
+        ["LC_ALL", "LC_MESSAGES", "LANG"]
+            .iter()
+            .filter_map(|name| std::env::var(name).ok())
+            .find(|value| !value.is_empty())
+            .and_then(|value| Self::parse(&value))
+            .unwrap_or(Language::En)
+    }
+    fn catalog(&self) -> &'static [(&'static str, &'static str)] {
+        match self {
+            Language::En => &[],
+            Language::Hu => HU,
+        }
+    }
+}
+
+/// Selected language, set by the project or the LANG environment variable
+pub fn language() -> Language {
+    match LANGUAGE.load(Ordering::Relaxed) {
+        1 => Language::En,
+        2 => Language::Hu,
+        _ => Language::from_env(),
+    }
+}
+
+pub fn set_language(language: Language) {
+    let value = match language {
+        Language::En => 1,
+        Language::Hu => 2,
+    };
+    LANGUAGE.store(value, Ordering::Relaxed);
+}
+
+/// Message of the selected language, use tr!
+pub fn translate(message: &'static str, args: &[&dyn Display]) -> String {
+    translate_to(language(), message, args)
+}
+
+fn translate_to(language: Language, message: &'static str, args: &[&dyn Display]) -> String {
+    let template = language
+        .catalog()
+        .iter()
+        .find(|(en, _)| *en == message)
+        .map_or(message, |(_, translation)| translation);
+    // {} is the next argument, {N} is the Nth one
+    let mut res = String::new();
+    let mut next = 0;
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        res.push_str(&rest[..start]);
+        let end = match rest[start..].find('}') {
+            Some(end) => start + end,
+            None => break,
+        };
+        let index = match &rest[start + 1..end] {
+            "" => {
+                next += 1;
+                next - 1
+            }
+            n => match n.parse::<usize>() {
+                Ok(n) => n,
+                Err(_) => {
+                    res.push_str(&rest[start..=end]);
+                    rest = &rest[end + 1..];
+                    continue;
+                }
+            },
+        };
+        if let Some(arg) = args.get(index) {
+            res.push_str(&arg.to_string());
+        }
+        rest = &rest[end + 1..];
+    }
+    res.push_str(rest);
+    res
+}
+
+// Hungarian messages
+const HU: &[(&str, &str)] = &[
+    ("{} errors, {} warnings", "{} hiba, {} figyelmeztetés"),
+    ("asset", "eszköz"),
+    ("liability", "kötelezettség"),
+    ("equity", "saját tőke"),
+    ("revenue", "bevétel"),
+    ("expense", "ráfordítás"),
+    ("off-balance", "mérlegen kívüli"),
+    ("debit", "tartozik"),
+    ("credit", "követel"),
+    ("reference", "hivatkozás"),
+    ("event", "esemény"),
+    (
+        "No completion date for transaction!",
+        "A tranzakciónak nincs teljesítési dátuma!",
+    ),
+    (
+        "Set COMPLETION_DATE for the note or CDATE for its event",
+        "Adj meg COMPLETION_DATE-et a jegyzetnek vagy CDATE-et az eseményének",
+    ),
+    (
+        "Unknown account ID {} for {}",
+        "Ismeretlen főkönyvi számla {0} ({1})",
+    ),
+    (
+        "Sub-accounts need their parent account {} declared",
+        "Az alszámlákhoz a(z) {} szülő számlát is meg kell adni",
+    ),
+    (
+        "Account {} has sub-accounts",
+        "A(z) {} számlának alszámlái vannak",
+    ),
+    ("Post to one of: {}", "Könyvelj ezek egyikére: {}"),
+    (
+        "Completion date {} is out of the ledger range",
+        "A(z) {} teljesítési dátum kívül esik a főkönyv időszakán",
+    ),
+    (
+        "Amount overflow in ledger",
+        "Összeg túlcsordulás a főkönyvben",
+    ),
+    (
+        "Amount overflow in ledger of {}",
+        "Összeg túlcsordulás a(z) {} főkönyvi számlán",
+    ),
+    (
+        "{} account {} has {} balance {}",
+        "A(z) {1} {0} számla egyenlege {2} {3}",
+    ),
+    (
+        "Check the postings, or the TYPE of the account",
+        "Ellenőrizd a könyveléseket vagy a számla TYPE értékét",
+    ),
+    ("Out of range date", "Időszakon kívüli dátum"),
+    ("Project name: ", "Projekt neve: "),
+    ("Project desc: ", "Projekt leírása: "),
+    ("Project currency: ", "Projekt pénzneme: "),
+    ("Project created", "A projekt elkészült"),
+    ("Balance sheet at {}", "Mérleg {} napon"),
+    ("Assets", "Eszközök"),
+    ("Total assets", "Eszközök összesen"),
+    ("Liabilities", "Kötelezettségek"),
+    ("Equity", "Saját tőke"),
+    ("Result of the year", "Tárgyévi eredmény"),
+    ("Total liabilities and equity", "Források összesen"),
+    ("Income statement", "Eredménykimutatás"),
+    ("Revenue", "Bevételek"),
+    ("Expense", "Ráfordítások"),
+    (
+        "Balance sheet does not balance, check account types",
+        "A mérleg nem egyezik, ellenőrizd a számlák típusát",
+    ),
+    ("Project is healthy", "A projekt rendben van"),
+    ("Note ID", "Jegyzet"),
+    ("Event ID", "Esemény"),
+    ("Name", "Név"),
+    ("C. Date", "Telj. dát."),
+    ("Amount", "Összeg"),
+    (
+        "Note has {} signed revisions",
+        "A jegyzetnek {} aláírt változata van",
+    ),
+    ("Not formatted: {}", "Nincs formázva: {}"),
+    ("Formatted: {}", "Formázva: {}"),
+    (
+        "{} files are not formatted, run bit fmt",
+        "{} fájl nincs formázva, futtasd a bit fmt parancsot",
+    ),
+    ("Account", "Számla"),
+    ("Debit", "Tartozik"),
+    ("Credit", "Követel"),
+    ("Ledger for date: {} - {}", "Főkönyv: {} - {}"),
+    ("Account ID", "Számla"),
+    ("T. Debit", "F. Tartozik"),
+    ("T. Credit", "F. Követel"),
+    ("B. Debit", "E. Tartozik"),
+    ("B. Credit", "E. Követel"),
+    ("Transactions", "Tranzakciók"),
+    ("Revisions", "Változatok"),
+    ("signed", "aláírva"),
+    ("invalid", "érvénytelen"),
+    ("unsigned", "aláíratlan"),
+    (
+        "--- must be alone in its line",
+        "A --- sorában más nem állhat",
+    ),
+    ("Title block is not closed", "A címblokk nincs lezárva"),
+    (
+        "Close the title with a --- line",
+        "Zárd le a címet egy --- sorral",
+    ),
+    (
+        "Unexpected ; without statement",
+        "Váratlan ; utasítás nélkül",
+    ),
+    (
+        "Multi-line statement is not terminated",
+        "A többsoros utasítás nincs lezárva",
+    ),
+    (
+        "Close the statement with ;",
+        "Zárd le az utasítást ; jellel",
+    ),
+    (
+        "Unexpected ; inside statement",
+        "Váratlan ; az utasításon belül",
+    ),
+    (
+        "Only one statement is allowed per ;",
+        "Egy ; jelhez csak egy utasítás tartozhat",
+    ),
+    (
+        "TEMPLATE can be defined only in account files",
+        "TEMPLATE csak számlafájlban adható meg",
+    ),
+    (
+        "INCLUDE can be used only in account files",
+        "INCLUDE csak számlafájlban használható",
+    ),
+    (
+        "Account cannot be defined in a note file",
+        "Jegyzetfájlban nem adható meg számla",
+    ),
+    ("Unknown command", "Ismeretlen utasítás"),
+    (
+        "Alias must have one parameter",
+        "Az ALIAS-nak egy paramétere kell legyen",
+    ),
+    (
+        "ID must have one parameter",
+        "Az ID-nak egy paramétere kell legyen",
+    ),
+    (
+        "DOCID must have one parameter",
+        "A DOCID-nak egy paramétere kell legyen",
+    ),
+    (
+        "AUTHOR must have at least one parameter",
+        "Az AUTHOR-nak legalább egy paramétere kell legyen",
+    ),
+    (
+        "TITLE must have at least one parameter",
+        "A TITLE-nek legalább egy paramétere kell legyen",
+    ),
+    (
+        "SOURCE must have one parameter",
+        "A SOURCE-nak egy paramétere kell legyen",
+    ),
+    (
+        "TYPE must have one parameter",
+        "A TYPE-nak egy paramétere kell legyen",
+    ),
+    (
+        "TAG accepts only #tag and key:value parameters",
+        "A TAG csak #címke és kulcs:érték paramétert fogad el",
+    ),
+    (
+        "TAG must have minimum 1 parameter",
+        "A TAG-nek legalább egy paramétere kell legyen",
+    ),
+    (
+        "PAYMENT_KIND must have one parameter",
+        "A PAYMENT_KIND-nak egy paramétere kell legyen",
+    ),
+    (
+        "PAYMENT_KIND value error. Must be: card, cash or transfer",
+        "Hibás PAYMENT_KIND érték. Lehet: card, cash vagy transfer",
+    ),
+    (
+        "TRANSACTION must have minimum 3 parameters. Debit, Credit, Amount",
+        "A TRANSACTION-nek legalább 3 paramétere kell legyen. Tartozik, Követel, Összeg",
+    ),
+    (
+        "Or use named form: TRANSACTION DEBIT .. CREDIT .. AMOUNT ..;",
+        "Vagy használd a nevesített alakot: TRANSACTION DEBIT .. CREDIT .. AMOUNT ..;",
+    ),
+    (
+        "{} leg must have an account and an amount",
+        "A(z) {} tételnek számla és összeg kell",
+    ),
+    (
+        "Use AMOUNT key for a simple transaction",
+        "Egyszerű tranzakcióhoz használd az AMOUNT kulcsot",
+    ),
+    (
+        "SIGNATURE must have a hash, and optionally a timestamp before it",
+        "A SIGNATURE-nek hash kell, előtte opcionálisan időbélyeg",
+    ),
+    ("USE must have a template name", "A USE-nak sablonnév kell"),
+    ("Unknown template", "Ismeretlen sablon"),
+    (
+        "Templates are defined in account files by TEMPLATE",
+        "Sablonok a számlafájlokban adhatók meg TEMPLATE-tel",
+    ),
+    ("Template {} is defined at {}", "A(z) {} sablon helye: {}"),
+    (
+        "USE {} must have {} arguments",
+        "A USE {} utasításnak {} argumentuma kell legyen",
+    ),
+    (
+        "INCLUDE must have one parameter",
+        "Az INCLUDE-nak egy paramétere kell legyen",
+    ),
+    (
+        "File, directory or file name pattern, e.g. INCLUDE accounts/384/*.bit",
+        "Fájl, mappa vagy fájlnév minta, pl. INCLUDE accounts/384/*.bit",
+    ),
+    (
+        "TYPE must have exactly one value",
+        "A TYPE-nak pontosan egy értéke kell legyen",
+    ),
+    (
+        "ACCOUNT must have at least two parameter",
+        "Az ACCOUNT-nak legalább két paramétere kell legyen",
+    ),
+    ("MISSING ID", "HIÁNYZÓ ID"),
+    ("Duplicate {} ID {}", "Ismétlődő {} azonosító: {}"),
+    ("Unknown reference ID {}", "Ismeretlen hivatkozás: {}"),
+    ("Unknown event ID {}", "Ismeretlen esemény: {}"),
+    ("Note has already exist.", "A jegyzet már létezik."),
+    (
+        "Error while creating note template file",
+        "Hiba a jegyzet sablonfájl létrehozásakor",
+    ),
+    (
+        "{} must have one parameter",
+        "A(z) {} utasításnak egy paramétere kell legyen",
+    ),
+    (
+        "{} must be a valid amount",
+        "A(z) {} érvényes összeg kell legyen",
+    ),
+    (
+        "{} can refer only to fields defined before it",
+        "A(z) {} csak előtte megadott mezőkre hivatkozhat",
+    ),
+    (
+        "Dimension is set more than once",
+        "A dimenzió többször van megadva",
+    ),
+    ("Unknown account type", "Ismeretlen számlatípus"),
+    (
+        "Account type must be one of: {}",
+        "A számla típusa ezek egyike lehet: {}",
+    ),
+    (
+        "Date must have valid ISO format. YYYY-mm-dd",
+        "A dátum érvényes ISO formátumú kell legyen. ÉÉÉÉ-hh-nn",
+    ),
+    ("Duplicate key {}", "Ismétlődő kulcs: {}"),
+    ("Unknown key {} for {}", "Ismeretlen kulcs: {0} ({1})"),
+    ("Allowed keys: {}", "Megengedett kulcsok: {}"),
+    ("Missing value for key {}", "Hiányzik a(z) {} kulcs értéke"),
+    (
+        "Split legs cannot be used together with AMOUNT",
+        "Bontott tételek nem használhatók AMOUNT-tal együtt",
+    ),
+    (
+        "{} must have exactly one value, quote texts with spaces",
+        "A(z) {} kulcsnak pontosan egy értéke lehet, a szóközös szöveget tedd idézőjelbe",
+    ),
+    ("{} must have {} key", "A(z) {} utasításnak {} kulcs kell"),
+    (
+        "TRANSACTION amount must be a valid amount",
+        "A TRANSACTION összege érvényes összeg kell legyen",
+    ),
+    (
+        "Transaction is not balanced",
+        "A tranzakció nincs egyensúlyban",
+    ),
+    (
+        "Debit total is {}, credit total is {}, difference {}",
+        "Tartozik összesen {}, követel összesen {}, eltérés {}",
+    ),
+    (
+        "Debit total is {}, credit total is {}",
+        "Tartozik összesen {}, követel összesen {}",
+    ),
+    (
+        "Transaction total overflow",
+        "A tranzakció összege túlcsordult",
+    ),
+    ("No config file found!", "Nem található konfigurációs fájl!"),
+    (
+        "Precision must be at most {}",
+        "A pontosság legfeljebb {} lehet",
+    ),
+    (
+        "Cannot read config.toml content",
+        "A config.toml nem olvasható",
+    ),
+    (
+        "Error while deserialize config.toml",
+        "Hiba a config.toml feldolgozásakor",
+    ),
+    (
+        "Decimal and thousand separator must be different",
+        "A tizedes- és az ezres elválasztó nem lehet azonos",
+    ),
+    ("Unknown language {}", "Ismeretlen nyelv: {}"),
+    ("Supported languages: en, hu", "Támogatott nyelvek: en, hu"),
+    (
+        "Accounts file or directory does not exist!",
+        "A számlafájl vagy mappa nem létezik!",
+    ),
+    (
+        "NOTEs path not exist or not a folder!",
+        "A jegyzetek útvonala nem létezik vagy nem mappa!",
+    ),
+    (
+        "Parent account {} of {} is not declared",
+        "A(z) {1} számla {0} szülő számlája nincs megadva",
+    ),
+    (
+        "Account {} is its own ancestor",
+        "A(z) {} számla saját maga őse",
+    ),
+    (
+        "Transaction mixes off-balance and balance sheet accounts",
+        "A tranzakció mérlegen kívüli és mérlegszámlákat is tartalmaz",
+    ),
+    (
+        "Cannot serialize to JSON: {}",
+        "Nem sikerült JSON-ba menteni: {}",
+    ),
+    (
+        "Cannot serialize to CBOR: {}",
+        "Nem sikerült CBOR-ba menteni: {}",
+    ),
+    (
+        "Unexpected token in expression",
+        "Váratlan elem a kifejezésben",
+    ),
+    (
+        "{} is not defined in this note",
+        "A(z) {} nincs megadva ebben a jegyzetben",
+    ),
+    ("Amount overflow", "Összeg túlcsordulás"),
+    ("Division by zero", "Osztás nullával"),
+    (
+        "Invalid token in expression",
+        "Érvénytelen elem a kifejezésben",
+    ),
+    (
+        "Invalid character in expression",
+        "Érvénytelen karakter a kifejezésben",
+    ),
+    (
+        "Expression ends unexpectedly",
+        "A kifejezés váratlanul véget ér",
+    ),
+    (
+        "Invalid amount in expression",
+        "Érvénytelen összeg a kifejezésben",
+    ),
+    ("Unknown reference {}", "Ismeretlen hivatkozás: {}"),
+    (
+        "Only NET, VAT and GROSS can be used in expressions",
+        "Kifejezésben csak NET, VAT és GROSS használható",
+    ),
+    ("Unclosed parenthesis", "Lezáratlan zárójel"),
+    (
+        "Expected number, field or parenthesis",
+        "Szám, mező vagy zárójel várható",
+    ),
+    ("Path not exist", "Az útvonal nem létezik"),
+    ("Path is not a directory", "Az útvonal nem mappa"),
+    (
+        "Include cycle, {} includes itself",
+        "Körkörös INCLUDE, a(z) {} saját magát tölti be",
+    ),
+    ("Include chain: {}", "INCLUDE lánc: {}"),
+    ("included from {}:{}:{}", "betöltve innen: {}:{}:{}"),
+    (
+        "Cannot read include directory: {}",
+        "Az INCLUDE mappa nem olvasható: {}",
+    ),
+    (
+        "No file matches include pattern {}",
+        "Egy fájl sem illeszkedik a(z) {} mintára",
+    ),
+    (
+        "Included file {} does not exist",
+        "A betöltött {} fájl nem létezik",
+    ),
+    (
+        "Cannot read note file: {}",
+        "A jegyzetfájl nem olvasható: {}",
+    ),
+    ("Unterminated string", "Lezáratlan szöveg"),
+    (
+        "Close the text with \", use \\\" to write a quote inside",
+        "Zárd le a szöveget \" jellel, idézőjel írásához használd a \\\" alakot",
+    ),
+    (
+        "Current working dir does not exist",
+        "Az aktuális munkakönyvtár nem létezik",
+    ),
+    ("No account file found!", "Nem található számlafájl!"),
+    (
+        "Given directory is not a BIT working directory",
+        "A mappa nem BIT munkakönyvtár",
+    ),
+    (
+        "{} is already set in this revision",
+        "A(z) {} már meg van adva ebben a változatban",
+    ),
+    (
+        "Fields can be overridden after a SIGNATURE",
+        "A mezők SIGNATURE után írhatók felül",
+    ),
+    (
+        "Signature does not match the signed lines",
+        "Az aláírás nem egyezik az aláírt sorokkal",
+    ),
+    (
+        "Signed lines are changed, append corrections after it",
+        "Az aláírt sorok megváltoztak, a javításokat utána írd",
+    ),
+    (
+        "Template signature is name(param, ..), e.g. purchase(expense, net)",
+        "A sablon alakja név(paraméter, ..), pl. purchase(expense, net)",
+    ),
+    (
+        "TEMPLATE must have a signature",
+        "A TEMPLATE-nek név és paraméterlista kell",
+    ),
+    ("Invalid template signature", "Érvénytelen sablon fejléc"),
+    ("Invalid template name", "Érvénytelen sablonnév"),
+    (
+        "Invalid or duplicate template parameter {}",
+        "Érvénytelen vagy ismétlődő sablonparaméter: {}",
+    ),
+    (
+        "TEMPLATE must have a transaction body",
+        "A TEMPLATE-nek tranzakció törzs kell",
+    ),
+    (
+        "e.g. TEMPLATE pay(supplier, bank, amount) supplier bank amount",
+        "pl. TEMPLATE pay(supplier, bank, amount) supplier bank amount",
+    ),
+    (
+        "Template {} is defined more than once",
+        "A(z) {} sablon többször van megadva",
+    ),
+    ("First defined at {}", "Első megadás helye: {}"),
+    (
+        "NET + VAT does not equal GROSS",
+        "NET + VAT nem egyenlő GROSS értékével",
+    ),
+    ("NET + VAT is {}, GROSS is {}", "NET + VAT {}, GROSS {}"),
+    (
+        "VAT postings do not equal VAT",
+        "Az ÁFA könyvelések nem egyeznek a VAT értékével",
+    ),
+    (
+        "Posted to {} is {}, VAT is {}",
+        "{} számlákra könyvelve {}, VAT {}",
+    ),
+    (
+        "Posted total does not equal GROSS",
+        "A könyvelt összeg nem egyezik GROSS értékével",
+    ),
+    (
+        "Posted total is {}, GROSS is {}",
+        "Könyvelt összeg {}, GROSS {}",
+    ),
+    ("{} must not be zero", "A(z) {} nem lehet nulla"),
+    (
+        "Transaction amount must not be zero",
+        "A tranzakció összege nem lehet nulla",
+    ),
+    ("Duplicate note {} {}", "Ismétlődő jegyzet {} {}"),
+    ("First defined at {}:{}:{}", "Első megadás helye: {}:{}:{}"),
+    ("Unknown ID namespace {}", "Ismeretlen azonosító névtér: {}"),
+    (
+        "ID {} has no namespace",
+        "A(z) {} azonosítónak nincs névtere",
+    ),
+    (
+        "ID {} does not match namespace {}",
+        "A(z) {} azonosító nem felel meg a(z) {} névtérnek",
+    ),
+    ("IDs of {} look like {}", "A(z) {} azonosítói ilyenek: {}"),
+    (
+        "ID {} repeats number {} of {}",
+        "A(z) {0} azonosító megismétli a(z) {2} {1} sorszámát",
+    ),
+    ("IDs are missing before {}", "Hiányzó azonosítók {} előtt"),
+    ("Missing: {}", "Hiányzik: {}"),
+    ("Namespaces: {}", "Névterek: {}"),
+    (
+        "Note IDs must have a namespace",
+        "A jegyzet azonosítójának névtér kell",
+    ),
+    (
+        "Choose one by --namespace",
+        "Válassz egyet a --namespace kapcsolóval",
+    ),
+    (
+        "Cannot create directory: {}",
+        "A mappa nem hozható létre: {}",
+    ),
+    ("Note created: {}", "Jegyzet létrehozva: {}"),
+    (
+        "No more IDs in namespace {}",
+        "Nincs több azonosító a(z) {} névtérben",
+    ),
+    (
+        "ID {} is out of range",
+        "A(z) {} azonosító kívül esik a tartományon",
+    ),
+    (
+        "Account {} is declared more than once",
+        "A(z) {} számla többször van megadva",
+    ),
+    (
+        "Check the PARENT of the accounts in the cycle",
+        "Ellenőrizze a körben lévő számlák PARENT értékét",
+    ),
+    ("Wrong month number. 1-12", "Hibás hónap. 1-12"),
+    (
+        "Month date should be a valid 1-12 integer",
+        "A hónap 1 és 12 közötti egész szám legyen",
+    ),
+    (
+        "Thousand separator {} must be followed by three digits",
+        "A(z) {} ezres elválasztó után három számjegy következik",
+    ),
+    (
+        "Invalid character '{}' in amount",
+        "Érvénytelen karakter az összegben: '{}'",
+    ),
+    (
+        "Amount must contain digits",
+        "Az összegnek számjegyet kell tartalmaznia",
+    ),
+    (
+        "Too many decimal digits, maximum {} allowed",
+        "Túl sok tizedesjegy, legfeljebb {} lehet",
+    ),
+    ("Amount is too large", "Az összeg túl nagy"),
+    ("Balance: {}", "Egyenleg: {}"),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_translate() {
+        assert_eq!(Language::parse("hu_HU.UTF-8"), Some(Language::Hu));
+        assert_eq!(Language::parse("en-US"), Some(Language::En));
+        assert_eq!(Language::parse("C"), None);
+        assert_eq!(
+            translate_to(
+                Language::En,
+                "Unknown account ID {} for {}",
+                &[&"5", &"DEBIT"]
+            ),
+            "Unknown account ID 5 for DEBIT"
+        );
+        assert_eq!(
+            translate_to(
+                Language::Hu,
+                "Unknown account ID {} for {}",
+                &[&"5", &"DEBIT"]
+            ),
+            "Ismeretlen főkönyvi számla 5 (DEBIT)"
+        );
+    }
+
+    #[test]
+    fn test_catalog() {
+        // Every message has its Hungarian translation
+        let sources = [
+            include_str!("chart.rs"),
+            include_str!("diagnostic.rs"),
+            include_str!("export.rs"),
+            include_str!("expr.rs"),
+            include_str!("format.rs"),
+            include_str!("fs.rs"),
+            include_str!("include.rs"),
+            include_str!("ledger.rs"),
+            include_str!("namespace.rs"),
+            include_str!("lsp.rs"),
+            include_str!("main.rs"),
+            include_str!("money.rs"),
+            include_str!("note.rs"),
+            include_str!("parser.rs"),
+            include_str!("project.rs"),
+            include_str!("report.rs"),
+            include_str!("revision.rs"),
+            include_str!("settings.rs"),
+            include_str!("template.rs"),
+            include_str!("validate.rs"),
+        ];
+        for source in sources {
+            for part in source.split("tr!(").skip(1) {
+                let Some(literal) = part.trim_start().strip_prefix('"') else {
+                    continue;
+                };
+                // Messages are compared in their escaped source form
+                assert!(
+                    HU.iter().any(|(en, _)| {
+                        let escaped = en.replace('\\', "\\\\").replace('"', "\\\"");
+                        literal.starts_with(&format!("{}\"", escaped))
+                    }),
+                    "No Hungarian translation: {}",
+                    literal.lines().next().unwrap_or_default()
+                );
+            }
+        }
+    }
+}
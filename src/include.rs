@@ -0,0 +1,346 @@
+use std::{
+    collections::HashSet,
+    path::{Component, Path, PathBuf},
+};
+
+use crate::{
+    diagnostic::{Code, Diagnostic, Diagnostics, Span},
+    fs::get_files_recours,
+    ledger::Account,
+    note::{Note, NoteContext},
+    template::Template,
+    tr,
+};
+
+/// Accounts and templates of the account files
+#[derive(Debug, Default)]
+pub struct AccountFiles {
+    pub accounts: Vec<Account>,
+    pub templates: Vec<Template>,
+    /// Loaded files in loading order
+    pub files: Vec<PathBuf>,
+    pub diagnostics: Diagnostics,
+}
+
+/// Load an account file or a directory of account files,
+/// following their INCLUDE commands
+///
+/// Every file is loaded once. Include cycles and errors of included
+/// files are reported with the chain of including files as hints.
+pub fn load_account_files(path: &Path, ctx: &NoteContext) -> AccountFiles {
+    let mut loader = Loader {
+        ctx,
+        loaded: HashSet::new(),
+        chain: Vec::new(),
+        res: AccountFiles::default(),
+    };
+    match path.is_dir() {
+        true => match bit_files(path) {
+            Ok(files) => files.iter().for_each(|file| loader.load(file)),
+            Err(e) => loader.res.diagnostics.push(e),
+        },
+        false => loader.load(path),
+    }
+    loader.res
+}
+
+struct Loader<'a> {
+    ctx: &'a NoteContext,
+    loaded: HashSet<PathBuf>,
+    /// Including files with the span of their INCLUDE, outermost first
+    chain: Vec<(PathBuf, Span)>,
+    res: AccountFiles,
+}
+
+impl<'a> Loader<'a> {
+    fn load(&mut self, path: &Path) {
+        if !self.loaded.insert(canonical(path)) {
+            return;
+        }
+        self.res.files.push(path.to_path_buf());
+        let note = match Note::from_file(path, true, self.ctx) {
+            Ok(note) => note,
+            Err(e) => {
+                for diagnostic in e {
+                    let diagnostic = self.with_chain(diagnostic);
+                    self.res.diagnostics.push(diagnostic);
+                }
+                return;
+            }
+        };
+        for warning in note.warnings {
+            let warning = self.with_chain(warning);
+            self.res.diagnostics.push(warning);
+        }
+        for account in note.accounts {
+            // The first declaration is kept, later ones are errors
+            if let Some(first) = self.res.accounts.iter().find(|a| a.id == account.id) {
+                let e = Diagnostic::error(
+                    Code::DuplicateId,
+                    tr!("Account {} is declared more than once", account.id),
+                )
+                .with_path(&account.path)
+                .with_span(account.span)
+                .with_hint(tr!(
+                    "First defined at {}:{}:{}",
+                    first.path.display(),
+                    first.span.line,
+                    first.span.column
+                ));
+                let e = self.with_chain(e);
+                self.res.diagnostics.push(e);
+                continue;
+            }
+            self.res.accounts.push(account);
+        }
+        self.res.templates.extend(note.templates);
+        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+        for include in note.includes {
+            let files = match resolve(dir, &include.path) {
+                Ok(files) => files,
+                Err(e) => {
+                    let e = self.with_chain(e.with_path(path).with_span(include.span));
+                    self.res.diagnostics.push(e);
+                    continue;
+                }
+            };
+            self.chain.push((path.to_path_buf(), include.span));
+            for file in files {
+                match self.is_cycle(&file) {
+                    true => {
+                        let e = self.cycle_error(&file);
+                        self.res.diagnostics.push(e);
+                    }
+                    false => self.load(&file),
+                }
+            }
+            self.chain.pop();
+        }
+    }
+    fn is_cycle(&self, file: &Path) -> bool {
+        let file = canonical(file);
+        self.chain.iter().any(|(path, _)| canonical(path) == file)
+    }
+    // Error at the last INCLUDE, the chain is shown from the first
+    // including file to the included one
+    fn cycle_error(&self, file: &Path) -> Diagnostic {
+        let (path, span) = self.chain.last().expect("Cycle needs an including file");
+        let files = self
+            .chain
+            .iter()
+            .map(|(path, _)| path.display().to_string())
+            .chain(std::iter::once(file.display().to_string()))
+            .collect::<Vec<String>>();
+        Diagnostic::error(
+            Code::IncludeCycle,
+            tr!("Include cycle, {} includes itself", file.display()),
+        )
+        .with_path(path)
+        .with_span(*span)
+        .with_hint(tr!("Include chain: {}", files.join(" -> ")))
+    }
+    // Add the including files as hints, innermost first
+    fn with_chain(&self, mut diagnostic: Diagnostic) -> Diagnostic {
+        for (path, span) in self.chain.iter().rev() {
+            diagnostic = diagnostic.with_hint(tr!(
+                "included from {}:{}:{}",
+                path.display(),
+                span.line,
+                span.column
+            ));
+        }
+        diagnostic
+    }
+}
+
+// Files of an INCLUDE, a file, all the .bit files of a directory or
+// the files matching a file name pattern with *
+fn resolve(dir: &Path, include: &str) -> Result<Vec<PathBuf>, Diagnostic> {
+    let path = normalize(&dir.join(include));
+    let file_name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    if file_name.contains('*') {
+        let parent = path.parent().unwrap_or(dir);
+        let entries = std::fs::read_dir(parent).map_err(|e| {
+            Diagnostic::error(Code::Io, tr!("Cannot read include directory: {}", e))
+        })?;
+        let mut files = entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.is_file())
+            .filter(|path| {
+                path.file_name()
+                    .is_some_and(|name| wildcard_match(&file_name, &name.to_string_lossy()))
+            })
+            .collect::<Vec<PathBuf>>();
+        files.sort();
+        return match files.is_empty() {
+            true => Err(Diagnostic::error(
+                Code::Io,
+                tr!("No file matches include pattern {}", include),
+            )),
+            false => Ok(files),
+        };
+    }
+    if path.is_dir() {
+        return bit_files(&path);
+    }
+    match path.is_file() {
+        true => Ok(vec![path]),
+        false => Err(Diagnostic::error(
+            Code::Io,
+            tr!("Included file {} does not exist", path.display()),
+        )),
+    }
+}
+
+// All the .bit files of a directory in name order
+fn bit_files(dir: &Path) -> Result<Vec<PathBuf>, Diagnostic> {
+    let mut files = get_files_recours(dir, Some("bit"))?
+        .iter()
+        .map(|doc| doc.relative_path().to_path_buf())
+        .collect::<Vec<PathBuf>>();
+    files.sort();
+    Ok(files)
+}
+
+// Remove . and .. components, so include chains stay readable
+fn normalize(path: &Path) -> PathBuf {
+    let mut res = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => (),
+            Component::ParentDir
+                if matches!(res.components().next_back(), Some(Component::Normal(_))) =>
+            {
+                res.pop();
+            }
+            component => res.push(component),
+        }
+    }
+    res
+}
+
+fn canonical(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
+
+// Match text to pattern, * matches any number of characters
+fn wildcard_match(pattern: &str, text: &str) -> bool {
+    match pattern.split_once('*') {
+        None => pattern == text,
+        Some((prefix, rest)) => {
+            let text = match text.strip_prefix(prefix) {
+                Some(text) => text,
+                None => return false,
+            };
+            (0..=text.len())
+                .filter(|i| text.is_char_boundary(*i))
+                .any(|i| wildcard_match(rest, &text[i..]))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wildcard_match() {
+        assert!(wildcard_match("*.bit", "peti_bank.bit"));
+        assert!(wildcard_match("p*_*.bit", "peti_bank.bit"));
+        assert!(!wildcard_match("*.bit", "peti_bank.toml"));
+        assert!(wildcard_match("*", ""));
+    }
+
+    #[test]
+    fn test_includes() {
+        let dir = std::env::temp_dir().join(format!("bit_include_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("384")).unwrap();
+        let write = |name: &str, content: &str| std::fs::write(dir.join(name), content).unwrap();
+        write(
+            "accounts.bit",
+            "% 384 Bank\nINCLUDE 384/*.bit\nINCLUDE 384\n",
+        );
+        write(
+            "384/peti_bank.bit",
+            "% 384/peti \"Peti bank\"\nINCLUDE ../cycle.bit\n",
+        );
+        write(
+            "384/kriszti_bank.bit",
+            "% 384/kriszti \"Kriszti bank\"\n% 9\n",
+        );
+        write(
+            "cycle.bit",
+            "INCLUDE 384/peti_bank.bit\nINCLUDE missing.bit\n",
+        );
+
+        let files = load_account_files(&dir.join("accounts.bit"), &Default::default());
+        let ids = files
+            .accounts
+            .iter()
+            .map(|a| a.id.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(ids, ["384", "384/peti"]);
+        let res = files
+            .diagnostics
+            .iter()
+            .map(|d| (d.code(), d.hints().len()))
+            .collect::<Vec<_>>();
+        // Kriszti bank has a parameter error, included from accounts.bit
+        assert_eq!(
+            res,
+            [
+                (Code::ParameterCount, 1),
+                (Code::IncludeCycle, 1),
+                (Code::Io, 2)
+            ]
+        );
+        let cycle = files.diagnostics.iter().nth(1).unwrap();
+        assert_eq!(cycle.span(), Some(Span::new(1, 9, 17)));
+        let chain = format!(
+            "Include chain: {0}/accounts.bit -> {0}/384/peti_bank.bit -> {0}/cycle.bit -> {0}/384/peti_bank.bit",
+            dir.display()
+        );
+        assert_eq!(cycle.hints(), [chain]);
+
+        // Accounts directory, every file is loaded once
+        let files = load_account_files(&dir, &Default::default());
+        assert_eq!(files.accounts.len(), 2);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_duplicate_accounts() {
+        let dir = std::env::temp_dir().join(format!("bit_duplicate_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let write = |name: &str, content: &str| std::fs::write(dir.join(name), content).unwrap();
+        write("accounts.bit", "% 384 Bank\nINCLUDE other.bit\n");
+        write("other.bit", "% 5 Cost\n% 384 \"Bank again\"\n");
+
+        let files = load_account_files(&dir.join("accounts.bit"), &Default::default());
+        let accounts = files
+            .accounts
+            .iter()
+            .map(|a| (a.id.as_str(), a.name.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(accounts, [("384", "Bank"), ("5", "Cost")]);
+        let e = files.diagnostics.iter().next().unwrap();
+        assert_eq!(files.diagnostics.len(), 1);
+        assert_eq!(e.code(), Code::DuplicateId);
+        assert_eq!(e.path(), Some(dir.join("other.bit").as_path()));
+        assert_eq!(e.span().map(|s| s.line), Some(2));
+        assert_eq!(
+            e.hints()[0],
+            format!(
+                "First defined at {}:1:3",
+                dir.join("accounts.bit").display()
+            )
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
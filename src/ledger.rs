@@ -1,202 +1,514 @@
 use chrono::{Datelike, NaiveDate, Utc};
-use std::{
-    collections::{HashMap, HashSet},
-    ops::Add,
-    path::PathBuf,
-};
-use thousands::Separable;
+use serde::Serialize;
+use std::{collections::HashMap, path::PathBuf};
 
-use crate::note::Note;
+use crate::{
+    chart::Chart,
+    diagnostic::{Code, Diagnostic, Diagnostics, Span},
+    money::Money,
+    note::{Note, Side, Tag, Transaction as NoteTransaction},
+    tr,
+};
 
-#[derive(Default, Debug, Eq, Hash, PartialEq)]
+#[derive(Default, Debug, Eq, Hash, PartialEq, Serialize)]
 pub struct Account {
     pub id: String,
     pub name: String,
+    /// Explicit parent account
+    pub parent: Option<String>,
+    /// Account classification, sub-accounts inherit it
+    pub account_type: Option<AccountType>,
+    /// Location of the ACCOUNT command
+    pub path: PathBuf,
+    pub span: Span,
 }
 
 impl Account {
     pub fn new(id: String, name: String) -> Self {
-        Self { id, name }
+        Self {
+            id,
+            name,
+            parent: None,
+            account_type: None,
+            path: PathBuf::new(),
+            span: Span::default(),
+        }
+    }
+}
+
+/// Account classification
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AccountType {
+    Asset,
+    Liability,
+    Equity,
+    Revenue,
+    Expense,
+    OffBalance,
+}
+
+impl AccountType {
+    /// Translated name for messages
+    pub fn label(&self) -> String {
+        match self {
+            AccountType::Asset => tr!("asset"),
+            AccountType::Liability => tr!("liability"),
+            AccountType::Equity => tr!("equity"),
+            AccountType::Revenue => tr!("revenue"),
+            AccountType::Expense => tr!("expense"),
+            AccountType::OffBalance => tr!("off-balance"),
+        }
+    }
+    pub const ALL: [AccountType; 6] = [
+        AccountType::Asset,
+        AccountType::Liability,
+        AccountType::Equity,
+        AccountType::Revenue,
+        AccountType::Expense,
+        AccountType::OffBalance,
+    ];
+    pub fn parse(text: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|t| t.to_string().eq_ignore_ascii_case(text))
+    }
+    /// Side on which the balance normally stands
+    pub fn normal_side(&self) -> Side {
+        match self {
+            AccountType::Asset | AccountType::Expense | AccountType::OffBalance => Side::Debit,
+            AccountType::Liability | AccountType::Equity | AccountType::Revenue => Side::Credit,
+        }
+    }
+}
+
+impl std::fmt::Display for AccountType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AccountType::Asset => write!(f, "asset"),
+            AccountType::Liability => write!(f, "liability"),
+            AccountType::Equity => write!(f, "equity"),
+            AccountType::Revenue => write!(f, "revenue"),
+            AccountType::Expense => write!(f, "expense"),
+            AccountType::OffBalance => write!(f, "off_balance"),
+        }
     }
 }
 
-#[derive(Debug)]
-struct Transaction {
-    completion_date: NaiveDate,
-    amount: i64,
-    debit: String,
-    credit: String,
+/// Posted leg of a journal entry
+#[derive(Debug, Clone, Serialize)]
+pub struct JournalLeg {
+    pub side: Side,
+    pub account: String,
+    pub amount: Money,
+}
+
+/// Posted transaction
+#[derive(Debug, Clone, Serialize)]
+pub struct JournalEntry {
+    pub note_id: Option<String>,
+    pub event_id: Option<String>,
+    pub completion_date: NaiveDate,
+    pub legs: Vec<JournalLeg>,
+    /// Note and transaction tags
+    pub tags: Vec<Tag>,
+}
+
+impl JournalEntry {
+    /// Transaction amount, the total of the debit legs
+    pub fn amount(&self) -> Result<Money, Diagnostic> {
+        self.legs
+            .iter()
+            .filter(|leg| leg.side == Side::Debit)
+            .try_fold(Money::default(), |sum, leg| sum.checked_add(&leg.amount))
+            .ok_or_else(overflow)
+    }
 }
 
 #[derive(Default, Debug)]
 pub struct Ledger {
     ledger_index: Vec<HashMap<String, LedgerIndexItem>>,
+    journal: Vec<JournalEntry>,
+    /// Journal entry indexes by tag
+    tag_index: HashMap<Tag, Vec<usize>>,
     note_counter: i32,
     transaction_counter: i32,
 }
 
 impl Ledger {
-    pub fn init(&mut self, accounts: &HashSet<String>) {
+    pub fn init(&mut self, chart: &Chart) {
         // Create all days in index as empty
         self.ledger_index = (0..NaiveDate::from_ymd(Utc::today().year(), 12, 31).ordinal0())
             .into_iter()
             .map(|_| HashMap::new())
             .collect();
         // Add accounts to index to each day
-        accounts.iter().for_each(|account| {
-            self.ledger_index.iter_mut().for_each(|day| {
-                day.insert(account.clone(), LedgerIndexItem::default());
-            });
-        });
+        chart.ids().for_each(|account| self.ensure_account(account));
     }
-    pub fn add_note(&mut self, note: &Note, accounts: &HashSet<String>) -> Result<(), String> {
-        if note.transactions.len() == 0 {
+    // Add account to each day, sub-accounts can be created after init
+    fn ensure_account(&mut self, account: &str) {
+        for day in self.ledger_index.iter_mut() {
+            if !day.contains_key(account) {
+                day.insert(account.to_string(), LedgerIndexItem::default());
+            }
+        }
+    }
+    /// Add note transactions to ledger
+    ///
+    /// Invalid transactions are skipped, and all of their errors are returned.
+    pub fn add_note(&mut self, note: &Note, chart: &Chart) -> Result<(), Diagnostics> {
+        if note.transactions.is_empty() {
             return Ok(());
         }
-        let completion_date = note
-            .completion_date
-            .clone()
-            .ok_or_else(|| "No completion date for note!".to_string())?;
         self.note_counter += 1;
+        let mut diagnostics = Diagnostics::new();
         for transaction in &note.transactions {
-            self.add_transaction(
-                Transaction {
-                    completion_date: completion_date,
-                    amount: transaction.amount,
-                    debit: transaction.debit.clone(),
-                    credit: transaction.credit.clone(),
-                },
-                accounts,
-            )?;
+            // Transactions can have their own completion date by their event
+            let completion_date = match note.transaction_completion_date(transaction) {
+                Some(date) => date,
+                None => {
+                    diagnostics.push(
+                        Diagnostic::error(
+                            Code::MissingCompletionDate,
+                            tr!("No completion date for transaction!"),
+                        )
+                        .with_path(&note.path)
+                        .with_span(transaction.span)
+                        .with_hint(tr!(
+                            "Set COMPLETION_DATE for the note or CDATE for its event"
+                        )),
+                    );
+                    continue;
+                }
+            };
+            let tags = note.transaction_tags(transaction);
+            let res =
+                self.add_transaction(note.id.clone(), transaction, completion_date, tags, chart);
+            if let Err(e) = res {
+                let e = match &transaction.template {
+                    Some(template) => e.with_hint(template.clone()),
+                    None => e,
+                };
+                diagnostics.push(e.with_path(&note.path));
+            }
+        }
+        match diagnostics.is_empty() {
+            true => Ok(()),
+            false => Err(diagnostics),
         }
-        Ok(())
     }
+    // Post all the legs of a transaction, or none of them
     fn add_transaction(
         &mut self,
-        transaction: Transaction,
-        accounts: &HashSet<String>,
-    ) -> Result<(), String> {
-        let has_account = |account_id: &str| accounts.contains(account_id);
-        // Check tr debit account
-        if !has_account(&transaction.debit) {
-            return Err(format!(
-                "Unknown account ID {} for debit",
-                &transaction.debit
-            ));
-        }
-        // Check tr credit account
-        if !has_account(&transaction.credit) {
-            return Err(format!(
-                "Unknown account ID for credit {}",
-                &transaction.credit
-            ));
+        note_id: Option<String>,
+        transaction: &NoteTransaction,
+        completion_date: NaiveDate,
+        tags: Vec<Tag>,
+        chart: &Chart,
+    ) -> Result<(), Diagnostic> {
+        // Check leg accounts
+        for leg in &transaction.legs {
+            if !chart.contains(&leg.account) {
+                let e = Diagnostic::error(
+                    Code::UnknownAccount,
+                    tr!(
+                        "Unknown account ID {} for {}",
+                        leg.account,
+                        leg.side.label()
+                    ),
+                )
+                .with_span(leg.account_span);
+                return Err(match leg.account.split_once('/') {
+                    Some((parent, _)) => e.with_hint(tr!(
+                        "Sub-accounts need their parent account {} declared",
+                        parent
+                    )),
+                    None => e,
+                });
+            }
+            if chart.leaf_only && !chart.is_leaf(&leg.account) {
+                let children = chart
+                    .children(&leg.account)
+                    .map(|a| a.id.as_str())
+                    .collect::<Vec<&str>>();
+                return Err(Diagnostic::error(
+                    Code::NonLeafAccount,
+                    tr!("Account {} has sub-accounts", leg.account),
+                )
+                .with_span(leg.account_span)
+                .with_hint(tr!("Post to one of: {}", children.join(", "))));
+            }
         }
+        transaction.check_balance()?;
         // Add transaction to index
-        let last_day = self.ledger_index.len();
-        let completion_day = transaction.completion_date.ordinal0() as usize;
-
-        // Process completion day debit
-        let index = &mut self.ledger_index[completion_day];
-        // cbd => closing balance debit
-        let cbd = match index.get_mut(&transaction.debit) {
-            Some(index_item) => {
-                index_item.td += transaction.amount;
-                index_item.bc = index_item.bo + (index_item.td - index_item.tc);
-                index_item.bc
+        let completion_day = completion_date.ordinal0() as usize;
+        if completion_day >= self.ledger_index.len() {
+            return Err(Diagnostic::error(
+                Code::DateOutOfRange,
+                tr!(
+                    "Completion date {} is out of the ledger range",
+                    completion_date
+                ),
+            )
+            .with_span(transaction.span));
+        }
+        for leg in &transaction.legs {
+            self.post_leg(&leg.account, completion_day, leg.side, leg.amount)
+                .ok_or_else(|| overflow().with_span(leg.account_span))?;
+        }
+        self.journal.push(JournalEntry {
+            note_id,
+            event_id: transaction.event_id.clone(),
+            completion_date,
+            legs: transaction
+                .legs
+                .iter()
+                .map(|leg| JournalLeg {
+                    side: leg.side,
+                    account: leg.account.clone(),
+                    amount: leg.amount,
+                })
+                .collect(),
+            tags,
+        });
+        self.index_tags(self.journal.len() - 1);
+        Ok(())
+    }
+    fn index_tags(&mut self, entry: usize) {
+        for tag in &self.journal[entry].tags {
+            self.tag_index.entry(tag.clone()).or_default().push(entry);
+        }
+    }
+    /// Tags used by posted transactions
+    pub fn tags(&self) -> impl Iterator<Item = &Tag> {
+        self.tag_index.keys()
+    }
+    /// Posted transactions with the given tag
+    pub fn tag_entries(&self, tag: &Tag) -> impl Iterator<Item = &JournalEntry> {
+        self.tag_index
+            .get(tag)
+            .into_iter()
+            .flatten()
+            .map(|i| &self.journal[*i])
+    }
+    /// Ledger of the transactions with the given tag
+    pub fn tagged(&self, chart: &Chart, tag: &Tag) -> Result<Ledger, Diagnostic> {
+        let mut res = Ledger::default();
+        res.init(chart);
+        for entry in self.tag_entries(tag) {
+            let day = entry.completion_date.ordinal0() as usize;
+            for leg in &entry.legs {
+                res.post_leg(&leg.account, day, leg.side, leg.amount)
+                    .ok_or_else(|| {
+                        Diagnostic::error(
+                            Code::AmountOverflow,
+                            tr!("Amount overflow in ledger of {}", tag),
+                        )
+                    })?;
             }
-            None => panic!("Cannot get mutable account"),
+            res.journal.push(entry.clone());
+            res.index_tags(res.journal.len() - 1);
+        }
+        Ok(res)
+    }
+    /// Posted transactions in posting order
+    pub fn journal(&self) -> &[JournalEntry] {
+        &self.journal
+    }
+    /// Posted transactions of a note event
+    pub fn event_entries<'a>(
+        &'a self,
+        note_id: &'a str,
+        event_id: &'a str,
+    ) -> impl Iterator<Item = &'a JournalEntry> {
+        self.journal.iter().filter(move |entry| {
+            entry.note_id.as_deref() == Some(note_id) && entry.event_id.as_deref() == Some(event_id)
+        })
+    }
+    // Post a debit or credit leg, creating the account if needed
+    fn post_leg(&mut self, account: &str, day: usize, side: Side, amount: Money) -> Option<()> {
+        self.ensure_account(account);
+        let zero = Money::zero(amount.precision());
+        let (td, tc) = match side {
+            Side::Debit => (amount, zero),
+            Side::Credit => (zero, amount),
         };
-        // cbc => closing balance credit
-        let cbc = match index.get_mut(&transaction.credit) {
-            Some(index_item) => {
-                index_item.tc += transaction.amount;
-                index_item.bc = index_item.bo + (index_item.td - index_item.tc);
-                index_item.bc
+        self.post(account, day, td, tc)
+    }
+    // Post turnover on the completion day, and update the opening
+    // balance of all the following days
+    fn post(&mut self, account: &str, day: usize, td: Money, tc: Money) -> Option<()> {
+        let change = td.checked_sub(&tc)?;
+        for (day_number, index) in self.ledger_index.iter_mut().enumerate().skip(day) {
+            let item = index.get_mut(account).expect("Cannot get mutable account");
+            if day_number == day {
+                item.td = item.td.checked_add(&td)?;
+                item.tc = item.tc.checked_add(&tc)?;
+            } else {
+                item.bo = item.bo.checked_add(&change)?;
             }
-            None => panic!("Cannot get mutable account"),
+            item.bc = item.bo.checked_add(&item.td)?.checked_sub(&item.tc)?;
+        }
+        Some(())
+    }
+    /// Index of the last ledger day
+    pub fn last_day(&self) -> usize {
+        self.ledger_index.len().saturating_sub(1)
+    }
+    /// Accounts with closing balance on the opposite of their normal side
+    ///
+    /// Only leaf accounts are checked, parents would repeat the same warning.
+    pub fn unusual_balances(&self, chart: &Chart) -> Vec<Diagnostic> {
+        let mut res = Vec::new();
+        let day = match self.ledger_index.last() {
+            Some(day) => day,
+            None => return res,
         };
-
-        (completion_day + 1..last_day)
-            .into_iter()
-            .for_each(|day_number| {
-                let item = &mut self.ledger_index[day_number];
-
-                // Process debit
-                match item.get_mut(&transaction.debit) {
-                    Some(index_item) => {
-                        index_item.bo += cbd;
-                        index_item.bc += index_item.bo + index_item.td - index_item.tc;
-                    }
-                    None => (),
+        for (account, _) in chart.tree() {
+            let (account_type, item) = match (chart.account_type(&account.id), day.get(&account.id))
+            {
+                (Some(account_type), Some(item)) if chart.is_leaf(&account.id) => {
+                    (account_type, item)
                 }
-
-                // Process credit
-                match item.get_mut(&transaction.credit) {
-                    Some(index_item) => {
-                        index_item.bo += cbc;
-                        index_item.bc += index_item.bo + index_item.td - index_item.tc;
-                    }
-                    None => (),
+                _ => continue,
+            };
+            let side = account_type.normal_side();
+            let balance = match item.natural_balance(side) {
+                Ok(balance) => balance,
+                Err(err) => {
+                    res.push(err);
+                    continue;
                 }
-            });
-        Ok(())
+            };
+            if balance.is_negative() {
+                let opposite = match side {
+                    Side::Debit => Side::Credit,
+                    Side::Credit => Side::Debit,
+                };
+                res.push(
+                    Diagnostic::warning(
+                        Code::UnusualBalance,
+                        tr!(
+                            "{} account {} has {} balance {}",
+                            account_type.label(),
+                            account.id,
+                            opposite.label(),
+                            balance.abs().separate_with_spaces()
+                        ),
+                    )
+                    .with_hint(tr!("Check the postings, or the TYPE of the account")),
+                );
+            }
+        }
+        res
     }
     pub fn get_ledger_by_date(
         &self,
         day_index: usize,
-    ) -> Result<HashMap<String, LedgerIndexItem>, String> {
+    ) -> Result<HashMap<String, LedgerIndexItem>, Diagnostic> {
         let day = self
             .ledger_index
             .get(day_index)
-            .ok_or("Out of range date")?;
+            .ok_or_else(|| Diagnostic::error(Code::DateOutOfRange, tr!("Out of range date")))?;
         Ok((*day).clone())
     }
 }
 
-#[derive(Debug, Default, Clone)]
+/// Error of ledger sums out of the Money range
+pub fn overflow() -> Diagnostic {
+    Diagnostic::error(Code::AmountOverflow, tr!("Amount overflow in ledger"))
+}
+
+#[derive(Debug, Default, Clone, Serialize)]
 pub struct LedgerIndexItem {
     /// Balance opening
-    bo: i64,
+    #[serde(rename = "opening")]
+    bo: Money,
     /// Turnover debit
-    td: i64,
+    #[serde(rename = "debit")]
+    td: Money,
     /// Turnover credit
-    tc: i64,
+    #[serde(rename = "credit")]
+    tc: Money,
     /// Balance closing
-    bc: i64,
+    #[serde(rename = "closing")]
+    bc: Money,
 }
 
-impl Add for LedgerIndexItem {
-    type Output = LedgerIndexItem;
-
-    fn add(self, rhs: Self) -> Self::Output {
-        let new_td = self.td + rhs.td;
-        let new_tc = self.tc + rhs.tc;
-        LedgerIndexItem {
-            bo: self.bo,
-            td: new_td,
-            tc: new_tc,
-            bc: self.bo + new_td - new_tc,
+impl LedgerIndexItem {
+    /// Closing balance, debit minus credit
+    pub fn closing_balance(&self) -> Money {
+        self.bc
+    }
+    /// Closing balance positive on the given side
+    pub fn natural_balance(&self, side: Side) -> Result<Money, Diagnostic> {
+        match side {
+            Side::Debit => Ok(self.bc),
+            Side::Credit => self.bc.checked_neg().ok_or_else(overflow),
         }
     }
-}
-
-impl LedgerIndexItem {
-    pub fn print_full(&self) -> String {
-        format!(
+    /// Item of a period, this item followed by the turnovers of the next
+    /// day
+    pub fn followed_by(&self, next: &Self) -> Result<Self, Diagnostic> {
+        let td = self.td.checked_add(&next.td).ok_or_else(overflow)?;
+        let tc = self.tc.checked_add(&next.tc).ok_or_else(overflow)?;
+        let bc = self
+            .bo
+            .checked_add(&td)
+            .and_then(|b| b.checked_sub(&tc))
+            .ok_or_else(overflow)?;
+        Ok(Self {
+            bo: self.bo,
+            td,
+            tc,
+            bc,
+        })
+    }
+    /// Sum of two account items, e.g. parent and sub-account
+    pub fn combine(&self, rhs: &Self) -> Result<Self, Diagnostic> {
+        let sum = |a: Money, b: Money| a.checked_add(&b).ok_or_else(overflow);
+        Ok(Self {
+            bo: sum(self.bo, rhs.bo)?,
+            td: sum(self.td, rhs.td)?,
+            tc: sum(self.tc, rhs.tc)?,
+            bc: sum(self.bc, rhs.bc)?,
+        })
+    }
+    /// Turnovers and closing balance, the balance on the normal side of
+    /// the account type
+    ///
+    /// A balance on the other side is marked with !. Without account type
+    /// the balance is split by its sign.
+    pub fn print_full(&self, account_type: Option<AccountType>) -> Result<String, Diagnostic> {
+        let side = match account_type {
+            Some(account_type) => account_type.normal_side(),
+            None if self.bc.is_negative() => Side::Credit,
+            None => Side::Debit,
+        };
+        let balance = self.natural_balance(side)?;
+        let (side, balance) = match side {
+            _ if !balance.is_negative() => (side, balance.separate_with_spaces()),
+            Side::Debit => (
+                Side::Credit,
+                format!("{} !", balance.abs().separate_with_spaces()),
+            ),
+            Side::Credit => (
+                Side::Debit,
+                format!("{} !", balance.abs().separate_with_spaces()),
+            ),
+        };
+        let zero = Money::zero(self.bc.precision()).separate_with_spaces();
+        let (debit, credit) = match side {
+            Side::Debit => (balance, zero),
+            Side::Credit => (zero, balance),
+        };
+        Ok(format!(
             "{0: <13} | {1: <13} | {2: <13} | {3: <13}",
             self.td.separate_with_spaces(),
             self.tc.separate_with_spaces(),
-            match self.bc {
-                x if x >= 0 => x.abs(),
-                _ => 0,
-            }
-            .separate_with_spaces(),
-            match self.bc {
-                x if x < 0 => x.abs(),
-                _ => 0,
-            }
-            .separate_with_spaces()
-        )
+            debit,
+            credit
+        ))
     }
 }
 
@@ -208,11 +520,11 @@ pub fn unzip_dates(date_str: Option<String>) -> Result<(NaiveDate, NaiveDate), S
             // Try to map month str to i32
             let month = month
                 .parse::<u32>()
-                .map_err(|_| "Wrong month number. 1-12".to_string())?;
+                .map_err(|_| tr!("Wrong month number. 1-12"))?;
 
             // Check date range
             if month < 1 || month > 12 {
-                return Err("Month date should be a valid 1-12 integer".to_string());
+                return Err(tr!("Month date should be a valid 1-12 integer"));
             }
 
             // Define month first day
@@ -232,3 +544,229 @@ pub fn unzip_dates(date_str: Option<String>) -> Result<(NaiveDate, NaiveDate), S
         )),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn note(date: NaiveDate, amounts: &[i64]) -> Note {
+        Note {
+            completion_date: Some(date),
+            transactions: amounts
+                .iter()
+                .map(|amount| NoteTransaction::simple("1", "2", Money::new(*amount, 2)))
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    fn chart(ids: &[&str]) -> Chart {
+        let mut chart = Chart::default();
+        for id in ids {
+            chart.add(&Account::new(id.to_string(), String::new()));
+        }
+        chart.link();
+        chart
+    }
+
+    #[test]
+    fn test_sub_account_rollup() {
+        let mut accounts = chart(&["1", "2"]);
+        let mut ledger = Ledger::default();
+        ledger.init(&accounts);
+        let day = NaiveDate::from_ymd_opt(Utc::now().year(), 1, 10).unwrap();
+        let mut note = note(day, &[1000]);
+        note.transactions
+            .push(NoteTransaction::simple("1", "2/a", Money::new(250, 2)));
+        note.transactions
+            .push(NoteTransaction::simple("1", "3/a", Money::new(5, 2)));
+        assert!(accounts.resolve("2/a"));
+        assert!(!accounts.resolve("3/a"));
+        let err = ledger.add_note(&note, &accounts).unwrap_err();
+        assert_eq!(err.len(), 1);
+
+        let day = accounts
+            .rollup(&ledger.get_ledger_by_date(9).unwrap())
+            .unwrap();
+        assert_eq!(day["2/a"].tc, Money::new(250, 2));
+        assert_eq!(day["2"].tc, Money::new(1250, 2));
+        assert_eq!(day["2"].bc, Money::new(-1250, 2));
+    }
+
+    #[test]
+    fn test_leaf_only() {
+        let mut accounts = chart(&["1", "2", "21"]);
+        accounts.leaf_only = true;
+        let mut ledger = Ledger::default();
+        ledger.init(&accounts);
+        let day = NaiveDate::from_ymd_opt(Utc::now().year(), 1, 10).unwrap();
+        let mut note = note(day, &[1000]);
+        note.transactions
+            .push(NoteTransaction::simple("1", "21", Money::new(250, 2)));
+        let err = ledger.add_note(&note, &accounts).unwrap_err();
+        let err = err.iter().collect::<Vec<_>>();
+        assert_eq!(err.len(), 1);
+        assert_eq!(err[0].code(), Code::NonLeafAccount);
+        assert_eq!(err[0].hints(), ["Post to one of: 21"]);
+        assert_eq!(ledger.journal().len(), 1);
+    }
+
+    #[test]
+    fn test_event_journal() {
+        let accounts = chart(&["1", "2"]);
+        let mut ledger = Ledger::default();
+        ledger.init(&accounts);
+        let day = NaiveDate::from_ymd_opt(Utc::now().year(), 1, 10).unwrap();
+        let mut note = note(day, &[1000, 250]);
+        note.id = Some("n1".to_string());
+        note.events.push(crate::note::Event {
+            id: "e1".to_string(),
+            completion_date: day.succ_opt(),
+            ..Default::default()
+        });
+        note.transactions[1].event_id = Some("e1".to_string());
+        ledger.add_note(&note, &accounts).unwrap();
+
+        assert_eq!(ledger.journal().len(), 2);
+        let entries = ledger.event_entries("n1", "e1").collect::<Vec<_>>();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].amount().unwrap(), Money::new(250, 2));
+        assert_eq!(entries[0].completion_date, day.succ_opt().unwrap());
+        assert_eq!(
+            ledger.get_ledger_by_date(9).unwrap()["1"].td,
+            Money::new(1000, 2)
+        );
+    }
+
+    #[test]
+    fn test_split_transaction() {
+        let accounts = chart(&["1", "2"]);
+        let mut ledger = Ledger::default();
+        ledger.init(&accounts);
+        let day = NaiveDate::from_ymd_opt(Utc::now().year(), 1, 10).unwrap();
+        let mut note = note(day, &[1000]);
+        let mut transaction = NoteTransaction::simple("1", "2", Money::new(1000, 2));
+        transaction.legs[1].amount = Money::new(600, 2);
+        transaction.legs.push(
+            NoteTransaction::simple("1", "2", Money::new(400, 2))
+                .legs
+                .remove(1),
+        );
+        note.transactions.push(transaction);
+        // Unbalanced transaction is not posted at all
+        let mut unbalanced = NoteTransaction::simple("1", "2", Money::new(1, 2));
+        unbalanced.legs[0].amount = Money::new(2, 2);
+        note.transactions.push(unbalanced);
+        let err = ledger.add_note(&note, &accounts).unwrap_err();
+        assert_eq!(
+            err.iter().next().unwrap().code(),
+            Code::UnbalancedTransaction
+        );
+
+        assert_eq!(ledger.journal().len(), 2);
+        assert_eq!(ledger.journal()[1].legs.len(), 3);
+        assert_eq!(ledger.journal()[1].amount().unwrap(), Money::new(1000, 2));
+        let item = &ledger.get_ledger_by_date(9).unwrap()["2"];
+        assert_eq!(item.tc, Money::new(2000, 2));
+    }
+
+    #[test]
+    fn test_unusual_balances() {
+        let mut accounts = Chart::default();
+        for id in ["1", "2"] {
+            let mut account = Account::new(id.to_string(), String::new());
+            account.account_type = Some(AccountType::Asset);
+            accounts.add(&account);
+        }
+        let mut ledger = Ledger::default();
+        ledger.init(&accounts);
+        let day = NaiveDate::from_ymd_opt(Utc::now().year(), 1, 10).unwrap();
+        ledger.add_note(&note(day, &[1000]), &accounts).unwrap();
+        let warnings = ledger.unusual_balances(&accounts);
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].code(), Code::UnusualBalance);
+        assert!(warnings[0]
+            .message()
+            .contains("account 2 has credit balance"));
+
+        let day = ledger.get_ledger_by_date(9).unwrap();
+        let balance = |id: &str, account_type| {
+            let line = day[id].print_full(account_type).unwrap();
+            line.split('|')
+                .skip(2)
+                .map(|s| s.trim().to_string())
+                .collect::<Vec<String>>()
+        };
+        let asset = Some(AccountType::Asset);
+        assert_eq!(balance("1", asset), ["10.00", "0.00"]);
+        assert_eq!(balance("2", asset), ["0.00", "10.00 !"]);
+        assert_eq!(balance("2", Some(AccountType::Revenue)), ["0.00", "10.00"]);
+        assert_eq!(balance("2", None), ["0.00", "10.00"]);
+    }
+
+    #[test]
+    fn test_tagged_ledger() {
+        let accounts = chart(&["1", "2"]);
+        let mut ledger = Ledger::default();
+        ledger.init(&accounts);
+        let day = NaiveDate::from_ymd_opt(Utc::now().year(), 1, 10).unwrap();
+        let mut note = note(day, &[1000, 250]);
+        note.tags = vec![Tag::Tag("fuel".into())];
+        note.transactions[1].tags = vec![Tag::Dimension("project".into(), "alpha".into())];
+        ledger.add_note(&note, &accounts).unwrap();
+
+        let project = Tag::Dimension("project".into(), "alpha".into());
+        assert_eq!(ledger.tag_entries(&project).count(), 1);
+        let tagged = ledger.tagged(&accounts, &project).unwrap();
+        let item = &tagged.get_ledger_by_date(9).unwrap()["1"];
+        assert_eq!(item.td, Money::new(250, 2));
+        let fuel = ledger.tagged(&accounts, &Tag::Tag("fuel".into())).unwrap();
+        assert_eq!(fuel.journal().len(), 2);
+        assert!(ledger
+            .tagged(&accounts, &Tag::Tag("other".into()))
+            .unwrap()
+            .journal()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_balances() {
+        let accounts = chart(&["1", "2"]);
+        let mut ledger = Ledger::default();
+        ledger.init(&accounts);
+        let day = NaiveDate::from_ymd_opt(Utc::now().year(), 1, 10).unwrap();
+        ledger
+            .add_note(&note(day, &[1000, 250]), &accounts)
+            .unwrap();
+
+        let completion_day = ledger.get_ledger_by_date(9).unwrap();
+        let item = &completion_day["1"];
+        assert_eq!(item.td, Money::new(1250, 2));
+        assert_eq!(item.bc, Money::new(1250, 2));
+
+        let next_day = ledger.get_ledger_by_date(10).unwrap();
+        assert_eq!(next_day["1"].bo, Money::new(1250, 2));
+        assert_eq!(next_day["1"].bc, Money::new(1250, 2));
+        assert_eq!(next_day["2"].bc, Money::new(-1250, 2));
+    }
+
+    #[test]
+    fn test_overflow() {
+        let max = LedgerIndexItem {
+            bc: Money::new(i64::MAX, 0),
+            ..Default::default()
+        };
+        let err = max.combine(&max).unwrap_err();
+        assert_eq!(err.code(), Code::AmountOverflow);
+        let min = LedgerIndexItem {
+            bc: Money::new(i64::MIN, 0),
+            ..Default::default()
+        };
+        assert!(min.natural_balance(Side::Credit).is_err());
+        let item = LedgerIndexItem {
+            td: Money::new(i64::MAX, 0),
+            ..Default::default()
+        };
+        assert!(item.followed_by(&item).is_err());
+    }
+}
@@ -1,10 +1,24 @@
 #![feature(test)]
 extern crate test;
 
+pub mod chart;
+pub mod diagnostic;
+pub mod export;
+pub mod expr;
+pub mod format;
 pub mod fs;
+pub mod i18n;
+pub mod include;
 pub mod ledger;
+pub mod lsp;
+pub mod money;
+pub mod namespace;
 pub mod note;
 pub mod parser;
 pub mod project;
+pub mod report;
+pub mod revision;
 pub mod settings;
 pub mod signature;
+pub mod template;
+pub mod validate;
@@ -0,0 +1,378 @@
+use std::{collections::HashMap, error::Error, path::Path};
+
+use lsp_server::{Connection, ErrorCode, Message, Notification, Request, Response};
+use lsp_types::{
+    notification::{
+        DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument, DidSaveTextDocument,
+        Notification as _, PublishDiagnostics,
+    },
+    request::{Completion, Formatting, GotoDefinition, HoverRequest, Request as _},
+    CompletionItem, CompletionItemKind, CompletionOptions, CompletionParams, CompletionResponse,
+    DiagnosticSeverity, DocumentFormattingParams, GotoDefinitionParams, GotoDefinitionResponse,
+    Hover, HoverContents, HoverParams, HoverProviderCapability, InitializeParams, Location,
+    MarkupContent, MarkupKind, NumberOrString, OneOf, Position, PublishDiagnosticsParams, Range,
+    ServerCapabilities, TextDocumentSyncCapability, TextDocumentSyncKind, TextEdit, Url,
+};
+use serde::Serialize;
+
+use crate::{
+    diagnostic::{Diagnostic, Severity, Span},
+    format::format_note,
+    parser::Command,
+    project::Project,
+    tr,
+};
+
+type LspResult<T> = Result<T, Box<dyn Error + Sync + Send>>;
+
+/// Language server on stdin and stdout
+///
+/// The project is loaded when a file is opened or saved, and its
+/// diagnostics are published for every file. Completion, hover and go
+/// to definition use the accounts of the last load. Columns are counted
+/// in characters.
+pub fn run() -> LspResult<()> {
+    let (connection, io_threads) = Connection::stdio();
+    let capabilities = serde_json::to_value(ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+        completion_provider: Some(CompletionOptions::default()),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
+        definition_provider: Some(OneOf::Left(true)),
+        document_formatting_provider: Some(OneOf::Left(true)),
+        ..Default::default()
+    })?;
+    let params: InitializeParams = serde_json::from_value(connection.initialize(capabilities)?)?;
+    // Project is searched from the working directory
+    if let Some(folder) = params.workspace_folders.iter().flatten().next() {
+        if let Ok(path) = folder.uri.to_file_path() {
+            std::env::set_current_dir(path)?;
+        }
+    }
+    let mut server = Server::default();
+    for notification in server.load() {
+        connection.sender.send(notification.into())?;
+    }
+    for message in &connection.receiver {
+        match message {
+            Message::Request(request) => {
+                if connection.handle_shutdown(&request)? {
+                    break;
+                }
+                connection.sender.send(server.request(request).into())?;
+            }
+            Message::Notification(notification) => {
+                for notification in server.notification(notification)? {
+                    connection.sender.send(notification.into())?;
+                }
+            }
+            Message::Response(_) => (),
+        }
+    }
+    // Writer thread stops when the connection is dropped
+    drop(connection);
+    io_threads.join()?;
+    Ok(())
+}
+
+#[derive(Default)]
+struct Server {
+    project: Option<Project>,
+    /// Text of the open documents
+    documents: HashMap<Url, String>,
+    /// Files with published diagnostics, cleared on the next load
+    published: Vec<Url>,
+}
+
+impl Server {
+    // Load the project and publish diagnostics per file
+    fn load(&mut self) -> Vec<Notification> {
+        let diagnostics = match Project::inspect() {
+            Ok((project, diagnostics)) => {
+                self.project = Some(project);
+                diagnostics.into_iter().collect()
+            }
+            Err(e) => vec![e],
+        };
+        let mut files: HashMap<Url, Vec<lsp_types::Diagnostic>> = self
+            .published
+            .drain(..)
+            .map(|uri| (uri, Vec::new()))
+            .collect();
+        for diagnostic in &diagnostics {
+            if let Some(uri) = diagnostic.path().and_then(file_url) {
+                files.entry(uri).or_default().push(to_lsp(diagnostic));
+            }
+        }
+        files
+            .into_iter()
+            .map(|(uri, diagnostics)| {
+                if !diagnostics.is_empty() {
+                    self.published.push(uri.clone());
+                }
+                let params = PublishDiagnosticsParams::new(uri, diagnostics, None);
+                Notification::new(PublishDiagnostics::METHOD.to_string(), params)
+            })
+            .collect()
+    }
+    fn notification(&mut self, notification: Notification) -> LspResult<Vec<Notification>> {
+        let params = notification.params;
+        match notification.method.as_str() {
+            DidOpenTextDocument::METHOD => {
+                let params: lsp_types::DidOpenTextDocumentParams = serde_json::from_value(params)?;
+                let document = params.text_document;
+                self.documents.insert(document.uri, document.text);
+                Ok(self.load())
+            }
+            DidChangeTextDocument::METHOD => {
+                let params: lsp_types::DidChangeTextDocumentParams =
+                    serde_json::from_value(params)?;
+                // Full sync, the last change is the whole text
+                if let Some(change) = params.content_changes.into_iter().last() {
+                    self.documents.insert(params.text_document.uri, change.text);
+                }
+                Ok(Vec::new())
+            }
+            DidSaveTextDocument::METHOD => Ok(self.load()),
+            DidCloseTextDocument::METHOD => {
+                let params: lsp_types::DidCloseTextDocumentParams = serde_json::from_value(params)?;
+                self.documents.remove(&params.text_document.uri);
+                Ok(Vec::new())
+            }
+            _ => Ok(Vec::new()),
+        }
+    }
+    fn request(&self, request: Request) -> Response {
+        let id = request.id.clone();
+        let res = match request.method.as_str() {
+            Completion::METHOD => handle(request, |p: CompletionParams| {
+                self.completion(
+                    p.text_document_position.text_document.uri,
+                    p.text_document_position.position,
+                )
+                .map(CompletionResponse::Array)
+            }),
+            HoverRequest::METHOD => handle(request, |p: HoverParams| {
+                let position = p.text_document_position_params;
+                self.hover(position.text_document.uri, position.position)
+            }),
+            GotoDefinition::METHOD => handle(request, |p: GotoDefinitionParams| {
+                let position = p.text_document_position_params;
+                self.definition(position.text_document.uri, position.position)
+                    .map(GotoDefinitionResponse::Scalar)
+            }),
+            Formatting::METHOD => handle(request, |p: DocumentFormattingParams| {
+                self.formatting(p.text_document.uri)
+            }),
+            _ => {
+                return Response::new_err(
+                    id,
+                    ErrorCode::MethodNotFound as i32,
+                    format!("Unknown method {}", request.method),
+                )
+            }
+        };
+        match res {
+            Ok(value) => Response::new_ok(id, value),
+            Err(e) => Response::new_err(id, ErrorCode::InvalidParams as i32, e.to_string()),
+        }
+    }
+    // Commands at the start of the line, account IDs after it
+    fn completion(&self, uri: Url, position: Position) -> Option<Vec<CompletionItem>> {
+        let line = self
+            .documents
+            .get(&uri)?
+            .lines()
+            .nth(position.line as usize)?;
+        let before = line
+            .chars()
+            .take(position.character as usize)
+            .collect::<String>();
+        if !before.trim_start().contains(' ') {
+            let items = Command::keywords()
+                .into_iter()
+                .map(|keyword| CompletionItem {
+                    label: keyword.to_string(),
+                    kind: Some(CompletionItemKind::KEYWORD),
+                    ..Default::default()
+                })
+                .collect();
+            return Some(items);
+        }
+        let items = self
+            .project
+            .as_ref()?
+            .accounts
+            .iter()
+            .map(|account| CompletionItem {
+                label: account.id.clone(),
+                kind: Some(CompletionItemKind::VALUE),
+                detail: Some(account.name.clone()),
+                ..Default::default()
+            })
+            .collect();
+        Some(items)
+    }
+    // Account name and its balance at the last posted day
+    fn hover(&self, uri: Url, position: Position) -> Option<Hover> {
+        let project = self.project.as_ref()?;
+        let text = self.documents.get(&uri)?;
+        let (id, range) = word_at(text, position)?;
+        let account = project.chart().get(&id)?;
+        let ledger = &project.ledger;
+        let balance = ledger
+            .get_ledger_by_date(ledger.last_day())
+            .and_then(|items| project.chart().rollup(&items))
+            .ok()
+            .and_then(|items| items.get(&id).map(|item| item.closing_balance()))
+            .unwrap_or_default();
+        Some(Hover {
+            contents: HoverContents::Markup(MarkupContent {
+                kind: MarkupKind::Markdown,
+                value: format!(
+                    "**{}** {}\n\n{}",
+                    account.id,
+                    account.name,
+                    tr!("Balance: {}", balance.separate_with_spaces())
+                ),
+            }),
+            range: Some(range),
+        })
+    }
+    // ACCOUNT line of the account, or of its closest declared parent
+    fn definition(&self, uri: Url, position: Position) -> Option<Location> {
+        let project = self.project.as_ref()?;
+        let (mut id, _) = word_at(self.documents.get(&uri)?, position)?;
+        loop {
+            if let Some(account) = project.accounts.iter().find(|a| a.id == id) {
+                return Some(Location::new(
+                    file_url(&account.path)?,
+                    span_range(account.span),
+                ));
+            }
+            id = id.rsplit_once('/')?.0.to_string();
+        }
+    }
+    // Whole document replaced by the formatted text
+    fn formatting(&self, uri: Url) -> Option<Vec<TextEdit>> {
+        let text = self.documents.get(&uri)?;
+        let format = self
+            .project
+            .as_ref()
+            .map(|project| project.settings().money_format())
+            .unwrap_or_default();
+        let formatted = format_note(text, &format);
+        if &formatted == text {
+            return Some(Vec::new());
+        }
+        let end = Position::new(text.lines().count() as u32 + 1, 0);
+        let range = Range::new(Position::new(0, 0), end);
+        Some(vec![TextEdit::new(range, formatted)])
+    }
+}
+
+// Parse request parameters and serialize the result
+fn handle<P, R>(request: Request, f: impl FnOnce(P) -> Option<R>) -> LspResult<serde_json::Value>
+where
+    P: serde::de::DeserializeOwned,
+    R: Serialize,
+{
+    let params: P = serde_json::from_value(request.params)?;
+    Ok(serde_json::to_value(f(params))?)
+}
+
+fn to_lsp(diagnostic: &Diagnostic) -> lsp_types::Diagnostic {
+    let mut message = diagnostic.message().to_string();
+    for hint in diagnostic.hints() {
+        message.push_str(&format!("\nhint: {}", hint));
+    }
+    lsp_types::Diagnostic {
+        range: diagnostic.span().map(span_range).unwrap_or_default(),
+        severity: Some(match diagnostic.severity() {
+            Severity::Error => DiagnosticSeverity::ERROR,
+            Severity::Warning => DiagnosticSeverity::WARNING,
+        }),
+        code: Some(NumberOrString::String(
+            diagnostic.code().as_str().to_string(),
+        )),
+        source: Some("bit".to_string()),
+        message,
+        ..Default::default()
+    }
+}
+
+// Spans are 1 based, LSP positions are 0 based
+fn span_range(span: Span) -> Range {
+    let line = span.line.saturating_sub(1) as u32;
+    let start = span.column.saturating_sub(1) as u32;
+    Range::new(
+        Position::new(line, start),
+        Position::new(line, start + span.len as u32),
+    )
+}
+
+// Absolute file URL, relative paths are relative to the working directory
+fn file_url(path: &Path) -> Option<Url> {
+    let path = std::env::current_dir().ok()?.join(path);
+    Url::from_file_path(path.canonicalize().unwrap_or(path)).ok()
+}
+
+// Word under the cursor, words are separated by whitespace, quotes and ;
+fn word_at(text: &str, position: Position) -> Option<(String, Range)> {
+    let line = text.lines().nth(position.line as usize)?;
+    let chars = line.chars().collect::<Vec<char>>();
+    let is_word = |ch: &char| !ch.is_whitespace() && !matches!(ch, '"' | ';');
+    let cursor = (position.character as usize).min(chars.len());
+    let start = chars[..cursor]
+        .iter()
+        .rposition(|ch| !is_word(ch))
+        .map_or(0, |i| i + 1);
+    let end = chars[cursor..]
+        .iter()
+        .position(|ch| !is_word(ch))
+        .map_or(chars.len(), |i| cursor + i);
+    if start == end {
+        return None;
+    }
+    let range = Range::new(
+        Position::new(position.line, start as u32),
+        Position::new(position.line, end as u32),
+    );
+    Some((chars[start..end].iter().collect(), range))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diagnostic::Code;
+
+    #[test]
+    fn test_word_at() {
+        let text = "ID 1\n> 161 454/agroker 1_000;\n";
+        let (word, range) = word_at(text, Position::new(1, 9)).unwrap();
+        assert_eq!(word, "454/agroker");
+        assert_eq!(range, Range::new(Position::new(1, 6), Position::new(1, 17)));
+        assert_eq!(word_at(text, Position::new(1, 23)).unwrap().0, "1_000");
+        assert_eq!(word_at(text, Position::new(1, 1)).unwrap().0, ">");
+        assert_eq!(word_at(text, Position::new(5, 0)), None);
+    }
+
+    #[test]
+    fn test_to_lsp() {
+        let diagnostic = Diagnostic::warning(Code::UnusualBalance, "Negative cash")
+            .with_span(Span::new(3, 5, 4))
+            .with_hint("Check the bank");
+        let res = to_lsp(&diagnostic);
+        assert_eq!(
+            res.range,
+            Range::new(Position::new(2, 4), Position::new(2, 8))
+        );
+        assert_eq!(res.severity, Some(DiagnosticSeverity::WARNING));
+        assert_eq!(res.message, "Negative cash\nhint: Check the bank");
+        assert_eq!(
+            res.code,
+            Some(NumberOrString::String(
+                Code::UnusualBalance.as_str().to_string()
+            ))
+        );
+    }
+}
@@ -1,12 +1,21 @@
 use bit::{
-    ledger::{unzip_dates, LedgerIndexItem},
+    diagnostic::Diagnostic,
+    export::{ProjectExport, Versioned},
+    format::format_note,
+    ledger::{overflow, unzip_dates, AccountType, Ledger, LedgerIndexItem},
+    money::Money,
+    note::{Note, Side, Tag},
+    parser::NoteRaw,
     project::Project,
+    report::Report,
+    tr,
 };
 use chrono::{Datelike, NaiveDate, Utc};
 use std::{
     collections::HashMap,
     error::Error,
     io::{self, BufRead, Write},
+    path::PathBuf,
     usize,
 };
 use structopt::clap::Shell;
@@ -20,14 +29,70 @@ use structopt::StructOpt;
 enum Command {
     #[structopt(about = "Create new BIT project")]
     New(NewOpt),
-    #[structopt(about = "Generate report about project")]
-    Report,
+    #[structopt(about = "Print balance sheet and income statement")]
+    Report(ReportOpt),
     #[structopt(about = "Check project health")]
     Check,
     #[structopt(about = "Get ledger details by date")]
     Ledger(LedgerOpt),
     #[structopt(about = "Create new note")]
-    Nn,
+    Nn(NnOpt),
+    #[structopt(about = "List business events with their turnover")]
+    Events,
+    #[structopt(about = "List posted journal entries")]
+    Journal,
+    #[structopt(about = "Note tools")]
+    Note(NoteCommand),
+    #[structopt(about = "Format unsigned lines of notes and account files")]
+    Fmt(FmtOpt),
+    #[structopt(about = "Start language server on stdin and stdout")]
+    Lsp,
+    #[structopt(about = "Export accounts, notes, journal and balances")]
+    Export(ExportOpt),
+}
+
+#[derive(Debug, StructOpt)]
+enum NoteCommand {
+    #[structopt(about = "Show effective note and its revision history")]
+    Show(ShowOpt),
+}
+
+#[derive(Debug, StructOpt)]
+pub struct ShowOpt {
+    /// Note ID or path
+    pub note: String,
+    /// Show the note as it was signed in this revision
+    #[structopt(long)]
+    pub revision: Option<usize>,
+    /// Print the parsed note as versioned JSON
+    #[structopt(long)]
+    pub json: bool,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct ExportOpt {
+    /// Balances date, default is today
+    pub date: Option<String>,
+    /// Export CBOR instead of JSON
+    #[structopt(long)]
+    pub cbor: bool,
+    /// Output file, default is stdout
+    #[structopt(short, long, parse(from_os_str))]
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct NnOpt {
+    /// ID namespace of the note, e.g. bank
+    #[structopt(long)]
+    pub namespace: Option<String>,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct FmtOpt {
+    /// Only list the files that are not formatted
+    #[structopt(long)]
+    pub check: bool,
 }
 
 #[derive(Debug, StructOpt)]
@@ -35,60 +100,298 @@ pub struct NewOpt {
     pub project_folder_name: String,
 }
 
+#[derive(Debug, StructOpt)]
+pub struct ReportOpt {
+    /// Report date, default is today
+    pub date: Option<String>,
+    /// Report only transactions with this #tag or key:value dimension
+    #[structopt(long)]
+    pub tag: Option<String>,
+}
+
 #[derive(Debug, StructOpt)]
 pub struct LedgerOpt {
     pub date: Option<String>,
+    /// Show accounts till this depth, top level accounts have depth 1
+    #[structopt(long)]
+    pub depth: Option<usize>,
+    /// Show only transactions with this #tag or key:value dimension
+    #[structopt(long)]
+    pub tag: Option<String>,
 }
 
-fn main() -> Result<(), Box<dyn Error>> {
+fn main() {
+    // Print errors rendered, diagnostics have their own source snippets
+    if let Err(e) = run() {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+fn run() -> Result<(), Box<dyn Error>> {
     // generate `bash` completions in "target" directory
     // Command::clap().gen_completions(env!("CARGO_PKG_NAME"), Shell::Bash, "target");
 
     let opt: Command = Command::from_args();
 
     match opt {
-        Command::Nn => {
+        Command::Nn(nopt) => {
             let project = Project::try_init()?;
+            let path = project.create_note(nopt.namespace.as_deref())?;
+            println!("{}", tr!("Note created: {}", path.display()));
         }
         Command::New(nopt) => {
             let mut line = String::new();
             let stdin = io::stdin();
 
             // Read project name
-            print!("Project name: ");
+            print!("{}", tr!("Project name: "));
             io::stdout().flush().unwrap();
             stdin.lock().read_line(&mut line).unwrap();
             let name = line.trim_end().to_owned();
             line.clear();
 
             // Read project desc
-            print!("Project desc: ");
+            print!("{}", tr!("Project desc: "));
             io::stdout().flush().unwrap();
             stdin.lock().read_line(&mut line).unwrap();
             let desc = line.trim_end().to_owned();
             line.clear();
 
             // Read project currency
-            print!("Project currency: ");
+            print!("{}", tr!("Project currency: "));
             io::stdout().flush().unwrap();
             stdin.lock().read_line(&mut line).unwrap();
             let currency = line.trim_end().to_owned();
             line.clear();
 
             // Project::new_project(&nopt.project_folder_name, name, desc, currency)?;
-            println!("Project created");
+            println!("{}", tr!("Project created"));
         }
-        Command::Report => {
+        Command::Report(ropt) => {
             // Init project
             let project = Project::try_init()?;
-            // let ledger = project.inspect()?;
-            // println!("{:?}", ledger);
+            let date = match ropt.date {
+                Some(date) => NaiveDate::parse_from_str(&date, "%Y-%m-%d")?,
+                None => Utc::now().date_naive(),
+            };
+            let tagged = tagged_ledger(&project, ropt.tag)?;
+            let ledger = tagged.as_ref().unwrap_or(&project.ledger);
+            let day_index = (date.ordinal0() as usize).min(ledger.last_day());
+            let items = ledger
+                .get_ledger_by_date(day_index)
+                .expect("Cannot get ledger by day index");
+            let precision = project.settings().money_format().precision;
+            let report = Report::new(project.chart(), &items, precision)?;
+            let total_sources = report.total_sources()?;
+
+            let print_line = |name: String, amount: Money| {
+                println!("{0: <40} {1: >15}", name, amount.separate_with_spaces())
+            };
+            let print_section = |title: String, account_type: AccountType| {
+                let section = report.section(account_type);
+                println!("{}", title);
+                for line in &section.lines {
+                    print_line(
+                        format!(
+                            "{}{} {}",
+                            "  ".repeat(line.depth + 1),
+                            line.id,
+                            line.name.chars().take(25).collect::<String>()
+                        ),
+                        line.amount,
+                    );
+                }
+                section.total
+            };
+            println!("\n{}\n", tr!("Balance sheet at {}", date));
+            let assets = print_section(tr!("Assets"), AccountType::Asset);
+            print_line(tr!("Total assets"), assets);
+            println!("{}", "-".repeat(56));
+            print_section(tr!("Liabilities"), AccountType::Liability);
+            print_section(tr!("Equity"), AccountType::Equity);
+            print_line(format!("  {}", tr!("Result of the year")), report.result);
+            print_line(tr!("Total liabilities and equity"), total_sources);
+            println!("\n{}\n", tr!("Income statement"));
+            print_section(tr!("Revenue"), AccountType::Revenue);
+            print_section(tr!("Expense"), AccountType::Expense);
+            print_line(tr!("Result of the year"), report.result);
+            if assets != total_sources {
+                println!(
+                    "\n{}",
+                    tr!("Balance sheet does not balance, check account types")
+                );
+            }
         }
         Command::Check => {
+            let (_project, diagnostics) = Project::inspect()?;
+            // Print every diagnostic, then the summary
+            for diagnostic in diagnostics.iter() {
+                eprintln!("{}\n", diagnostic);
+            }
+            println!("{}", diagnostics.summary());
+            if diagnostics.has_errors() {
+                std::process::exit(1);
+            }
+            println!("{}", tr!("Project is healthy"));
+        }
+        Command::Events => {
+            let project = Project::try_init()?;
+            let mut notes = project.notes().values().collect::<Vec<_>>();
+            notes.sort_by(|a, b| a.id.cmp(&b.id));
+
+            println!(
+                "{0: <12} {1: <12} {2: <25} {3: <10} {4: >15}",
+                tr!("Note ID"),
+                tr!("Event ID"),
+                tr!("Name"),
+                tr!("C. Date"),
+                tr!("Amount")
+            );
+            println!("{}", "-".repeat(78));
+            for note in notes {
+                let note_id = note.id.as_deref().unwrap_or_default();
+                for event in &note.events {
+                    let entries = project
+                        .ledger
+                        .event_entries(note_id, &event.id)
+                        .collect::<Vec<_>>();
+                    let amount = entries.iter().try_fold(Money::default(), |sum, entry| {
+                        sum.checked_add(&entry.amount()?).ok_or_else(overflow)
+                    })?;
+                    let date = entries
+                        .iter()
+                        .map(|entry| entry.completion_date)
+                        .min()
+                        .map(|date| date.to_string())
+                        .unwrap_or_default();
+                    println!(
+                        "{0: <12} {1: <12} {2: <25} {3: <10} {4: >15}",
+                        note_id,
+                        event.id,
+                        event
+                            .name
+                            .as_deref()
+                            .unwrap_or_default()
+                            .chars()
+                            .take(25)
+                            .collect::<String>(),
+                        date,
+                        amount.separate_with_spaces()
+                    );
+                }
+            }
+        }
+        Command::Note(NoteCommand::Show(sopt)) => {
+            let (project, _) = Project::inspect()?;
+            // Notes with errors are not in the project, find them by path
+            let path = project
+                .notes()
+                .values()
+                .find(|note| note.id.as_deref() == Some(sopt.note.as_str()))
+                .map(|note| note.path.clone())
+                .unwrap_or_else(|| PathBuf::from(&sopt.note));
+            let mut raw = NoteRaw::from_file(&path)?;
+            if let Some(revision) = sopt.revision {
+                raw = raw.until_revision(revision);
+            }
+            let note = Note::from_raw_note(raw, false, project.note_context())?;
+            let signed = note
+                .history
+                .revisions()
+                .iter()
+                .filter(|r| r.is_signed())
+                .count();
+            if sopt
+                .revision
+                .is_some_and(|revision| revision == 0 || revision > signed)
+            {
+                return Err(tr!("Note has {} signed revisions", signed).into());
+            }
+            match sopt.json {
+                true => println!("{}", Versioned::new(&note).to_json()?),
+                false => print_note(&note),
+            }
+        }
+        Command::Export(eopt) => {
             let project = Project::try_init()?;
-            // let _ = project.inspect()?;
-            println!("{:?}", project);
-            println!("Project is healthy");
+            let date = match eopt.date {
+                Some(date) => NaiveDate::parse_from_str(&date, "%Y-%m-%d")?,
+                None => Utc::now().date_naive(),
+            };
+            let export = Versioned::new(ProjectExport::new(
+                &project,
+                date,
+                date.ordinal0() as usize,
+            )?);
+            let bytes = match eopt.cbor {
+                true => export.to_cbor()?,
+                false => (export.to_json()? + "\n").into_bytes(),
+            };
+            match eopt.output {
+                Some(path) => std::fs::write(path, bytes)?,
+                None => io::stdout().write_all(&bytes)?,
+            }
+        }
+        Command::Lsp => bit::lsp::run().map_err(|e| e.to_string())?,
+        Command::Fmt(fopt) => {
+            let (project, _) = Project::inspect()?;
+            let format = project.settings().money_format();
+            let mut unformatted = 0;
+            for path in project.account_files().iter().chain(project.note_files()) {
+                let text = std::fs::read_to_string(path)?;
+                let formatted = format_note(&text, &format);
+                if formatted == text {
+                    continue;
+                }
+                unformatted += 1;
+                match fopt.check {
+                    true => println!("{}", tr!("Not formatted: {}", path.display())),
+                    false => {
+                        std::fs::write(path, formatted)?;
+                        println!("{}", tr!("Formatted: {}", path.display()));
+                    }
+                }
+            }
+            if fopt.check && unformatted > 0 {
+                println!(
+                    "{}",
+                    tr!("{} files are not formatted, run bit fmt", unformatted)
+                );
+                std::process::exit(1);
+            }
+        }
+        Command::Journal => {
+            let project = Project::try_init()?;
+            println!(
+                "{0: <10} {1: <25} {2: >15} {3: >15}",
+                tr!("C. Date"),
+                tr!("Account"),
+                tr!("Debit"),
+                tr!("Credit")
+            );
+            println!("{}", "-".repeat(68));
+            let mut entries = project.ledger.journal().iter().collect::<Vec<_>>();
+            entries.sort_by_key(|entry| entry.completion_date);
+            for entry in entries {
+                println!(
+                    "{0: <10} {1} {2}",
+                    entry.completion_date,
+                    entry.note_id.as_deref().unwrap_or_default(),
+                    entry.event_id.as_deref().unwrap_or_default()
+                );
+                for leg in &entry.legs {
+                    let amount = leg.amount.separate_with_spaces();
+                    let (debit, credit) = match leg.side {
+                        Side::Debit => (amount, String::new()),
+                        Side::Credit => (String::new(), amount),
+                    };
+                    println!(
+                        "{0: <10} {1: <25} {2: >15} {3: >15}",
+                        "", leg.account, debit, credit
+                    );
+                }
+            }
         }
         Command::Ledger(lopt) => {
             // Init project
@@ -103,7 +406,8 @@ fn main() -> Result<(), Box<dyn Error>> {
             let lday_index = dates.1.ordinal0() as usize;
 
             // Try inspect and get the given day
-            let ledger = project.ledger;
+            let tagged = tagged_ledger(&project, lopt.tag)?;
+            let ledger = tagged.as_ref().unwrap_or(&project.ledger);
             let res = (fday_index..lday_index)
                 .map(|day_index| {
                     ledger
@@ -112,48 +416,126 @@ fn main() -> Result<(), Box<dyn Error>> {
                 })
                 .collect::<Vec<HashMap<String, LedgerIndexItem>>>();
 
-            println!("\nLedger for date: {} - {}\n", dates.0, dates.1);
+            println!("\n{}\n", tr!("Ledger for date: {} - {}", dates.0, dates.1));
 
             println!(
                 "{0: <25}  {1: <13} | {2: <13} | {3: <13} | {4: <13}",
-                "Account ID", "T. Debit", "T. Credit", "B. Debit", "B. Credit"
+                tr!("Account ID"),
+                tr!("T. Debit"),
+                tr!("T. Credit"),
+                tr!("B. Debit"),
+                tr!("B. Credit")
             );
             println!(
               "{}",
               "-------------------------------------------------------------------------------------"
             );
-            // Print result
-            project.accounts.iter().for_each(|account| {
-                    let mut r = res[0]
-                        .get(&account.id)
-                        .expect("Cannot get first item")
-                        .to_owned();
-
+            // Sum the period for each account, then roll up sub-accounts
+            let period = res[0]
+                .keys()
+                .map(|id| {
+                    let mut r = res[0][id].to_owned();
                     for day_index in &res[1..] {
-                        r = r + day_index
-                            .get(&account.id)
-                            .expect("No data for account")
-                            .to_owned();
+                        r = r.followed_by(day_index.get(id).expect("No data for account"))?;
                     }
-
-                    println!(
-                        "{0: <5} {1: <20} {2: <13}",
-                        &account.id,
-                        account
-                            .name
-                            .chars()
-                            .into_iter()
-                            .take(15)
-                            .collect::<String>(),
-                        r.print_full()
-                    );
-                    println!(
+                    Ok((id.to_owned(), r))
+                })
+                .collect::<Result<HashMap<String, LedgerIndexItem>, Diagnostic>>()?;
+            let rollup = project.chart().rollup(&period)?;
+            // Print result
+            let max_depth = lopt.depth.unwrap_or(usize::MAX);
+            for (account, depth) in project
+                .chart()
+                .tree()
+                .iter()
+                .filter(|(_, depth)| *depth < max_depth)
+            {
+                println!(
+                    "{0: <12} {1: <13} {2: <13}",
+                    format!("{}{}", "  ".repeat(*depth), account.id),
+                    account.name.chars().take(13).collect::<String>(),
+                    rollup[&account.id].print_full(project.chart().account_type(&account.id))?
+                );
+                println!(
               "{}",
               "------------------------------------------------------------------------------------*"
             );
-                });
+            }
         }
     }
 
     Ok(())
 }
+
+fn print_note(note: &Note) {
+    let date = |date: Option<NaiveDate>| date.map(|d| d.to_string());
+    let money = |money: Option<Money>| money.map(|m| m.separate_with_spaces());
+    let fields = [
+        ("ID", note.id.clone()),
+        ("ALIAS", note.alias.clone()),
+        ("DOCID", note.docid.clone()),
+        ("TITLE", note.title.clone()),
+        ("DATE", date(note.date)),
+        ("SOURCE", note.source.clone()),
+        ("TYPE", note.note_type.clone()),
+        ("AUTHOR", note.author.clone()),
+        (
+            "PAYMENT_KIND",
+            note.payment_kind.as_ref().map(|p| p.to_string()),
+        ),
+        ("NET", money(note.net)),
+        ("VAT", money(note.vat)),
+        ("GROSS", money(note.gross)),
+        ("ISSUE_DATE", date(note.issue_date)),
+        ("COMPLETION_DATE", date(note.completion_date)),
+        ("DUEDATE", date(note.duedate)),
+    ];
+    println!("{}\n", note.path.display());
+    for (name, value) in fields {
+        if let Some(value) = value {
+            println!("{0: <16} {1}", name, value);
+        }
+    }
+    println!("\n{}", tr!("Transactions"));
+    for transaction in &note.transactions {
+        for leg in &transaction.legs {
+            println!(
+                "  {0: <4} {1: <6} {2: <25} {3: >15}",
+                transaction.id,
+                leg.side.to_string(),
+                leg.account,
+                leg.amount.separate_with_spaces()
+            );
+        }
+    }
+    println!("\n{}", tr!("Revisions"));
+    for revision in note.history.revisions() {
+        let state = match &revision.signature {
+            Some(signature) => format!(
+                "{} {} {}{}",
+                tr!("signed"),
+                signature.timestamp.as_deref().unwrap_or_default(),
+                signature.hash,
+                if signature.valid {
+                    String::new()
+                } else {
+                    format!(" ({})", tr!("invalid"))
+                }
+            ),
+            None => tr!("unsigned"),
+        };
+        println!("  {0: <4} {1}", revision.number, state);
+        for change in &revision.changes {
+            println!("         {}", change);
+        }
+    }
+}
+
+// Ledger of the transactions with the given tag, #tag and tag are the same
+fn tagged_ledger(project: &Project, tag: Option<String>) -> Result<Option<Ledger>, Box<dyn Error>> {
+    let tag = match tag {
+        Some(tag) => Tag::parse(&tag).unwrap_or(Tag::Tag(tag)),
+        None => return Ok(None),
+    };
+    Ok(Some(project.ledger.tagged(project.chart(), &tag)?))
+}
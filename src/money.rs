@@ -0,0 +1,411 @@
+use serde::Serialize;
+use std::{cmp::Ordering, fmt::Display};
+
+use crate::tr;
+
+/// Money parse and print settings
+///
+/// Precision is the number of minor unit digits of the currency,
+/// e.g. 2 for EUR (cents) and 0 for HUF.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MoneyFormat {
+    pub precision: u32,
+    pub decimal_separator: char,
+    pub thousand_separator: Option<char>,
+}
+
+impl Default for MoneyFormat {
+    fn default() -> Self {
+        Self {
+            precision: 0,
+            decimal_separator: '.',
+            thousand_separator: None,
+        }
+    }
+}
+
+impl MoneyFormat {
+    /// Default format for a currency code
+    ///
+    /// HUF amounts are written as 11.811 in Hungary, so . separates the
+    /// thousands there.
+    pub fn for_currency(currency: &str) -> Self {
+        let precision = currency_precision(currency);
+        match currency.to_uppercase().as_str() {
+            "HUF" => Self {
+                precision,
+                decimal_separator: ',',
+                thousand_separator: Some('.'),
+            },
+            _ => Self {
+                precision,
+                ..Default::default()
+            },
+        }
+    }
+}
+
+/// Largest precision, amounts are stored as i64 minor units
+pub const MAX_PRECISION: u32 = 18;
+
+/// Minor unit digits of a currency
+pub fn currency_precision(currency: &str) -> u32 {
+    match currency.to_uppercase().as_str() {
+        // Currencies booked without minor units
+        "HUF" | "JPY" | "KRW" | "ISK" | "CLP" | "VND" => 0,
+        "BHD" | "KWD" | "OMR" | "JOD" | "TND" => 3,
+        _ => 2,
+    }
+}
+
+/// Fixed-point money amount
+///
+/// Stored as minor units, e.g. 11.50 EUR is 1150 with precision 2.
+/// Amounts with different precision are rescaled to the higher one
+/// when combined.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Money {
+    units: i64,
+    precision: u32,
+}
+
+impl Money {
+    pub fn new(units: i64, precision: u32) -> Self {
+        Self { units, precision }
+    }
+    pub fn zero(precision: u32) -> Self {
+        Self::new(0, precision)
+    }
+    /// Minor units
+    pub fn units(&self) -> i64 {
+        self.units
+    }
+    pub fn precision(&self) -> u32 {
+        self.precision
+    }
+    pub fn is_zero(&self) -> bool {
+        self.units == 0
+    }
+    pub fn is_negative(&self) -> bool {
+        self.units < 0
+    }
+    pub fn abs(&self) -> Self {
+        Self::new(self.units.abs(), self.precision)
+    }
+    /// Same amount with more or equal minor unit digits
+    pub fn rescale(&self, precision: u32) -> Option<Self> {
+        if precision < self.precision {
+            return None;
+        }
+        let factor = 10i64.checked_pow(precision - self.precision)?;
+        Some(Self::new(self.units.checked_mul(factor)?, precision))
+    }
+    fn align(&self, rhs: &Self) -> Option<(i64, i64, u32)> {
+        let precision = self.precision.max(rhs.precision);
+        Some((
+            self.rescale(precision)?.units,
+            rhs.rescale(precision)?.units,
+            precision,
+        ))
+    }
+    pub fn checked_add(&self, rhs: &Self) -> Option<Self> {
+        let (a, b, precision) = self.align(rhs)?;
+        Some(Self::new(a.checked_add(b)?, precision))
+    }
+    pub fn checked_sub(&self, rhs: &Self) -> Option<Self> {
+        let (a, b, precision) = self.align(rhs)?;
+        Some(Self::new(a.checked_sub(b)?, precision))
+    }
+    pub fn checked_neg(&self) -> Option<Self> {
+        Some(Self::new(self.units.checked_neg()?, self.precision))
+    }
+    /// Multiply, result rounded half away from zero
+    pub fn checked_mul(&self, rhs: &Self) -> Option<Self> {
+        let (a, b, precision) = self.align(rhs)?;
+        let scale = 10i128.checked_pow(precision)?;
+        let res = round_div(a as i128 * b as i128, scale)?;
+        Some(Self::new(i64::try_from(res).ok()?, precision))
+    }
+    /// Divide, result rounded half away from zero
+    ///
+    /// Returns None when dividing by zero.
+    pub fn checked_div(&self, rhs: &Self) -> Option<Self> {
+        let (a, b, precision) = self.align(rhs)?;
+        let scale = 10i128.checked_pow(precision)?;
+        let res = round_div((a as i128).checked_mul(scale)?, b as i128)?;
+        Some(Self::new(i64::try_from(res).ok()?, precision))
+    }
+    /// Parse amount text
+    ///
+    /// Underscore is always allowed as thousand separator. The configured
+    /// thousand separator is allowed only before groups of three digits,
+    /// e.g. 1.000.000, so 1.5 is not read as 15.
+    pub fn parse(text: &str, format: &MoneyFormat) -> Result<Self, String> {
+        let (negative, text) = match text.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, text.strip_prefix('+').unwrap_or(text)),
+        };
+        let (integer_text, fraction_text) = text
+            .split_once(format.decimal_separator)
+            .unwrap_or((text, ""));
+        if let Some(separator) = format.thousand_separator {
+            let digits = |group: &str| group.chars().filter(|c| *c != '_').count();
+            let groups = integer_text.split(separator).collect::<Vec<&str>>();
+            if groups.len() > 1
+                && (!(1..=3).contains(&digits(groups[0]))
+                    || groups[1..].iter().any(|group| digits(group) != 3))
+            {
+                return Err(tr!(
+                    "Thousand separator {} must be followed by three digits",
+                    separator
+                ));
+            }
+        }
+        let mut integer = String::new();
+        let mut fraction = String::new();
+        for ch in integer_text.chars() {
+            match ch {
+                '_' => (),
+                x if Some(x) == format.thousand_separator => (),
+                x if x.is_ascii_digit() => integer.push(x),
+                _ => return Err(tr!("Invalid character '{}' in amount", ch)),
+            }
+        }
+        for ch in fraction_text.chars() {
+            match ch {
+                '_' => (),
+                x if x.is_ascii_digit() => fraction.push(x),
+                _ => return Err(tr!("Invalid character '{}' in amount", ch)),
+            }
+        }
+        if integer.is_empty() && fraction.is_empty() {
+            return Err(tr!("Amount must contain digits"));
+        }
+        if fraction.len() > format.precision as usize {
+            return Err(tr!(
+                "Too many decimal digits, maximum {} allowed",
+                format.precision
+            ));
+        }
+        // Pad fraction to precision
+        while fraction.len() < format.precision as usize {
+            fraction.push('0');
+        }
+        let units = format!("{}{}", integer, fraction)
+            .parse::<i64>()
+            .map_err(|_| tr!("Amount is too large"))?;
+        Ok(Self::new(
+            match negative {
+                true => -units,
+                false => units,
+            },
+            format.precision,
+        ))
+    }
+    /// Print amount with the given separators
+    pub fn to_string_with(
+        &self,
+        thousand_separator: Option<char>,
+        decimal_separator: char,
+    ) -> String {
+        let digits = self.units.unsigned_abs().to_string();
+        let precision = self.precision as usize;
+        let digits = format!("{:0>width$}", digits, width = precision + 1);
+        let (integer, fraction) = digits.split_at(digits.len() - precision);
+        let mut res = String::new();
+        if self.units < 0 {
+            res.push('-');
+        }
+        for (i, ch) in integer.chars().enumerate() {
+            if i > 0 && (integer.len() - i) % 3 == 0 {
+                if let Some(separator) = thousand_separator {
+                    res.push(separator);
+                }
+            }
+            res.push(ch);
+        }
+        if precision > 0 {
+            res.push(decimal_separator);
+            res.push_str(fraction);
+        }
+        res
+    }
+    pub fn separate_with_spaces(&self) -> String {
+        self.to_string_with(Some(' '), '.')
+    }
+}
+
+// Divide and round half away from zero
+fn round_div(a: i128, b: i128) -> Option<i128> {
+    if b == 0 {
+        return None;
+    }
+    let quotient = a / b;
+    let remainder = a % b;
+    match (remainder.abs() * 2).cmp(&b.abs()) {
+        Ordering::Less => Some(quotient),
+        _ => match (a < 0) == (b < 0) {
+            true => quotient.checked_add(1),
+            false => quotient.checked_sub(1),
+        },
+    }
+}
+
+impl PartialEq for Money {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Money {}
+
+impl PartialOrd for Money {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Money {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let precision = self.precision.max(other.precision);
+        let scale = |m: &Money| {
+            10i128
+                .checked_pow(precision - m.precision)
+                .and_then(|factor| (m.units as i128).checked_mul(factor))
+        };
+        match (scale(self), scale(other)) {
+            (Some(a), Some(b)) => a.cmp(&b),
+            // Only the amount with fewer digits can overflow, it is the
+            // larger one in magnitude
+            (None, _) => self.units.cmp(&0),
+            (_, None) => 0.cmp(&other.units),
+        }
+    }
+}
+
+// Amounts are serialized as exact decimal text, e.g. 1000.50
+impl Serialize for Money {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl Display for Money {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.to_string_with(None, '.'))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eur() -> MoneyFormat {
+        MoneyFormat::for_currency("EUR")
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            Money::parse("11_811", &eur()).unwrap(),
+            Money::new(1181100, 2)
+        );
+        assert_eq!(Money::parse("11.5", &eur()).unwrap(), Money::new(1150, 2));
+        assert_eq!(Money::parse("-0.05", &eur()).unwrap(), Money::new(-5, 2));
+        assert!(Money::parse("1.234", &eur()).is_err());
+        assert!(Money::parse("12x", &eur()).is_err());
+        assert!(Money::parse("", &eur()).is_err());
+    }
+
+    #[test]
+    fn test_parse_separators() {
+        let format = MoneyFormat {
+            precision: 0,
+            decimal_separator: ',',
+            thousand_separator: Some('.'),
+        };
+        assert_eq!(
+            Money::parse("12.177.079", &format).unwrap(),
+            Money::new(12177079, 0)
+        );
+        assert_eq!(
+            Money::parse("11.811", &MoneyFormat::for_currency("HUF")).unwrap(),
+            Money::new(11811, 0)
+        );
+        assert!(Money::parse("11.811", &MoneyFormat::for_currency("JPY")).is_err());
+    }
+
+    #[test]
+    fn test_parse_thousand_groups() {
+        let huf = MoneyFormat::for_currency("HUF");
+        assert_eq!(Money::parse("11.811", &huf).unwrap(), Money::new(11811, 0));
+        assert_eq!(
+            Money::parse("-1.000.000", &huf).unwrap(),
+            Money::new(-1000000, 0)
+        );
+        assert_eq!(Money::parse("1_000", &huf).unwrap(), Money::new(1000, 0));
+        for text in ["1.5", "1.2.3", "1234.567", ".811", "11.811.", "0.27"] {
+            assert!(Money::parse(text, &huf).is_err(), "{}", text);
+        }
+        let eur = MoneyFormat {
+            precision: 2,
+            decimal_separator: ',',
+            thousand_separator: Some('.'),
+        };
+        assert_eq!(
+            Money::parse("1_000,50", &eur).unwrap(),
+            Money::new(100050, 2)
+        );
+        assert_eq!(
+            Money::parse("1.000,5", &eur).unwrap(),
+            Money::new(100050, 2)
+        );
+        assert!(Money::parse("1,000.50", &eur).is_err());
+    }
+
+    #[test]
+    fn test_large_precision() {
+        let a = Money::new(1, 0);
+        let b = Money::new(1, 40);
+        assert!(a > b);
+        assert!(Money::new(-1, 0) < b);
+        assert_eq!(b.checked_mul(&b), None);
+        assert_eq!(b.checked_div(&b), None);
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        let a = Money::new(1000, 2);
+        let b = Money::new(3, 0);
+        assert_eq!(a.checked_add(&b).unwrap(), Money::new(1300, 2));
+        assert_eq!(b.checked_sub(&a).unwrap(), Money::new(-700, 2));
+        assert_eq!(Money::new(i64::MAX, 0).checked_add(&Money::new(1, 0)), None);
+        // 15 000 * 27 / 127 = 3188.97 -> 3189
+        let vat = Money::new(15000, 0)
+            .checked_mul(&Money::new(27, 0))
+            .unwrap()
+            .checked_div(&Money::new(127, 0))
+            .unwrap();
+        assert_eq!(vat, Money::new(3189, 0));
+        assert_eq!(
+            Money::new(-5, 0).checked_div(&Money::new(2, 0)).unwrap(),
+            Money::new(-3, 0)
+        );
+        assert_eq!(a.checked_div(&Money::zero(2)), None);
+    }
+
+    #[test]
+    fn test_print() {
+        assert_eq!(Money::new(-1234567, 2).to_string(), "-12345.67");
+        assert_eq!(Money::new(5, 2).to_string(), "0.05");
+        assert_eq!(
+            Money::new(11811, 0).to_string_with(Some('_'), '.'),
+            "11_811"
+        );
+        assert_eq!(Money::new(100000, 0).separate_with_spaces(), "100 000");
+        assert_eq!(
+            Money::new(-123456789, 2).separate_with_spaces(),
+            "-1 234 567.89"
+        );
+    }
+}
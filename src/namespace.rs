@@ -0,0 +1,288 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    diagnostic::{Code, Diagnostic},
+    note::Note,
+    parser::Command,
+    tr,
+};
+
+/// Sequence of the IDs in a namespace
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Sequence {
+    /// Increasing numbers, e.g. bank/0001
+    #[default]
+    Number,
+    /// Random UUIDs
+    Uuid,
+}
+
+/// Note ID namespace, a [namespaces.NAME] table of Bit.toml
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Namespace {
+    pub sequence: Sequence,
+    /// Minimum digits of the numbers, zero padded
+    pub width: usize,
+    /// Text before the number, {year} is the project year
+    pub prefix: String,
+}
+
+/// Note ID namespaces of a project
+///
+/// IDs are written as namespace/sequence, e.g. bank/0001. Without
+/// namespaces IDs are free texts.
+#[derive(Debug, Default, Clone)]
+pub struct Namespaces {
+    namespaces: BTreeMap<String, Namespace>,
+    year: i32,
+}
+
+impl Namespaces {
+    pub fn new(namespaces: BTreeMap<String, Namespace>, year: i32) -> Self {
+        Self { namespaces, year }
+    }
+    pub fn is_empty(&self) -> bool {
+        self.namespaces.is_empty()
+    }
+    /// Next free ID of a namespace, after the largest used number
+    pub fn next_id<'a>(
+        &self,
+        name: &str,
+        ids: impl Iterator<Item = &'a str>,
+    ) -> Result<String, Diagnostic> {
+        let namespace = self.namespaces.get(name).ok_or_else(|| {
+            Diagnostic::error(Code::Config, tr!("Unknown ID namespace {}", name))
+                .with_hint(self.names_hint())
+        })?;
+        let sequence = match namespace.sequence {
+            Sequence::Uuid => Uuid::new_v4().to_string(),
+            Sequence::Number => {
+                let last = ids
+                    .filter_map(|id| id.strip_prefix(name)?.strip_prefix('/'))
+                    .filter_map(|sequence| self.number(namespace, sequence))
+                    .max()
+                    .unwrap_or(0);
+                let next = last.checked_add(1).ok_or_else(|| {
+                    Diagnostic::error(Code::InvalidId, tr!("No more IDs in namespace {}", name))
+                })?;
+                self.format(namespace, next)
+            }
+        };
+        Ok(format!("{}/{}", name, sequence))
+    }
+    /// Check note IDs against their namespaces, and the numbered
+    /// namespaces for gaps and repeated numbers
+    pub fn check<'a>(&self, notes: impl Iterator<Item = &'a Note>) -> Vec<Diagnostic> {
+        let mut res = Vec::new();
+        if self.is_empty() {
+            return res;
+        }
+        let mut numbers: BTreeMap<&str, Vec<(u64, &Note)>> = BTreeMap::new();
+        for note in notes {
+            let id = note.id.as_deref().unwrap_or_default();
+            let error = |message: String| {
+                let diagnostic = Diagnostic::error(Code::InvalidId, message).with_path(&note.path);
+                match note.history.span(Command::Id) {
+                    Some(span) => diagnostic.with_span(span),
+                    None => diagnostic,
+                }
+            };
+            let Some((name, sequence)) = id.split_once('/') else {
+                res.push(error(tr!("ID {} has no namespace", id)).with_hint(self.names_hint()));
+                continue;
+            };
+            let Some(namespace) = self.namespaces.get(name) else {
+                res.push(error(tr!("Unknown ID namespace {}", name)).with_hint(self.names_hint()));
+                continue;
+            };
+            let valid = match namespace.sequence {
+                Sequence::Uuid => Uuid::parse_str(sequence).is_ok(),
+                // The largest number leaves no room for the next ID
+                Sequence::Number => match self.number(namespace, sequence) {
+                    Some(u64::MAX) => {
+                        res.push(error(tr!("ID {} is out of range", id)));
+                        continue;
+                    }
+                    Some(number) => {
+                        numbers.entry(name).or_default().push((number, note));
+                        true
+                    }
+                    None => false,
+                },
+            };
+            if !valid {
+                res.push(
+                    error(tr!("ID {} does not match namespace {}", id, name)).with_hint(tr!(
+                        "IDs of {} look like {}",
+                        name,
+                        self.example(name)
+                    )),
+                );
+            }
+        }
+        for (name, mut numbers) in numbers {
+            numbers.sort_by_key(|(number, _)| *number);
+            let mut last = 0;
+            for (number, note) in numbers {
+                let id = note.id.as_deref().unwrap_or_default();
+                let warning = |message: String| {
+                    let diagnostic =
+                        Diagnostic::warning(Code::InvalidId, message).with_path(&note.path);
+                    match note.history.span(Command::Id) {
+                        Some(span) => diagnostic.with_span(span),
+                        None => diagnostic,
+                    }
+                };
+                if number == last {
+                    res.push(warning(tr!(
+                        "ID {} repeats number {} of {}",
+                        id,
+                        number,
+                        name
+                    )));
+                } else if number > last.saturating_add(1) {
+                    let namespace = &self.namespaces[name];
+                    let mut missing = format!("{}/{}", name, self.format(namespace, last + 1));
+                    if number > last.saturating_add(2) {
+                        let to = self.format(namespace, number - 1);
+                        missing = format!("{} - {}/{}", missing, name, to);
+                    }
+                    res.push(
+                        warning(tr!("IDs are missing before {}", id))
+                            .with_hint(tr!("Missing: {}", missing)),
+                    );
+                }
+                last = number;
+            }
+        }
+        res
+    }
+    // Number of a sequence, None if it does not match the namespace
+    fn number(&self, namespace: &Namespace, sequence: &str) -> Option<u64> {
+        let digits = sequence.strip_prefix(self.prefix(namespace).as_str())?;
+        if digits.len() < namespace.width.max(1) || !digits.chars().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+        digits.parse().ok()
+    }
+    fn format(&self, namespace: &Namespace, number: u64) -> String {
+        format!(
+            "{}{:0width$}",
+            self.prefix(namespace),
+            number,
+            width = namespace.width
+        )
+    }
+    fn prefix(&self, namespace: &Namespace) -> String {
+        namespace.prefix.replace("{year}", &self.year.to_string())
+    }
+    // First ID of a namespace
+    fn example(&self, name: &str) -> String {
+        match self.namespaces[name].sequence {
+            Sequence::Uuid => format!("{}/{}", name, Uuid::nil()),
+            Sequence::Number => format!("{}/{}", name, self.format(&self.namespaces[name], 1)),
+        }
+    }
+    fn names_hint(&self) -> String {
+        let names = self
+            .namespaces
+            .keys()
+            .map(|name| self.example(name))
+            .collect::<Vec<String>>();
+        tr!("Namespaces: {}", names.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::note::NoteContext;
+    use std::path::PathBuf;
+
+    fn namespaces() -> Namespaces {
+        let bank = Namespace {
+            width: 4,
+            prefix: "{year}-".to_string(),
+            ..Default::default()
+        };
+        let partner = Namespace {
+            sequence: Sequence::Uuid,
+            ..Default::default()
+        };
+        let namespaces = [("bank", bank), ("partner", partner)]
+            .into_iter()
+            .map(|(name, namespace)| (name.to_string(), namespace))
+            .collect();
+        Namespaces::new(namespaces, 2022)
+    }
+
+    fn note(id: &str) -> Note {
+        let raw = crate::parser::parse(&format!("ID {}\n", id));
+        let mut note = Note::from_raw_note(raw, false, &NoteContext::default()).unwrap();
+        note.path = PathBuf::from(format!("{}.bit", id));
+        note
+    }
+
+    #[test]
+    fn test_next_id() {
+        let namespaces = namespaces();
+        let ids = ["bank/2022-0001", "bank/2022-0007", "custom/9", "bank/x"];
+        assert_eq!(
+            namespaces.next_id("bank", ids.into_iter()).unwrap(),
+            "bank/2022-0008"
+        );
+        let id = namespaces.next_id("partner", ids.into_iter()).unwrap();
+        assert!(Uuid::parse_str(id.strip_prefix("partner/").unwrap()).is_ok());
+        assert!(namespaces.next_id("custom", ids.into_iter()).is_err());
+    }
+
+    #[test]
+    fn test_check() {
+        let notes = [
+            "bank/2022-0001",
+            "bank/2022-0004",
+            "bank/2022-00001",
+            "bank/2021-0002",
+            "custom/1",
+            "1",
+            "partner/67e55044-10b1-426f-9247-bb680e5fe0c8",
+        ]
+        .map(note);
+        let res = namespaces().check(notes.iter());
+        let messages = res.iter().map(|d| d.message()).collect::<Vec<&str>>();
+        assert_eq!(
+            messages,
+            [
+                "ID bank/2021-0002 does not match namespace bank",
+                "Unknown ID namespace custom",
+                "ID 1 has no namespace",
+                "ID bank/2022-00001 repeats number 1 of bank",
+                "IDs are missing before bank/2022-0004",
+            ]
+        );
+        assert_eq!(res[0].hints(), ["IDs of bank look like bank/2022-0001"]);
+        assert_eq!(res[4].hints(), ["Missing: bank/2022-0002 - bank/2022-0003"]);
+        assert!(!res[4].is_error());
+
+        let notes = [
+            "bank/2022-18446744073709551615",
+            "bank/2022-18446744073709551614",
+        ]
+        .map(note);
+        let res = namespaces().check(notes.iter());
+        assert_eq!(
+            res[0].message(),
+            "ID bank/2022-18446744073709551615 is out of range"
+        );
+        assert!(res[0].is_error());
+        assert_eq!(res[0].code(), Code::InvalidId);
+        let ids = ["bank/2022-18446744073709551615"];
+        let err = namespaces().next_id("bank", ids.into_iter()).unwrap_err();
+        assert_eq!(err.code(), Code::InvalidId);
+    }
+}
@@ -4,30 +4,54 @@ use std::{
 };
 
 use crate::{
-    ledger::Account,
-    parser::{Command, NoteRaw, Token, TokenKind},
+    diagnostic::{Code, Diagnostic, Diagnostics, Span},
+    expr::{Expr, Field},
+    ledger::{Account, AccountType},
+    money::{Money, MoneyFormat},
+    parser::{Command, Line, NoteRaw, Token, TokenKind},
+    revision::{History, Signature},
+    template::{Template, Templates},
+    tr,
+    validate::Validation,
 };
 use chrono::{Local, NaiveDate};
-use serde::Deserialize;
-use serde_cbor::error;
+use serde::Serialize;
 use std::io::Write;
 
-#[derive(Default, Debug)]
+#[derive(Default, Debug, Serialize)]
 pub struct Note {
     pub path: PathBuf,
+    pub title: Option<String>,
+    /// Note creation date
+    pub date: Option<NaiveDate>,
+    /// Referenced source document
+    pub source: Option<String>,
+    pub note_type: Option<String>,
     pub id: Option<String>,
     pub alias: Option<String>,
     pub docid: Option<String>,
     pub author: Option<String>,
     pub payment_kind: Option<PaymentKind>,
-    pub net: Option<i64>,
-    pub vat: Option<i64>,
-    pub gross: Option<i64>,
+    pub net: Option<Money>,
+    pub vat: Option<Money>,
+    pub gross: Option<Money>,
     pub issue_date: Option<NaiveDate>,
     pub completion_date: Option<NaiveDate>,
     pub duedate: Option<NaiveDate>,
+    pub references: Vec<Reference>,
+    pub events: Vec<Event>,
     pub transactions: Vec<Transaction>,
+    /// Tags and dimensions of all the note transactions
+    pub tags: Vec<Tag>,
     pub accounts: Vec<Account>,
+    /// Included account files
+    pub includes: Vec<Include>,
+    /// Templates defined in an account file
+    pub templates: Vec<Template>,
+    /// Signed revisions of the note
+    pub history: History,
+    /// Warnings of a note without errors
+    pub warnings: Vec<Diagnostic>,
 }
 
 impl Note {
@@ -36,78 +60,328 @@ impl Note {
         res.path = path;
         res
     }
-    pub fn from_file(path: &Path, is_account_file: bool) -> Result<Self, String> {
+    pub fn from_file(
+        path: &Path,
+        is_account_file: bool,
+        ctx: &NoteContext,
+    ) -> Result<Self, Diagnostics> {
         let raw = NoteRaw::from_file(path)?;
-        Self::from_raw_note(raw, is_account_file)
+        Self::from_raw_note(raw, is_account_file, ctx)
     }
-    pub fn from_raw_note(raw: NoteRaw, is_account_file: bool) -> Result<Self, String> {
-        let mut note = Note::default();
-        note.path = raw.file_path().to_owned();
+    /// Build note from raw note
+    ///
+    /// Processes every line and returns all the errors found.
+    pub fn from_raw_note(
+        raw: NoteRaw,
+        is_account_file: bool,
+        ctx: &NoteContext,
+    ) -> Result<Self, Diagnostics> {
+        let mut note = Note {
+            path: raw.file_path().to_owned(),
+            ..Default::default()
+        };
+        let mut diagnostics = raw.diagnostics();
+        // Signatures are checked against the raw lines
+        let raw_lines = raw
+            .lines_ref()
+            .iter()
+            .map(|line| line.raw().to_string())
+            .collect::<Vec<String>>();
+        // Opening --- token and title lines of the title block
+        let mut title_block: Option<(Token, Vec<String>)> = None;
+        // Open multi-line statement
+        let mut statement: Option<Statement> = None;
         for line in raw.lines() {
-            // Skip empty lines
-            if line.tokens_ref().is_empty() {
+            let is_delimiter = matches!(
+                line.tokens_ref().first().map(|t| t.token_kind_ref()),
+                Some(TokenKind::Command(Command::TitleBlock))
+            );
+            // Collect title lines till the closing ---
+            if let Some((_, title_lines)) = &mut title_block {
+                match is_delimiter {
+                    true => {
+                        let title = title_lines.join(" ");
+                        let span = title_block.take().unwrap().0.span();
+                        note.history.record(Command::Title, title.clone(), span);
+                        note.title = Some(title);
+                    }
+                    false => title_lines.extend(title_text(&line)),
+                }
+                continue;
+            }
+            // Skip empty lines and lines with tokenizer error
+            if line.tokens_ref().is_empty() || line.error().is_some() {
                 continue;
             }
+            // Continue statement while lines start with its keys
+            if let Some(open) = &mut statement {
+                let params = line.param_tokens();
+                if is_key(&params[0], open.keys) || params[0].is_semicolon() {
+                    open.lines += 1;
+                    open.params.extend(params);
+                    if open.is_terminated() {
+                        let res =
+                            note.apply_statement(statement.take().unwrap(), is_account_file, ctx);
+                        if let Err(e) = res {
+                            diagnostics.push(e.with_path(&note.path));
+                        }
+                    }
+                    continue;
+                }
+                let res = note.apply_statement(statement.take().unwrap(), is_account_file, ctx);
+                if let Err(e) = res {
+                    diagnostics.push(e.with_path(&note.path));
+                }
+            }
             let mut tokens = line.tokens();
             let first_token = tokens.remove(0);
-            match first_token.token_kind_ref() {
-                TokenKind::Command(command) => match command {
-                    Command::Alias => note.set_alias(first_token, tokens)?,
-                    Command::Id => note.set_id(first_token, tokens)?,
-                    Command::Docid => note.set_docid(first_token, tokens)?,
-                    Command::Author => note.set_author(first_token, tokens)?,
-                    Command::PaymentKind => note.set_payment_kind(first_token, tokens)?,
-                    Command::Net => note.set_net(first_token, tokens)?,
-                    Command::Gross => note.set_gross(first_token, tokens)?,
-                    Command::Vat => note.set_vat(first_token, tokens)?,
-                    Command::IssueDate => note.set_issuedate(first_token, tokens)?,
-                    Command::CompletionDate => note.set_completiondate(first_token, tokens)?,
-                    Command::DueDate => note.set_duedate(first_token, tokens)?,
-                    Command::Transaction => note.set_transaction(first_token, tokens)?,
-                    Command::Signature => (),
-                    Command::Account => match is_account_file {
-                        true => note.set_account(first_token, tokens)?,
-                        false => {
-                            return Err(error_msg(
-                                &first_token,
-                                "Account cannot be defined in a note file",
-                            ))
+            if is_delimiter {
+                if let Some(token) = tokens.first() {
+                    diagnostics.push(
+                        param_count_error(token, tr!("--- must be alone in its line"))
+                            .with_path(&note.path),
+                    );
+                }
+                title_block = Some((first_token, Vec::new()));
+                continue;
+            }
+            let open = Statement::new(first_token, tokens);
+            // Named statements can continue in the next lines
+            if open.is_named() && !open.is_terminated() {
+                statement = Some(open);
+                continue;
+            }
+            if let Err(e) = note.apply_statement(open, is_account_file, ctx) {
+                diagnostics.push(e.with_path(&note.path));
+            }
+        }
+        if let Some(open) = statement {
+            if let Err(e) = note.apply_statement(open, is_account_file, ctx) {
+                diagnostics.push(e.with_path(&note.path));
+            }
+        }
+        if let Some((token, _)) = title_block {
+            diagnostics.push(
+                error_msg(
+                    &token,
+                    Code::UnclosedTitleBlock,
+                    tr!("Title block is not closed"),
+                )
+                .with_path(&note.path)
+                .with_hint(tr!("Close the title with a --- line")),
+            );
+        }
+        note.history.close();
+        let lines = raw_lines.iter().map(String::as_str).collect::<Vec<&str>>();
+        for e in note.history.check(&lines, ctx.validation.signature) {
+            diagnostics.push(e.with_path(&note.path));
+        }
+        // Evaluate transaction amounts, they can refer to
+        // fields defined anywhere in the note
+        for transaction in &mut note.transactions {
+            let template = transaction.template.clone();
+            let with_template = |e: Diagnostic| match &template {
+                Some(template) => e.with_hint(template.clone()),
+                None => e,
+            };
+            let mut error_spans = Vec::new();
+            for leg in &mut transaction.legs {
+                if let Some(expr) = &leg.amount_expr {
+                    match expr.eval(&|field| match field {
+                        Field::Net => note.net,
+                        Field::Vat => note.vat,
+                        Field::Gross => note.gross,
+                    }) {
+                        Ok(amount) => leg.amount = amount,
+                        // Simple transaction legs share their expression,
+                        // report its errors once
+                        Err(e) if error_spans.contains(&e.span()) => (),
+                        Err(e) => {
+                            error_spans.push(e.span());
+                            diagnostics.push(with_template(e).with_path(&note.path));
                         }
-                    },
-                    Command::Unknown => return Err(error_msg(&first_token, "Unknown command")),
-                },
-                TokenKind::Text(_) => (), // Skip text lines
+                    }
+                }
+            }
+            let evaluated = error_spans.is_empty();
+            if evaluated {
+                if let Err(e) = transaction.check_balance() {
+                    diagnostics.push(with_template(e).with_path(&note.path));
+                }
+            }
+        }
+        if let Err(e) = note.check(is_account_file) {
+            diagnostics.push(e);
+        }
+        for e in note.check_events() {
+            diagnostics.push(e.with_path(&note.path));
+        }
+        // Amounts are compared only when all of them are valid
+        if !is_account_file && !diagnostics.has_errors() {
+            for e in ctx.validation.check(&note) {
+                diagnostics.push(e.with_path(&note.path));
+            }
+        }
+        match diagnostics.has_errors() {
+            true => Err(diagnostics),
+            false => {
+                note.warnings = diagnostics.into_iter().collect();
+                Ok(note)
             }
         }
-        note.check(is_account_file)?;
-        Ok(note)
     }
-    fn set_alias(&mut self, first_token: Token, mut params: Vec<Token>) -> Result<(), String> {
+    // Check statement terminator and apply its command
+    fn apply_statement(
+        &mut self,
+        statement: Statement,
+        is_account_file: bool,
+        ctx: &NoteContext,
+    ) -> Result<(), Diagnostic> {
+        let Statement {
+            first_token,
+            mut params,
+            lines,
+            ..
+        } = statement;
+        if first_token.is_semicolon() {
+            return Err(error_msg(
+                &first_token,
+                Code::UnexpectedToken,
+                tr!("Unexpected ; without statement"),
+            ));
+        }
+        let terminated = params.last().is_some_and(|t| t.is_semicolon());
+        if terminated {
+            params.pop();
+        } else if lines > 1 {
+            return Err(error_msg(
+                &first_token,
+                Code::UnterminatedStatement,
+                tr!("Multi-line statement is not terminated"),
+            )
+            .with_hint(tr!("Close the statement with ;")));
+        }
+        if let Some(token) = params.iter().find(|t| t.is_semicolon()) {
+            return Err(error_msg(
+                token,
+                Code::UnexpectedToken,
+                tr!("Unexpected ; inside statement"),
+            )
+            .with_hint(tr!("Only one statement is allowed per ;")));
+        }
+        let change = match first_token.token_kind_ref() {
+            TokenKind::Command(Command::Signature) => None,
+            TokenKind::Command(command) => Some((*command, tokens_text(&params))),
+            _ => None,
+        };
+        let span = first_token.span();
+        self.apply_command(first_token, params, is_account_file, ctx)?;
+        if let Some((command, text)) = change {
+            self.history.record(command, text, span);
+        }
+        Ok(())
+    }
+    fn apply_command(
+        &mut self,
+        first_token: Token,
+        tokens: Vec<Token>,
+        is_account_file: bool,
+        ctx: &NoteContext,
+    ) -> Result<(), Diagnostic> {
+        match first_token.token_kind_ref() {
+            TokenKind::Command(command) => match command {
+                Command::Alias => self.set_alias(first_token, tokens),
+                Command::Id => self.set_id(first_token, tokens),
+                Command::Docid => self.set_docid(first_token, tokens),
+                Command::Author => self.set_author(first_token, tokens),
+                Command::PaymentKind => self.set_payment_kind(first_token, tokens),
+                Command::Net => self.set_net(first_token, tokens, ctx),
+                Command::Gross => self.set_gross(first_token, tokens, ctx),
+                Command::Vat => self.set_vat(first_token, tokens, ctx),
+                Command::IssueDate => self.set_issuedate(first_token, tokens),
+                Command::CompletionDate => self.set_completiondate(first_token, tokens),
+                Command::DueDate => self.set_duedate(first_token, tokens),
+                Command::Transaction => self.set_transaction(first_token, tokens, ctx),
+                Command::Reference => self.set_reference(first_token, tokens),
+                Command::Event => self.set_event(first_token, tokens),
+                Command::Signature => self.set_signature(first_token, tokens),
+                Command::Title => self.set_title(first_token, tokens),
+                Command::Date => self.set_date(first_token, tokens),
+                Command::Source => self.set_source(first_token, tokens),
+                Command::Type => self.set_type(first_token, tokens),
+                Command::Tag => self.set_tags(first_token, tokens),
+                // Handled by from_raw_note
+                Command::TitleBlock => Ok(()),
+                Command::Use => self.set_use(first_token, tokens, ctx),
+                Command::Template => match is_account_file {
+                    true => self.set_template(first_token, tokens),
+                    false => Err(error_msg(
+                        &first_token,
+                        Code::AccountOutsideAccountFile,
+                        tr!("TEMPLATE can be defined only in account files"),
+                    )),
+                },
+                Command::Include => match is_account_file {
+                    true => self.set_include(first_token, tokens),
+                    false => Err(error_msg(
+                        &first_token,
+                        Code::AccountOutsideAccountFile,
+                        tr!("INCLUDE can be used only in account files"),
+                    )),
+                },
+                Command::Account => match is_account_file {
+                    true => self.set_account(first_token, tokens),
+                    false => Err(error_msg(
+                        &first_token,
+                        Code::AccountOutsideAccountFile,
+                        tr!("Account cannot be defined in a note file"),
+                    )),
+                },
+                Command::Unknown => Err(error_msg(
+                    &first_token,
+                    Code::UnknownCommand,
+                    tr!("Unknown command"),
+                )),
+            },
+            TokenKind::Text(_) => Ok(()), // Skip text lines
+            TokenKind::Semicolon => Ok(()),
+        }
+    }
+    fn set_alias(&mut self, first_token: Token, mut params: Vec<Token>) -> Result<(), Diagnostic> {
         if params.len() != 1 {
-            return Err(error_msg(&first_token, "Alias must have one parameter"));
+            return Err(param_count_error(
+                &first_token,
+                tr!("Alias must have one parameter"),
+            ));
         }
         self.alias = params.remove(0).token_kind().take_text_string();
         Ok(())
     }
-    fn set_id(&mut self, first_token: Token, mut params: Vec<Token>) -> Result<(), String> {
+    fn set_id(&mut self, first_token: Token, mut params: Vec<Token>) -> Result<(), Diagnostic> {
         if params.len() != 1 {
-            return Err(error_msg(&first_token, "ID must have one parameter"));
+            return Err(param_count_error(
+                &first_token,
+                tr!("ID must have one parameter"),
+            ));
         }
         self.id = params.remove(0).token_kind().take_text_string();
         Ok(())
     }
-    fn set_docid(&mut self, first_token: Token, mut params: Vec<Token>) -> Result<(), String> {
+    fn set_docid(&mut self, first_token: Token, mut params: Vec<Token>) -> Result<(), Diagnostic> {
         if params.len() != 1 {
-            return Err(error_msg(&first_token, "DOCID must have one parameter"));
+            return Err(param_count_error(
+                &first_token,
+                tr!("DOCID must have one parameter"),
+            ));
         }
         self.docid = params.remove(0).token_kind().take_text_string();
         Ok(())
     }
-    fn set_author(&mut self, first_token: Token, mut params: Vec<Token>) -> Result<(), String> {
-        if params.len() < 1 {
-            return Err(error_msg(
+    fn set_author(&mut self, first_token: Token, params: Vec<Token>) -> Result<(), Diagnostic> {
+        if params.is_empty() {
+            return Err(param_count_error(
                 &first_token,
-                "AUTHOR must have at least one parameter",
+                tr!("AUTHOR must have at least one parameter"),
             ));
         }
         let value = params
@@ -118,286 +392,964 @@ impl Note {
         self.author = Some(value);
         Ok(())
     }
-    fn set_net(&mut self, first_token: Token, mut params: Vec<Token>) -> Result<(), String> {
-        if params.len() != 1 {
-            return Err(error_msg(&first_token, "NET must have one parameter"));
+    fn set_title(&mut self, first_token: Token, params: Vec<Token>) -> Result<(), Diagnostic> {
+        if params.is_empty() {
+            return Err(param_count_error(
+                &first_token,
+                tr!("TITLE must have at least one parameter"),
+            ));
         }
-        self.net = match params.remove(0).token_kind().take_text_string() {
-            Some(text) => match text.replace("_", "").parse::<i64>() {
-                Ok(res) => Some(res),
-                Err(_) => return Err(error_msg(&first_token, "NET must be integer number")),
-            },
-            None => return Err(error_msg(&first_token, "NET must have one parameter")),
-        };
+        let value = params
+            .into_iter()
+            .map(|i| i.token_kind().take_text_string().unwrap())
+            .collect::<Vec<String>>()
+            .join(" ");
+        self.title = Some(value);
         Ok(())
     }
-    fn set_gross(&mut self, first_token: Token, mut params: Vec<Token>) -> Result<(), String> {
+    fn set_date(&mut self, first_token: Token, params: Vec<Token>) -> Result<(), Diagnostic> {
+        self.date = Some(parse_single_date(&first_token, params, "DATE")?);
+        Ok(())
+    }
+    fn set_source(&mut self, first_token: Token, mut params: Vec<Token>) -> Result<(), Diagnostic> {
         if params.len() != 1 {
-            return Err(error_msg(&first_token, "GROSS must have one parameter"));
+            return Err(param_count_error(
+                &first_token,
+                tr!("SOURCE must have one parameter"),
+            ));
         }
-        self.gross = match params.remove(0).token_kind().take_text_string() {
-            Some(text) => match text.replace("_", "").parse::<i64>() {
-                Ok(res) => Some(res),
-                Err(_) => return Err(error_msg(&first_token, "GROSS must be integer number")),
-            },
-            None => return Err(error_msg(&first_token, "GROSS must have one parameter")),
-        };
+        self.source = params.remove(0).token_kind().take_text_string();
         Ok(())
     }
-    fn set_vat(&mut self, first_token: Token, mut params: Vec<Token>) -> Result<(), String> {
+    fn set_type(&mut self, first_token: Token, mut params: Vec<Token>) -> Result<(), Diagnostic> {
         if params.len() != 1 {
-            return Err(error_msg(&first_token, "VAT must have one parameter"));
+            return Err(param_count_error(
+                &first_token,
+                tr!("TYPE must have one parameter"),
+            ));
         }
-        self.vat = match params.remove(0).token_kind().take_text_string() {
-            Some(text) => match text.replace("_", "").parse::<i64>() {
-                Ok(res) => Some(res),
-                Err(_) => return Err(error_msg(&first_token, "VAT must be integer number")),
-            },
-            None => return Err(error_msg(&first_token, "VAT must have one parameter")),
-        };
+        self.note_type = params.remove(0).token_kind().take_text_string();
+        Ok(())
+    }
+    fn set_tags(&mut self, first_token: Token, mut params: Vec<Token>) -> Result<(), Diagnostic> {
+        let tags = take_tags(&mut params)?;
+        if let Some(token) = params.first() {
+            return Err(error_msg(
+                token,
+                Code::InvalidTag,
+                tr!("TAG accepts only #tag and key:value parameters"),
+            ));
+        }
+        if tags.is_empty() {
+            return Err(param_count_error(
+                &first_token,
+                tr!("TAG must have minimum 1 parameter"),
+            ));
+        }
+        self.tags.extend(tags);
+        Ok(())
+    }
+    fn set_net(
+        &mut self,
+        first_token: Token,
+        params: Vec<Token>,
+        ctx: &NoteContext,
+    ) -> Result<(), Diagnostic> {
+        self.net = Some(parse_single_amount(self, &first_token, params, "NET", ctx)?);
+        Ok(())
+    }
+    fn set_gross(
+        &mut self,
+        first_token: Token,
+        params: Vec<Token>,
+        ctx: &NoteContext,
+    ) -> Result<(), Diagnostic> {
+        self.gross = Some(parse_single_amount(
+            self,
+            &first_token,
+            params,
+            "GROSS",
+            ctx,
+        )?);
+        Ok(())
+    }
+    fn set_vat(
+        &mut self,
+        first_token: Token,
+        params: Vec<Token>,
+        ctx: &NoteContext,
+    ) -> Result<(), Diagnostic> {
+        self.vat = Some(parse_single_amount(self, &first_token, params, "VAT", ctx)?);
         Ok(())
     }
     fn set_payment_kind(
         &mut self,
         first_token: Token,
         mut params: Vec<Token>,
-    ) -> Result<(), String> {
+    ) -> Result<(), Diagnostic> {
         if params.len() != 1 {
-            return Err(error_msg(&first_token, "AUTHOR must have one parameter"));
+            return Err(param_count_error(
+                &first_token,
+                tr!("PAYMENT_KIND must have one parameter"),
+            ));
         }
-        self.payment_kind = match params.remove(0).token_kind().take_text_string() {
+        let param = params.remove(0);
+        self.payment_kind = match param.token_kind_ref().take_text_string_ref() {
             Some(text) => match text.as_str() {
                 "cash" => Some(PaymentKind::Cash),
                 "card" => Some(PaymentKind::Card),
                 "transfer" => Some(PaymentKind::Transfer),
                 _ => {
                     return Err(error_msg(
-                        &first_token,
-                        "PAYMENT_KIND value error. Must be: card, cash or transfer",
+                        &param,
+                        Code::InvalidPaymentKind,
+                        tr!("PAYMENT_KIND value error. Must be: card, cash or transfer"),
                     ))
                 }
             },
             None => {
-                return Err(error_msg(
+                return Err(param_count_error(
                     &first_token,
-                    "PAYMENT_KIND must have one parameter",
+                    tr!("PAYMENT_KIND must have one parameter"),
                 ))
             }
         };
         Ok(())
     }
-    fn set_issuedate(&mut self, first_token: Token, mut params: Vec<Token>) -> Result<(), String> {
-        if params.len() != 1 {
-            return Err(error_msg(&first_token, "ISSUEDATE must have one parameter"));
-        }
-        let date_string = params.remove(0).token_kind().take_text_string().unwrap();
-        let date = match NaiveDate::parse_from_str(&date_string, "%Y-%m-%d") {
-            Ok(date) => date,
-            Err(_) => {
-                return Err(error_msg(
-                    &first_token,
-                    "Date must have valid ISO format. YYYY-mm-dd",
-                ))
-            }
-        };
-        self.issue_date = Some(date);
+    fn set_issuedate(&mut self, first_token: Token, params: Vec<Token>) -> Result<(), Diagnostic> {
+        self.issue_date = Some(parse_single_date(&first_token, params, "ISSUEDATE")?);
         Ok(())
     }
     fn set_completiondate(
+        &mut self,
+        first_token: Token,
+        params: Vec<Token>,
+    ) -> Result<(), Diagnostic> {
+        self.completion_date = Some(parse_single_date(&first_token, params, "COMPLETIONDATE")?);
+        Ok(())
+    }
+    fn set_duedate(&mut self, first_token: Token, params: Vec<Token>) -> Result<(), Diagnostic> {
+        self.duedate = Some(parse_single_date(&first_token, params, "DUEDATE")?);
+        Ok(())
+    }
+    fn set_transaction(
         &mut self,
         first_token: Token,
         mut params: Vec<Token>,
-    ) -> Result<(), String> {
-        if params.len() != 1 {
-            return Err(error_msg(
+        ctx: &NoteContext,
+    ) -> Result<(), Diagnostic> {
+        // Tags can be anywhere, e.g. > T5 K454 1_000 #fuel project:alpha
+        let tags = take_tags(&mut params)?;
+        // Named form, e.g. TRANSACTION DEBIT 161 CREDIT 38 AMOUNT 1_000;
+        if params.first().is_some_and(|t| is_key(t, TRANSACTION_KEYS)) {
+            return self.set_named_transaction(first_token, params, tags, ctx);
+        }
+        if params.len() < 3 {
+            return Err(param_count_error(
                 &first_token,
-                "COMPLETIONDATE must have one parameter",
-            ));
+                tr!("TRANSACTION must have minimum 3 parameters. Debit, Credit, Amount"),
+            )
+            .with_hint(tr!(
+                "Or use named form: TRANSACTION DEBIT .. CREDIT .. AMOUNT ..;"
+            )));
         }
-        let date_string = params.remove(0).token_kind().take_text_string().unwrap();
-        let date = match NaiveDate::parse_from_str(&date_string, "%Y-%m-%d") {
-            Ok(date) => date,
-            Err(_) => {
-                return Err(error_msg(
-                    &first_token,
-                    "Date must have valid ISO format. YYYY-mm-dd",
-                ))
+        // Optional trailing EVENT_ID and CDATE keys after the amount
+        let tail_pos = params
+            .iter()
+            .skip(3)
+            .position(|t| is_key(t, TRANSACTION_TAIL_KEYS))
+            .map(|i| i + 3)
+            .unwrap_or(params.len());
+        let (params, tail) = params.split_at(tail_pos);
+        let mut kv = KeyValues::parse(
+            first_token.clone(),
+            tail.to_vec(),
+            "TRANSACTION",
+            TRANSACTION_TAIL_KEYS,
+        )?;
+        let event = kv.single("EVENT_ID")?;
+        let mut completion_date = kv.date("CDATE")?;
+        // Positional completion date after a single token amount
+        let mut amount = &params[2..];
+        if let [value, date] = amount {
+            if completion_date.is_none() && looks_like_date(date) {
+                completion_date = Some(parse_date(date)?);
+                amount = std::slice::from_ref(value);
+            }
+        }
+        let legs = simple_legs(&params[0], &params[1], amount, ctx)?;
+        self.add_transaction(first_token, legs, event.as_ref(), completion_date, tags)
+    }
+    // Named transaction, either simple with AMOUNT or split into legs,
+    // e.g. DEBIT 161 10_000 DEBIT 466 2_700 CREDIT 454 12_700;
+    fn set_named_transaction(
+        &mut self,
+        first_token: Token,
+        params: Vec<Token>,
+        tags: Vec<Tag>,
+        ctx: &NoteContext,
+    ) -> Result<(), Diagnostic> {
+        let mut kv = KeyValues::parse_pairs(
+            first_token.clone(),
+            params,
+            "TRANSACTION",
+            TRANSACTION_KEYS,
+            &["DEBIT", "CREDIT"],
+        )?;
+        let event = kv.single("EVENT_ID")?;
+        let completion_date = kv.date("CDATE")?;
+        let legs = match kv.take("AMOUNT") {
+            Some((_, amount)) => {
+                kv.check_single("DEBIT")?;
+                kv.check_single("CREDIT")?;
+                let debit = kv.required("DEBIT")?;
+                let credit = kv.required("CREDIT")?;
+                simple_legs(&debit, &credit, &amount, ctx)?
+            }
+            // Simple form without AMOUNT
+            None if kv.pairs.iter().all(|(_, value)| value.len() == 1) => {
+                kv.check_single("DEBIT")?;
+                kv.check_single("CREDIT")?;
+                return Err(kv.missing("AMOUNT"));
+            }
+            None => {
+                let mut legs = Vec::new();
+                for (key, value) in kv.take_all(&["DEBIT", "CREDIT"]) {
+                    let side = match text_of(&key).as_str() {
+                        "DEBIT" => Side::Debit,
+                        _ => Side::Credit,
+                    };
+                    if value.len() < 2 {
+                        return Err(param_count_error(
+                            &key,
+                            tr!("{} leg must have an account and an amount", text_of(&key)),
+                        )
+                        .with_hint(tr!("Use AMOUNT key for a simple transaction")));
+                    }
+                    legs.push(Leg::new(side, &value[0], &value[1..], ctx)?);
+                }
+                for side in [Side::Debit, Side::Credit] {
+                    if !legs.iter().any(|leg| leg.side == side) {
+                        return Err(kv.missing(&side.to_string()));
+                    }
+                }
+                legs
             }
         };
-        self.completion_date = Some(date);
+        self.add_transaction(first_token, legs, event.as_ref(), completion_date, tags)
+    }
+    fn add_transaction(
+        &mut self,
+        first_token: Token,
+        legs: Vec<Leg>,
+        event: Option<&Token>,
+        completion_date: Option<NaiveDate>,
+        tags: Vec<Tag>,
+    ) -> Result<(), Diagnostic> {
+        let transaction = Transaction {
+            id: self.transactions.len() as i32 + 1,
+            span: first_token.span(),
+            legs,
+            event_id: event.map(text_of),
+            event_span: event.map(|t| t.span()).unwrap_or_default(),
+            completion_date,
+            tags,
+            template: None,
+        };
+        self.transactions.push(transaction);
         Ok(())
     }
-    fn set_duedate(&mut self, first_token: Token, mut params: Vec<Token>) -> Result<(), String> {
-        if params.len() != 1 {
-            return Err(error_msg(&first_token, "DUEDATE must have one parameter"));
-        }
-        let date_string = params.remove(0).token_kind().take_text_string().unwrap();
-        let date = match NaiveDate::parse_from_str(&date_string, "%Y-%m-%d") {
-            Ok(date) => date,
-            Err(_) => {
-                return Err(error_msg(
-                    &first_token,
-                    "Date must have valid ISO format. YYYY-mm-dd",
-                ))
-            }
+    fn set_reference(&mut self, first_token: Token, params: Vec<Token>) -> Result<(), Diagnostic> {
+        let mut kv = KeyValues::parse(first_token, params, "REFERENCE", REFERENCE_KEYS)?;
+        let id = kv.required("ID")?;
+        self.references.push(Reference {
+            id: text_of(&id),
+            id_span: id.span(),
+            name: kv.string("NAME")?,
+            issue_date: kv.date("IDATE")?,
+            completion_date: kv.date("CDATE")?,
+            duedate: kv.date("DDATE")?,
+        });
+        Ok(())
+    }
+    fn set_event(&mut self, first_token: Token, params: Vec<Token>) -> Result<(), Diagnostic> {
+        let mut kv = KeyValues::parse(first_token, params, "EVENT", EVENT_KEYS)?;
+        let id = kv.required("ID")?;
+        let reference = kv.required("REFERENCE_ID")?;
+        self.events.push(Event {
+            id: text_of(&id),
+            id_span: id.span(),
+            reference_id: text_of(&reference),
+            reference_span: reference.span(),
+            name: kv.string("NAME")?,
+            issue_date: kv.date("IDATE")?,
+            completion_date: kv.date("CDATE")?,
+            duedate: kv.date("DDATE")?,
+        });
+        Ok(())
+    }
+    // Close the current revision, e.g. SIGNATURE 2022-05-20T06:11:34+02:00 21a22c..
+    fn set_signature(&mut self, first_token: Token, params: Vec<Token>) -> Result<(), Diagnostic> {
+        // Older notes have timestamps with spaces, e.g.
+        // 2022-05-19 15:24:01.896752 UTC, the hash is always the last token
+        let Some((hash, timestamp)) = params.split_last() else {
+            return Err(param_count_error(
+                &first_token,
+                tr!("SIGNATURE must have a hash, and optionally a timestamp before it"),
+            ));
+        };
+        let timestamp = match timestamp.is_empty() {
+            true => None,
+            false => Some(
+                timestamp
+                    .iter()
+                    .map(text_of)
+                    .collect::<Vec<String>>()
+                    .join(" "),
+            ),
         };
-        self.duedate = Some(date);
+        self.history.sign(Signature {
+            timestamp,
+            hash: text_of(hash),
+            span: first_token.span(),
+            valid: false,
+        });
         Ok(())
     }
-    fn set_transaction(
+    fn set_template(&mut self, first_token: Token, params: Vec<Token>) -> Result<(), Diagnostic> {
+        let mut template = Template::parse(&first_token, params)?;
+        template.path = self.path.clone();
+        self.templates.push(template);
+        Ok(())
+    }
+    // Expand template call into a transaction
+    fn set_use(
         &mut self,
         first_token: Token,
         mut params: Vec<Token>,
-    ) -> Result<(), String> {
-        if params.len() < 3 {
-            return Err(error_msg(
+        ctx: &NoteContext,
+    ) -> Result<(), Diagnostic> {
+        if params.is_empty() {
+            return Err(param_count_error(
                 &first_token,
-                "TRANSACTION must have minimum 3 parameters. Debit, Credit, Amount",
+                tr!("USE must have a template name"),
             ));
         }
-        let mut transaction: Transaction = Transaction::default();
-        transaction.debit = params
-            .get(0)
-            .unwrap()
-            .token_kind_ref()
-            .take_text_string_ref()
-            .unwrap()
-            .clone();
-        transaction.credit = params
-            .get(1)
-            .unwrap()
-            .token_kind_ref()
-            .take_text_string_ref()
-            .unwrap()
-            .clone();
-        transaction.amount = match params
-            .get(2)
-            .unwrap()
-            .token_kind_ref()
-            .take_text_string_ref()
-            .unwrap()
-            .replace("_", "")
-            .parse::<i64>()
-        {
-            Ok(res) => res,
-            Err(_) => {
-                return Err(error_msg(
-                    &first_token,
-                    "TRANSACTION amount must be integer number",
-                ))
-            }
-        };
-        transaction.id = self.transactions.len() as i32 + 1;
-        self.transactions.push(transaction);
+        let name = params.remove(0);
+        let template = ctx.templates.get(&text_of(&name)).ok_or_else(|| {
+            error_msg(&name, Code::UnknownTemplate, tr!("Unknown template"))
+                .with_hint(tr!("Templates are defined in account files by TEMPLATE"))
+        })?;
+        let defined_at = tr!(
+            "Template {} is defined at {}",
+            template.signature(),
+            template.location()
+        );
+        if params.len() < template.params.len() {
+            return Err(param_count_error(
+                &first_token,
+                tr!(
+                    "USE {} must have {} arguments",
+                    template.name,
+                    template.params.len()
+                ),
+            )
+            .with_hint(defined_at));
+        }
+        let params = template.expand(&first_token, &params);
+        self.set_transaction(first_token, params, ctx)
+            .map_err(|e| e.with_hint(defined_at.clone()))?;
+        if let Some(transaction) = self.transactions.last_mut() {
+            transaction.template = Some(defined_at);
+        }
         Ok(())
     }
-    fn set_account(&mut self, first_token: Token, mut params: Vec<Token>) -> Result<(), String> {
+    fn set_include(&mut self, first_token: Token, params: Vec<Token>) -> Result<(), Diagnostic> {
+        match params.as_slice() {
+            [path] => {
+                self.includes.push(Include {
+                    path: text_of(path),
+                    span: path.span(),
+                });
+                Ok(())
+            }
+            _ => Err(
+                param_count_error(&first_token, tr!("INCLUDE must have one parameter")).with_hint(
+                    tr!("File, directory or file name pattern, e.g. INCLUDE accounts/384/*.bit"),
+                ),
+            ),
+        }
+    }
+    fn set_account(
+        &mut self,
+        first_token: Token,
+        mut params: Vec<Token>,
+    ) -> Result<(), Diagnostic> {
+        // Named form, e.g. ACCOUNT ID 161 NAME Beruházás;
+        if params.first().is_some_and(|t| is_key(t, ACCOUNT_KEYS)) {
+            let mut kv = KeyValues::parse(first_token, params, "ACCOUNT", ACCOUNT_KEYS)?;
+            let id = kv.required("ID")?;
+            let name = kv
+                .required_tokens("NAME")?
+                .iter()
+                .map(text_of)
+                .collect::<Vec<String>>()
+                .join(" ");
+            let mut account = Account::new(text_of(&id), name);
+            account.path = self.path.clone();
+            account.span = id.span();
+            account.parent = kv.string("PARENT")?;
+            account.account_type = kv
+                .single("TYPE")?
+                .map(|t| parse_account_type(&t))
+                .transpose()?;
+            self.accounts.push(account);
+            return Ok(());
+        }
+        // Optional trailing TYPE key after the name
+        let mut account_type = None;
+        if let Some(pos) = params.iter().skip(2).position(|t| is_key(t, &["TYPE"])) {
+            let mut tail = params.split_off(pos + 2);
+            if tail.len() != 2 {
+                return Err(param_count_error(
+                    &tail[0],
+                    tr!("TYPE must have exactly one value"),
+                ));
+            }
+            account_type = Some(parse_account_type(&tail.remove(1))?);
+        }
         if params.len() < 2 {
-            return Err(error_msg(
+            return Err(param_count_error(
                 &first_token,
-                "ACCOUNT must have at least two parameter",
+                tr!("ACCOUNT must have at least two parameter"),
             ));
         }
+        let span = params[0].span();
         let account_name = params.remove(0).token_kind().take_text_string().unwrap();
         let account_value = params
             .into_iter()
             .map(|i| i.token_kind().take_text_string().unwrap())
             .collect::<Vec<String>>()
             .join(" ");
-        let account = Account::new(account_name, account_value);
+        let mut account = Account::new(account_name, account_value);
+        account.path = self.path.clone();
+        account.span = span;
+        account.account_type = account_type;
         self.accounts.push(account);
         Ok(())
     }
-    fn check(&self, is_account_file: bool) -> Result<(), String> {
+    /// Value of a field used by amount expressions
+    pub fn field(&self, field: Field) -> Option<Money> {
+        match field {
+            Field::Net => self.net,
+            Field::Vat => self.vat,
+            Field::Gross => self.gross,
+        }
+    }
+    fn check(&self, is_account_file: bool) -> Result<(), Diagnostic> {
         if !is_account_file && self.id.is_none() {
-            return Err(format!("MISSING ID at {}", self.path.to_string_lossy()));
+            return Err(Diagnostic::error(Code::MissingId, tr!("MISSING ID")).with_path(&self.path));
         }
         Ok(())
     }
-    pub fn write_to_file(&self) -> Result<(), String> {
+    // Check reference and event IDs and the links between them
+    fn check_events(&self) -> Vec<Diagnostic> {
+        let mut res = Vec::new();
+        let duplicate = |id: &str, span: Span, name: String| {
+            Diagnostic::error(Code::DuplicateId, tr!("Duplicate {} ID {}", name, id))
+                .with_span(span)
+        };
+        for (i, reference) in self.references.iter().enumerate() {
+            if self.references[..i].iter().any(|r| r.id == reference.id) {
+                res.push(duplicate(
+                    &reference.id,
+                    reference.id_span,
+                    tr!("reference"),
+                ));
+            }
+        }
+        for (i, event) in self.events.iter().enumerate() {
+            if self.events[..i].iter().any(|e| e.id == event.id) {
+                res.push(duplicate(&event.id, event.id_span, tr!("event")));
+            }
+            if self.reference(&event.reference_id).is_none() {
+                res.push(
+                    Diagnostic::error(
+                        Code::UnknownReference,
+                        tr!("Unknown reference ID {}", event.reference_id),
+                    )
+                    .with_span(event.reference_span),
+                );
+            }
+        }
+        for transaction in &self.transactions {
+            if let Some(event_id) = &transaction.event_id {
+                if self.event(event_id).is_none() {
+                    res.push(
+                        Diagnostic::error(Code::UnknownEvent, tr!("Unknown event ID {}", event_id))
+                            .with_span(transaction.event_span),
+                    );
+                }
+            }
+        }
+        res
+    }
+    pub fn reference(&self, id: &str) -> Option<&Reference> {
+        self.references.iter().find(|r| r.id == id)
+    }
+    pub fn event(&self, id: &str) -> Option<&Event> {
+        self.events.iter().find(|e| e.id == id)
+    }
+    /// Completion date of a transaction
+    ///
+    /// Transaction's own date first, then its event's, its reference's
+    /// and finally the note's.
+    pub fn transaction_completion_date(&self, transaction: &Transaction) -> Option<NaiveDate> {
+        if transaction.completion_date.is_some() {
+            return transaction.completion_date;
+        }
+        let event = transaction.event_id.as_ref().and_then(|id| self.event(id));
+        event
+            .and_then(|e| e.completion_date)
+            .or_else(|| {
+                event
+                    .and_then(|e| self.reference(&e.reference_id))
+                    .and_then(|r| r.completion_date)
+            })
+            .or(self.completion_date)
+    }
+    /// Note tags and the transaction's own tags
+    ///
+    /// Transaction dimensions override the note dimensions with the same key.
+    pub fn transaction_tags(&self, transaction: &Transaction) -> Vec<Tag> {
+        let mut res = self
+            .tags
+            .iter()
+            .filter(|tag| match tag {
+                Tag::Dimension(key, _) => !transaction.tags.iter().any(|t| t.key() == Some(key)),
+                Tag::Tag(_) => true,
+            })
+            .chain(&transaction.tags)
+            .cloned()
+            .collect::<Vec<Tag>>();
+        res.sort();
+        res.dedup();
+        res
+    }
+    pub fn write_to_file(&self) -> Result<(), Diagnostic> {
         if self.path.exists() {
-            return Err(format!("Note has already exist. {:?}", &self.path));
+            return Err(
+                Diagnostic::error(Code::Io, tr!("Note has already exist.")).with_path(&self.path)
+            );
         }
-        let mut new_file = std::fs::File::create(&self.path)
-            .map_err(|_| format!("Error while creating note template file: {:?}", &self.path))?;
+        let mut new_file = std::fs::File::create(&self.path).map_err(|_| {
+            Diagnostic::error(Code::Io, tr!("Error while creating note template file"))
+                .with_path(&self.path)
+        })?;
         writeln!(
             &mut new_file,
             "ID {}",
-            &self.id.as_ref().unwrap_or(&String::new())
-        )
-        .unwrap();
-        writeln!(
-            &mut new_file,
-            "DOCID {}",
-            &self.docid.as_ref().unwrap_or(&String::new())
-        )
-        .unwrap();
-        writeln!(
-            &mut new_file,
-            "ALIAS {}",
-            &self.alias.as_ref().unwrap_or(&String::new())
+            self.id.as_ref().unwrap_or(&String::new())
         )
         .unwrap();
+        // Empty DOCID and ALIAS would not parse
+        if let Some(docid) = &self.docid {
+            writeln!(&mut new_file, "DOCID {}", docid).unwrap();
+        }
+        if let Some(alias) = &self.alias {
+            writeln!(&mut new_file, "ALIAS {}", alias).unwrap();
+        }
         writeln!(
             &mut new_file,
             "PAYMENT_KIND {}",
-            &self.payment_kind.as_ref().unwrap_or(&PaymentKind::Cash)
+            self.payment_kind.as_ref().unwrap_or(&PaymentKind::Cash)
         )
         .unwrap();
         writeln!(
             &mut new_file,
             "COMPLETION_DATE {}",
-            &self
-                .completion_date
-                .as_ref()
-                .unwrap_or(&Local::today().naive_local())
+            self.completion_date
+                .unwrap_or_else(|| Local::now().date_naive())
         )
         .unwrap();
-        writeln!(&mut new_file, "NET {}", &self.net.as_ref().unwrap_or(&0)).unwrap();
-        writeln!(&mut new_file, "VAT {}", &self.vat.as_ref().unwrap_or(&0)).unwrap();
-        writeln!(
-            &mut new_file,
-            "GROSS {}",
-            &self.gross.as_ref().unwrap_or(&0)
-        )
-        .unwrap();
-        writeln!(&mut new_file, "\n\nTransactions:\n",).unwrap();
+        writeln!(&mut new_file, "NET {}", self.net.unwrap_or_default()).unwrap();
+        writeln!(&mut new_file, "VAT {}", self.vat.unwrap_or_default()).unwrap();
+        writeln!(&mut new_file, "GROSS {}", self.gross.unwrap_or_default()).unwrap();
+        writeln!(&mut new_file, "\n# Transactions\n").unwrap();
         Ok(())
     }
 }
 
-fn parse_single_string(tokens: &Vec<Token>) -> Result<String, String> {
-    if tokens.len() > 2 {
-        return Err(error_msg(
-            tokens.last().unwrap(),
-            "Too many parameters. Maximum 1.",
+// Parse and evaluate header amount, it can refer only to
+// the fields defined before
+fn parse_single_amount(
+    note: &Note,
+    first_token: &Token,
+    params: Vec<Token>,
+    name: &str,
+    ctx: &NoteContext,
+) -> Result<Money, Diagnostic> {
+    if params.is_empty() {
+        return Err(param_count_error(
+            first_token,
+            tr!("{} must have one parameter", name),
         ));
     }
-    if tokens.len() > 2 {
-        return Err(error_msg(
-            tokens.last().unwrap(),
-            "Few parameters. Need exactly 1.",
+    let expr = parse_amount_expr(&params, &tr!("{} must be a valid amount", name), ctx)?;
+    expr.eval(&|field| note.field(field))
+        .map_err(|e| e.with_hint(tr!("{} can refer only to fields defined before it", name)))
+}
+
+// Parse amount literal or expression
+fn parse_amount_expr(
+    params: &[Token],
+    message: &str,
+    ctx: &NoteContext,
+) -> Result<Expr, Diagnostic> {
+    if let [token] = params {
+        let text = token.token_kind_ref().take_text_string_ref().unwrap();
+        let is_expression = text.starts_with('(')
+            || text.chars().skip(1).any(|c| "+-*/()".contains(c))
+            || Field::parse(text).is_some();
+        if !is_expression {
+            return Money::parse(text, &ctx.money_format)
+                .map(|value| Expr::Number(value, token.span()))
+                .map_err(|e| error_msg(token, Code::InvalidNumber, message).with_hint(e));
+        }
+    }
+    Expr::parse(params, &ctx.money_format)
+}
+
+fn parse_single_date(
+    first_token: &Token,
+    mut params: Vec<Token>,
+    name: &str,
+) -> Result<NaiveDate, Diagnostic> {
+    if params.len() != 1 {
+        return Err(param_count_error(
+            first_token,
+            tr!("{} must have one parameter", name),
         ));
     }
-    match tokens.get(1) {
-        Some(t) => match t.token_kind_ref() {
-            TokenKind::Command(_) => todo!(),
-            TokenKind::Text(text) => todo!(),
-        },
-        None => unimplemented!(),
+    parse_date(&params.remove(0))
+}
+
+// Debit and credit legs with the same amount
+fn simple_legs(
+    debit: &Token,
+    credit: &Token,
+    amount: &[Token],
+    ctx: &NoteContext,
+) -> Result<Vec<Leg>, Diagnostic> {
+    Ok(vec![
+        Leg::new(Side::Debit, debit, amount, ctx)?,
+        Leg::new(Side::Credit, credit, amount, ctx)?,
+    ])
+}
+
+// Remove #tag and key:value tokens from params
+fn take_tags(params: &mut Vec<Token>) -> Result<Vec<Tag>, Diagnostic> {
+    let mut tags: Vec<Tag> = Vec::new();
+    let mut rest = Vec::new();
+    for token in params.drain(..) {
+        let tag = match token.is_quoted() {
+            true => None,
+            false => token
+                .token_kind_ref()
+                .take_text_string_ref()
+                .and_then(|t| Tag::parse(t)),
+        };
+        match tag {
+            Some(tag) => {
+                if tag.key().is_some() && tags.iter().any(|t| t.key() == tag.key()) {
+                    return Err(error_msg(
+                        &token,
+                        Code::DuplicateKey,
+                        tr!("Dimension is set more than once"),
+                    ));
+                }
+                tags.push(tag);
+            }
+            None => rest.push(token),
+        }
+    }
+    *params = rest;
+    Ok(tags)
+}
+
+fn parse_account_type(token: &Token) -> Result<AccountType, Diagnostic> {
+    AccountType::parse(&text_of(token)).ok_or_else(|| {
+        error_msg(token, Code::InvalidAccountType, tr!("Unknown account type")).with_hint(tr!(
+            "Account type must be one of: {}",
+            AccountType::ALL.map(|t| t.to_string()).join(", ")
+        ))
+    })
+}
+
+// Date shaped token, e.g. 2022-04-10
+fn looks_like_date(token: &Token) -> bool {
+    let text = text_of(token);
+    let parts = text.split('-').collect::<Vec<&str>>();
+    !token.is_quoted()
+        && parts.len() == 3
+        && parts[0].len() == 4
+        && parts
+            .iter()
+            .all(|p| !p.is_empty() && p.chars().all(|c| c.is_ascii_digit()))
+}
+
+fn parse_date(param: &Token) -> Result<NaiveDate, Diagnostic> {
+    param
+        .token_kind_ref()
+        .take_text_string_ref()
+        .and_then(|date_string| NaiveDate::parse_from_str(date_string, "%Y-%m-%d").ok())
+        .ok_or_else(|| {
+            error_msg(
+                param,
+                Code::InvalidDate,
+                tr!("Date must have valid ISO format. YYYY-mm-dd"),
+            )
+        })
+}
+
+// Parameters as written, quoted ones between quotes
+fn tokens_text(tokens: &[Token]) -> String {
+    tokens
+        .iter()
+        .map(|t| match t.is_quoted() {
+            true => format!("\"{}\"", text_of(t)),
+            false => text_of(t),
+        })
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+fn text_of(token: &Token) -> String {
+    token
+        .token_kind_ref()
+        .take_text_string_ref()
+        .unwrap()
+        .clone()
+}
+
+pub(crate) const TRANSACTION_KEYS: &[&str] = &["DEBIT", "CREDIT", "AMOUNT", "EVENT_ID", "CDATE"];
+// Optional keys after the positional transaction amount
+pub(crate) const TRANSACTION_TAIL_KEYS: &[&str] = &["EVENT_ID", "CDATE"];
+const ACCOUNT_KEYS: &[&str] = &["ID", "NAME", "PARENT", "TYPE"];
+const REFERENCE_KEYS: &[&str] = &["ID", "NAME", "IDATE", "CDATE", "DDATE"];
+const EVENT_KEYS: &[&str] = &["ID", "REFERENCE_ID", "NAME", "IDATE", "CDATE", "DDATE"];
+
+// Keys of the commands having named KEY VALUE form
+pub(crate) fn command_keys(token: &Token) -> &'static [&'static str] {
+    match token.token_kind_ref() {
+        TokenKind::Command(Command::Transaction | Command::Template) => TRANSACTION_KEYS,
+        TokenKind::Command(Command::Account) => ACCOUNT_KEYS,
+        TokenKind::Command(Command::Reference) => REFERENCE_KEYS,
+        TokenKind::Command(Command::Event) => EVENT_KEYS,
+        _ => &[],
+    }
+}
+
+pub(crate) fn is_key(token: &Token, keys: &[&str]) -> bool {
+    !token.is_quoted()
+        && token
+            .token_kind_ref()
+            .take_text_string_ref()
+            .is_some_and(|text| keys.contains(&text.as_str()))
+}
+
+/// Command with its parameters, can span multiple lines
+pub(crate) struct Statement {
+    first_token: Token,
+    params: Vec<Token>,
+    pub(crate) keys: &'static [&'static str],
+    /// Number of lines
+    lines: usize,
+}
+
+impl Statement {
+    pub(crate) fn new(first_token: Token, params: Vec<Token>) -> Self {
+        Self {
+            keys: command_keys(&first_token),
+            first_token,
+            params,
+            lines: 1,
+        }
+    }
+    // Named form starts with a key, or with the command alone.
+    // Template body follows its signature.
+    pub(crate) fn is_named(&self) -> bool {
+        let is_template = matches!(
+            self.first_token.token_kind_ref(),
+            TokenKind::Command(Command::Template)
+        );
+        is_template
+            || !self.keys.is_empty()
+                && self
+                    .params
+                    .first()
+                    .is_none_or(|token| is_key(token, self.keys))
+    }
+    pub(crate) fn is_terminated(&self) -> bool {
+        self.params.iter().any(|token| token.is_semicolon())
     }
 }
 
-fn error_msg(token: &Token, message: &str) -> String {
-    format!(
-        "Error row {} column {}\n{}",
-        token.position().0,
-        token.position().1,
-        message
-    )
+/// KEY VALUE parameters of a command
+///
+/// Value lasts till the next key, so expressions can be values as well.
+struct KeyValues {
+    pairs: Vec<(Token, Vec<Token>)>,
+    first_token: Token,
+    name: &'static str,
 }
 
-#[derive(Debug)]
+impl KeyValues {
+    fn parse(
+        first_token: Token,
+        params: Vec<Token>,
+        name: &'static str,
+        keys: &[&str],
+    ) -> Result<Self, Diagnostic> {
+        Self::parse_pairs(first_token, params, name, keys, &[])
+    }
+    fn parse_pairs(
+        first_token: Token,
+        params: Vec<Token>,
+        name: &'static str,
+        keys: &[&str],
+        repeated: &[&str],
+    ) -> Result<Self, Diagnostic> {
+        let mut pairs: Vec<(Token, Vec<Token>)> = Vec::new();
+        for token in params {
+            if is_key(&token, keys) {
+                let key_text = text_of(&token);
+                if !repeated.contains(&key_text.as_str())
+                    && pairs.iter().any(|(k, _)| text_of(k) == key_text)
+                {
+                    return Err(error_msg(
+                        &token,
+                        Code::DuplicateKey,
+                        tr!("Duplicate key {}", key_text),
+                    ));
+                }
+                pairs.push((token, Vec::new()));
+                continue;
+            }
+            match pairs.last_mut() {
+                Some((_, value)) => value.push(token),
+                None => {
+                    return Err(error_msg(
+                        &token,
+                        Code::UnknownKey,
+                        tr!("Unknown key {} for {}", text_of(&token), name),
+                    )
+                    .with_hint(tr!("Allowed keys: {}", keys.join(", "))))
+                }
+            }
+        }
+        for (key, value) in &pairs {
+            if value.is_empty() {
+                return Err(param_count_error(
+                    key,
+                    tr!("Missing value for key {}", text_of(key)),
+                ));
+            }
+        }
+        Ok(Self {
+            pairs,
+            first_token,
+            name,
+        })
+    }
+    // Error if a repeatable key is given more than once
+    fn check_single(&self, key: &str) -> Result<(), Diagnostic> {
+        match self.pairs.iter().filter(|(k, _)| text_of(k) == key).nth(1) {
+            Some((k, _)) => Err(
+                error_msg(k, Code::DuplicateKey, tr!("Duplicate key {}", key))
+                    .with_hint(tr!("Split legs cannot be used together with AMOUNT")),
+            ),
+            None => Ok(()),
+        }
+    }
+    // All the pairs of the given keys in source order
+    fn take_all(&mut self, keys: &[&str]) -> Vec<(Token, Vec<Token>)> {
+        let (res, rest) = std::mem::take(&mut self.pairs)
+            .into_iter()
+            .partition(|(k, _)| keys.contains(&text_of(k).as_str()));
+        self.pairs = rest;
+        res
+    }
+    // Value tokens of a key
+    fn take(&mut self, key: &str) -> Option<(Token, Vec<Token>)> {
+        let pos = self.pairs.iter().position(|(k, _)| text_of(k) == key)?;
+        Some(self.pairs.remove(pos))
+    }
+    // Single token value of a key
+    fn single(&mut self, key: &str) -> Result<Option<Token>, Diagnostic> {
+        match self.take(key) {
+            None => Ok(None),
+            Some((_, mut value)) if value.len() == 1 => Ok(value.pop()),
+            Some((_, value)) => Err(error_msg(
+                &value[1],
+                Code::UnknownKey,
+                tr!("Unknown key {} for {}", text_of(&value[1]), self.name),
+            )
+            .with_hint(tr!(
+                "{} must have exactly one value, quote texts with spaces",
+                key
+            ))),
+        }
+    }
+    fn missing(&self, key: &str) -> Diagnostic {
+        error_msg(
+            &self.first_token,
+            Code::MissingKey,
+            tr!("{} must have {} key", self.name, key),
+        )
+    }
+    fn required(&mut self, key: &str) -> Result<Token, Diagnostic> {
+        self.single(key)?.ok_or_else(|| self.missing(key))
+    }
+    fn required_tokens(&mut self, key: &str) -> Result<Vec<Token>, Diagnostic> {
+        self.take(key)
+            .map(|(_, value)| value)
+            .ok_or_else(|| self.missing(key))
+    }
+    fn string(&mut self, key: &str) -> Result<Option<String>, Diagnostic> {
+        Ok(self.single(key)?.map(|t| text_of(&t)))
+    }
+    fn date(&mut self, key: &str) -> Result<Option<NaiveDate>, Diagnostic> {
+        self.single(key)?.map(|t| parse_date(&t)).transpose()
+    }
+}
+
+// Line text without its comment
+fn title_text(line: &Line) -> Option<String> {
+    if line.tokens_ref().is_empty() {
+        return None;
+    }
+    let text = match line.comment() {
+        Some(comment) => line
+            .raw()
+            .chars()
+            .take(comment.span().column - 1)
+            .collect::<String>(),
+        None => line.raw().to_string(),
+    };
+    Some(text.trim().to_string())
+}
+
+fn param_count_error(token: &Token, message: impl Into<String>) -> Diagnostic {
+    error_msg(token, Code::ParameterCount, message)
+}
+
+fn error_msg(token: &Token, code: Code, message: impl Into<String>) -> Diagnostic {
+    Diagnostic::error(code, message).with_span(token.span())
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "lowercase")]
 pub enum PaymentKind {
     Cash,
     Card,
@@ -414,12 +1366,240 @@ impl Display for PaymentKind {
     }
 }
 
-#[derive(Debug, Default)]
+/// Side of a transaction leg
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Side {
+    #[default]
+    Debit,
+    Credit,
+}
+
+impl Side {
+    /// Translated name for messages
+    pub fn label(&self) -> String {
+        match self {
+            Side::Debit => tr!("debit"),
+            Side::Credit => tr!("credit"),
+        }
+    }
+}
+
+impl Display for Side {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Side::Debit => write!(f, "DEBIT"),
+            Side::Credit => write!(f, "CREDIT"),
+        }
+    }
+}
+
+/// One account line of a transaction
+#[derive(Debug, Default, Serialize)]
+pub struct Leg {
+    pub side: Side,
+    pub account: String,
+    pub account_span: Span,
+    pub amount: Money,
+    /// Amount expression, None if amount is a plain number.
+    /// Only the evaluated amount is serialized.
+    #[serde(skip)]
+    pub amount_expr: Option<Expr>,
+}
+
+impl Leg {
+    // Amount expressions are evaluated when the whole note is parsed
+    fn new(
+        side: Side,
+        account: &Token,
+        amount: &[Token],
+        ctx: &NoteContext,
+    ) -> Result<Self, Diagnostic> {
+        let amount_expr = parse_amount_expr(
+            amount,
+            &tr!("TRANSACTION amount must be a valid amount"),
+            ctx,
+        )?;
+        Ok(Self {
+            side,
+            account: text_of(account),
+            account_span: account.span(),
+            amount: match &amount_expr {
+                Expr::Number(value, _) => *value,
+                _ => Money::zero(ctx.money_format.precision),
+            },
+            amount_expr: match amount_expr.is_literal() {
+                true => None,
+                false => Some(amount_expr),
+            },
+        })
+    }
+}
+
+/// Transaction label, either #tag or key:value dimension,
+/// e.g. #fuel or cost_center:K2
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Tag {
+    Tag(String),
+    Dimension(String, String),
+}
+
+impl Tag {
+    /// Parse #tag or key:value, anything else is not a tag
+    pub fn parse(text: &str) -> Option<Self> {
+        if let Some(tag) = text.strip_prefix('#') {
+            return (!tag.is_empty()).then(|| Tag::Tag(tag.to_string()));
+        }
+        // Keys are words, so times, ratios and accounts like K454:x are not tags
+        let (key, value) = text.split_once(':')?;
+        let is_key = key.starts_with(char::is_alphabetic)
+            && key
+                .chars()
+                .all(|c| c.is_alphabetic() || c == '_' || c == '-');
+        (is_key && !value.is_empty()).then(|| Tag::Dimension(key.to_string(), value.to_string()))
+    }
+    /// Dimension key
+    pub fn key(&self) -> Option<&String> {
+        match self {
+            Tag::Tag(_) => None,
+            Tag::Dimension(key, _) => Some(key),
+        }
+    }
+}
+
+// Tags are written as in notes, #tag or key:value
+impl Serialize for Tag {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl std::fmt::Display for Tag {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Tag::Tag(tag) => write!(f, "#{}", tag),
+            Tag::Dimension(key, value) => write!(f, "{}:{}", key, value),
+        }
+    }
+}
+
+/// Compound transaction with debit and credit legs
+///
+/// Simple transactions have one debit and one credit leg.
+#[derive(Debug, Default, Serialize)]
 pub struct Transaction {
     pub id: i32,
-    pub debit: String,
-    pub credit: String,
-    pub amount: i64,
+    /// Span of the transaction command
+    pub span: Span,
+    pub legs: Vec<Leg>,
+    pub event_id: Option<String>,
+    pub event_span: Span,
+    /// Own completion date, overrides the event and note dates
+    pub completion_date: Option<NaiveDate>,
+    /// Own tags and dimensions, see Note::transaction_tags
+    pub tags: Vec<Tag>,
+    /// Template definition of a USE, as a diagnostic hint
+    pub template: Option<String>,
+}
+
+impl Transaction {
+    /// Simple transaction with one debit and one credit leg
+    pub fn simple(debit: &str, credit: &str, amount: Money) -> Self {
+        let leg = |side, account: &str| Leg {
+            side,
+            account: account.to_string(),
+            amount,
+            ..Default::default()
+        };
+        Self {
+            legs: vec![leg(Side::Debit, debit), leg(Side::Credit, credit)],
+            ..Default::default()
+        }
+    }
+    /// Sum of the legs of a side, None on overflow
+    pub fn total(&self, side: Side) -> Option<Money> {
+        self.legs
+            .iter()
+            .filter(|leg| leg.side == side)
+            .try_fold(Money::default(), |sum, leg| sum.checked_add(&leg.amount))
+    }
+    /// Debit and credit totals must be equal
+    pub fn check_balance(&self) -> Result<(), Diagnostic> {
+        let debit = self.total(Side::Debit);
+        let credit = self.total(Side::Credit);
+        match (debit, credit) {
+            (Some(debit), Some(credit)) if debit == credit => Ok(()),
+            (Some(debit), Some(credit)) => {
+                let diagnostic = Diagnostic::error(
+                    Code::UnbalancedTransaction,
+                    tr!("Transaction is not balanced"),
+                )
+                .with_span(self.span);
+                // The difference of two valid totals may still overflow
+                Err(match debit.checked_sub(&credit) {
+                    Some(difference) => diagnostic.with_hint(tr!(
+                        "Debit total is {}, credit total is {}, difference {}",
+                        debit,
+                        credit,
+                        difference
+                    )),
+                    None => diagnostic.with_hint(tr!(
+                        "Debit total is {}, credit total is {}",
+                        debit,
+                        credit
+                    )),
+                })
+            }
+            _ => Err(
+                Diagnostic::error(Code::AmountOverflow, tr!("Transaction total overflow"))
+                    .with_span(self.span),
+            ),
+        }
+    }
+}
+
+/// Account file included by INCLUDE, relative to the including file
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct Include {
+    pub path: String,
+    /// Span of the path parameter
+    pub span: Span,
+}
+
+/// Source document reference
+///
+/// One reference can yield several business events.
+#[derive(Debug, Default, Serialize)]
+pub struct Reference {
+    pub id: String,
+    pub id_span: Span,
+    pub name: Option<String>,
+    pub issue_date: Option<NaiveDate>,
+    pub completion_date: Option<NaiveDate>,
+    pub duedate: Option<NaiveDate>,
+}
+
+/// Business event of a reference
+#[derive(Debug, Default, Serialize)]
+pub struct Event {
+    pub id: String,
+    pub id_span: Span,
+    pub reference_id: String,
+    pub reference_span: Span,
+    pub name: Option<String>,
+    pub issue_date: Option<NaiveDate>,
+    pub completion_date: Option<NaiveDate>,
+    pub duedate: Option<NaiveDate>,
+}
+
+/// Project level settings used while parsing notes
+#[derive(Debug, Default, Clone)]
+pub struct NoteContext {
+    pub money_format: MoneyFormat,
+    /// Templates defined in the account files
+    pub templates: Templates,
+    /// Invoice arithmetic rules of the notes
+    pub validation: Validation,
 }
 
 #[cfg(test)]
@@ -436,4 +1616,313 @@ mod tests {
         template.write_to_file().unwrap();
         assert_eq!(1, 1)
     }
+
+    #[test]
+    fn test_error_points_to_value() {
+        let raw = crate::parser::parse("ID 1\nNET  12x00\n");
+        let err = Note::from_raw_note(raw, false, &NoteContext::default()).unwrap_err();
+        let err = err.iter().next().unwrap();
+        assert_eq!(err.code(), Code::InvalidNumber);
+        assert_eq!(err.span(), Some(Span::new(2, 6, 5)));
+    }
+
+    #[test]
+    fn test_missing_id() {
+        let raw = crate::parser::parse("NET 1\n");
+        let err = Note::from_raw_note(raw, false, &NoteContext::default()).unwrap_err();
+        assert_eq!(err.iter().next().unwrap().code(), Code::MissingId);
+    }
+
+    #[test]
+    fn test_unterminated_string_error() {
+        let raw = crate::parser::parse("ID 1\nAUTHOR \"Peter Mezei\n");
+        let err = Note::from_raw_note(raw, false, &NoteContext::default()).unwrap_err();
+        let codes = err.iter().map(|d| d.code()).collect::<Vec<Code>>();
+        assert_eq!(codes, vec![Code::UnterminatedString]);
+    }
+
+    #[test]
+    fn test_decimal_amounts() {
+        let ctx = NoteContext {
+            money_format: MoneyFormat::for_currency("EUR"),
+            ..Default::default()
+        };
+        let raw = crate::parser::parse("ID 1\nNET 11.81\n> 5 454 1_000.5\nVAT 0.123\n");
+        let err = Note::from_raw_note(raw, false, &ctx).unwrap_err();
+        assert_eq!(err.len(), 1);
+        assert_eq!(err.iter().next().unwrap().span(), Some(Span::new(4, 5, 5)));
+
+        let raw = crate::parser::parse("ID 1\nNET 11.81\n> 5 454 1_000.5\n");
+        let note = Note::from_raw_note(raw, false, &ctx).unwrap();
+        assert_eq!(note.net, Some(Money::new(1181, 2)));
+        assert_eq!(note.transactions[0].legs[0].amount, Money::new(100050, 2));
+    }
+
+    #[test]
+    fn test_amount_expressions() {
+        let raw = crate::parser::parse(
+            "ID 1\n> 466 454 GROSS - NET\n> 5 454 (15_000 * 27 / 127)\nNET 11_811\nGROSS NET + 3_189\n",
+        );
+        let note = Note::from_raw_note(raw, false, &NoteContext::default()).unwrap();
+        assert_eq!(note.gross, Some(Money::new(15000, 0)));
+        assert_eq!(note.transactions[0].legs[0].amount, Money::new(3189, 0));
+        assert_eq!(note.transactions[1].legs[0].amount, Money::new(3189, 0));
+    }
+
+    #[test]
+    fn test_amount_expression_errors() {
+        let raw = crate::parser::parse("ID 1\nVAT GROSS - NET\n> 466 454 VAT / 0\n");
+        let err = Note::from_raw_note(raw, false, &NoteContext::default()).unwrap_err();
+        let res = err
+            .iter()
+            .map(|d| (d.code(), d.span().unwrap()))
+            .collect::<Vec<(Code, Span)>>();
+        assert_eq!(
+            res,
+            vec![
+                (Code::UndefinedReference, Span::new(2, 5, 5)),
+                (Code::UndefinedReference, Span::new(3, 11, 3)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_header_commands() {
+        let raw = crate::parser::parse(
+            "TYPE    note\nDATE    2022-05-08\n\n---\nNyomdai kellékek // comment\n# block\nvásárlása\n---\n\nSOURCE  1\nID      12a3ef\n",
+        );
+        let note = Note::from_raw_note(raw, false, &NoteContext::default()).unwrap();
+        assert_eq!(note.note_type.as_deref(), Some("note"));
+        assert_eq!(note.date, NaiveDate::from_ymd_opt(2022, 5, 8));
+        assert_eq!(note.title.as_deref(), Some("Nyomdai kellékek vásárlása"));
+        assert_eq!(note.source.as_deref(), Some("1"));
+
+        let raw = crate::parser::parse("ID 1\nTITLE Telefon vásárlása\n");
+        let note = Note::from_raw_note(raw, false, &NoteContext::default()).unwrap();
+        assert_eq!(note.title.as_deref(), Some("Telefon vásárlása"));
+    }
+
+    #[test]
+    fn test_unclosed_title_block() {
+        let raw = crate::parser::parse("ID 1\n---\nTitle\nNET 1\n");
+        let err = Note::from_raw_note(raw, false, &NoteContext::default()).unwrap_err();
+        let err = err.iter().next().unwrap();
+        assert_eq!(err.code(), Code::UnclosedTitleBlock);
+        assert_eq!(err.span(), Some(Span::new(2, 1, 3)));
+    }
+
+    #[test]
+    fn test_references_and_events() {
+        let raw = crate::parser::parse(
+            "ID 1\nCOMPLETION_DATE 2022-01-10\nREFERENCE ID inv1 NAME \"Invoice 1\" CDATE 2022-01-05\nEVENT ID e1 REFERENCE_ID inv1\nEVENT ID e2 REFERENCE_ID inv1 CDATE 2022-02-01\n> 1 2 100 EVENT_ID e1\n> 1 2 200 EVENT_ID e2\n> 1 2 300\n",
+        );
+        let note = Note::from_raw_note(raw, false, &NoteContext::default()).unwrap();
+        assert_eq!(note.references[0].name.as_deref(), Some("Invoice 1"));
+        assert_eq!(note.events.len(), 2);
+        let dates = note
+            .transactions
+            .iter()
+            .map(|t| note.transaction_completion_date(t).unwrap().to_string())
+            .collect::<Vec<String>>();
+        assert_eq!(dates, ["2022-01-05", "2022-02-01", "2022-01-10"]);
+        assert_eq!(note.transactions[1].legs[0].amount, Money::new(200, 0));
+    }
+
+    #[test]
+    fn test_event_errors() {
+        let raw = crate::parser::parse(
+            "ID 1\nEVENT ID e1 REFERENCE_ID x\nEVENT ID e2\nREFERENCE ID r NAMES a\n> 1 2 100 EVENT_ID e9\n",
+        );
+        let err = Note::from_raw_note(raw, false, &NoteContext::default()).unwrap_err();
+        let codes = err.iter().map(|d| d.code()).collect::<Vec<Code>>();
+        assert_eq!(
+            codes,
+            [
+                Code::MissingKey,
+                Code::UnknownKey,
+                Code::UnknownReference,
+                Code::UnknownEvent
+            ]
+        );
+        assert_eq!(err.iter().last().unwrap().span(), Some(Span::new(5, 20, 2)));
+    }
+
+    #[test]
+    fn test_transaction_completion_date() {
+        let raw = crate::parser::parse(
+            "ID 1\nCOMPLETION_DATE 2022-04-01\n> 1 2 100 2022-04-10\n> 1 2 100 CDATE 2022-04-11 EVENT_ID e\n> 1 2 100\nEVENT ID e REFERENCE_ID r CDATE 2022-04-20\nREFERENCE ID r\nTRANSACTION DEBIT 1 CREDIT 2 AMOUNT 5 CDATE 2022-04-12;\n",
+        );
+        let note = Note::from_raw_note(raw, false, &NoteContext::default()).unwrap();
+        let dates = note
+            .transactions
+            .iter()
+            .map(|t| note.transaction_completion_date(t).unwrap().to_string())
+            .collect::<Vec<String>>();
+        assert_eq!(
+            dates,
+            ["2022-04-10", "2022-04-11", "2022-04-01", "2022-04-12"]
+        );
+        assert_eq!(note.transactions[0].legs[0].amount, Money::new(100, 0));
+
+        // Positional date only after a single token amount
+        let raw = crate::parser::parse("ID 1\n> 1 2 10 - 5 2022-04-13\n");
+        let err = Note::from_raw_note(raw, false, &NoteContext::default()).unwrap_err();
+        assert_eq!(err.iter().next().unwrap().code(), Code::InvalidExpression);
+    }
+
+    #[test]
+    fn test_tags() {
+        let raw = crate::parser::parse(
+            "ID 1\nTAG #fuel project:alpha\n> T5 K454/agroker 11_811 #1 project:beta\nTRANSACTION DEBIT 1 CREDIT 2 AMOUNT 5 cost_center:K2;\n> \"#3\" 2 5\n",
+        );
+        let note = Note::from_raw_note(raw, false, &NoteContext::default()).unwrap();
+        let tags = |text: &[&str]| {
+            text.iter()
+                .map(|t| Tag::parse(t).unwrap())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(note.transactions[0].legs[0].amount, Money::new(11811, 0));
+        assert_eq!(
+            note.transaction_tags(&note.transactions[0]),
+            tags(&["#1", "#fuel", "project:beta"])
+        );
+        assert_eq!(
+            note.transaction_tags(&note.transactions[1]),
+            tags(&["#fuel", "cost_center:K2", "project:alpha"])
+        );
+        assert_eq!(note.transactions[2].legs[0].account, "#3");
+        assert!(note.transactions[2].tags.is_empty());
+        assert_eq!(Tag::parse("12:30"), None);
+        assert_eq!(Tag::parse("K454:x"), None);
+        assert_eq!(
+            Tag::parse("cost-center:K2"),
+            Some(Tag::Dimension("cost-center".into(), "K2".into()))
+        );
+        assert_eq!(Tag::parse("#"), None);
+        assert_eq!(Tag::parse("a b:c"), None);
+
+        let raw = crate::parser::parse("ID 1\n> 1 2 5 a:x a:y\nTAG fuel\nTAG\n");
+        let err = Note::from_raw_note(raw, false, &NoteContext::default()).unwrap_err();
+        let res = err.iter().map(|d| d.code()).collect::<Vec<_>>();
+        assert_eq!(
+            res,
+            [Code::DuplicateKey, Code::InvalidTag, Code::ParameterCount]
+        );
+    }
+
+    #[test]
+    fn test_split_transaction() {
+        let raw = crate::parser::parse(
+            "ID 1\nNET 10_000\nVAT 2_700\nTRANSACTION\n  DEBIT  161 NET\n  DEBIT  466 VAT\n  CREDIT 454 12_700;\n",
+        );
+        let note = Note::from_raw_note(raw, false, &NoteContext::default()).unwrap();
+        let legs = &note.transactions[0].legs;
+        assert_eq!(legs.len(), 3);
+        assert_eq!(legs[1].side, Side::Debit);
+        assert_eq!(legs[1].amount, Money::new(2700, 0));
+        assert_eq!(
+            note.transactions[0].total(Side::Credit),
+            Some(Money::new(12700, 0))
+        );
+
+        let raw = crate::parser::parse(
+            "ID 1\nTRANSACTION\n  DEBIT  161 100\n  DEBIT  466 27\n  CREDIT 454 120;\nTRANSACTION DEBIT 1 100 DEBIT 2 5;\nTRANSACTION DEBIT 1 DEBIT 2 CREDIT 3 AMOUNT 5;\n",
+        );
+        let err = Note::from_raw_note(raw, false, &NoteContext::default()).unwrap_err();
+        let res = err
+            .iter()
+            .map(|d| (d.code(), d.span().unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            res,
+            [
+                (Code::MissingKey, Span::new(6, 1, 11)),
+                (Code::DuplicateKey, Span::new(7, 21, 5)),
+                (Code::UnbalancedTransaction, Span::new(2, 1, 11)),
+            ]
+        );
+        assert_eq!(
+            err.iter().last().unwrap().hints(),
+            ["Debit total is 127, credit total is 120, difference 7"]
+        );
+    }
+
+    #[test]
+    fn test_named_statements() {
+        let raw = crate::parser::parse(
+            "ID 1;\nNET 100\nTRANSACTION\n  DEBIT   161 // comment\n  CREDIT  38\n  AMOUNT  NET * 2;\nTRANSACTION DEBIT 161 CREDIT 38 AMOUNT 1_000;\n> 161 38 5;\n",
+        );
+        let note = Note::from_raw_note(raw, false, &NoteContext::default()).unwrap();
+        assert_eq!(note.id.as_deref(), Some("1"));
+        let amounts = note
+            .transactions
+            .iter()
+            .map(|t| t.legs[0].amount.units())
+            .collect::<Vec<i64>>();
+        assert_eq!(amounts, [200, 1000, 5]);
+        assert_eq!(note.transactions[0].legs[1].account, "38");
+        assert_eq!(
+            note.transactions[0].legs[1].account_span,
+            Span::new(5, 11, 2)
+        );
+
+        let raw = crate::parser::parse("ACCOUNT\n  ID   161\n  NAME Beruházás;\n% 38 Pénz\n");
+        let note = Note::from_raw_note(raw, true, &NoteContext::default()).unwrap();
+        assert_eq!(note.accounts[0].id, "161");
+        assert_eq!(note.accounts[0].name, "Beruházás");
+        // Location of the ID, used by go to definition
+        assert_eq!(note.accounts[0].span, Span::new(2, 8, 3));
+        assert_eq!(note.accounts[1].span, Span::new(4, 3, 2));
+        assert_eq!(note.accounts.len(), 2);
+
+        let raw = crate::parser::parse(
+            "ACCOUNT ID 5 NAME Költség PARENT T5 TYPE expense;\n% 9 Értékesítés árbevétele TYPE Revenue\n",
+        );
+        let note = Note::from_raw_note(raw, true, &NoteContext::default()).unwrap();
+        assert_eq!(note.accounts[0].parent.as_deref(), Some("T5"));
+        assert_eq!(note.accounts[0].account_type, Some(AccountType::Expense));
+        assert_eq!(note.accounts[1].name, "Értékesítés árbevétele");
+        assert_eq!(note.accounts[1].account_type, Some(AccountType::Revenue));
+
+        let raw = crate::parser::parse("% 9 Bevétel TYPE income\n");
+        let err = Note::from_raw_note(raw, true, &NoteContext::default()).unwrap_err();
+        assert_eq!(err.iter().next().unwrap().code(), Code::InvalidAccountType);
+    }
+
+    #[test]
+    fn test_named_statement_errors() {
+        let raw = crate::parser::parse(
+            "ID 1\nTRANSACTION\n  DEBIT 161\n  DEBIT 38;\nTRANSACTION DEBIT 161 CREDIT 38;\nTRANSACTION\n  DEBIT 161\n  CREDIT 38\n  AMOUNT 5\nNET 1 ; 2\n",
+        );
+        let err = Note::from_raw_note(raw, false, &NoteContext::default()).unwrap_err();
+        let res = err
+            .iter()
+            .map(|d| (d.code(), d.span().unwrap().line))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            res,
+            [
+                (Code::DuplicateKey, 4),
+                (Code::MissingKey, 5),
+                (Code::UnterminatedStatement, 6),
+                (Code::UnexpectedToken, 10),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_collect_all_errors() {
+        let raw = crate::parser::parse("NET x\nVAT y\nCOMPLETION_DATE 2022-13-01\n");
+        let err = Note::from_raw_note(raw, false, &NoteContext::default()).unwrap_err();
+        let codes = err.iter().map(|d| d.code()).collect::<Vec<Code>>();
+        assert_eq!(
+            codes,
+            vec![
+                Code::InvalidNumber,
+                Code::InvalidNumber,
+                Code::InvalidDate,
+                Code::MissingId
+            ]
+        );
+    }
 }
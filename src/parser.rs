@@ -1,11 +1,12 @@
-use std::{
-    default,
-    ops::Not,
-    path::{Path, PathBuf},
-    str::Bytes,
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+use crate::{
+    diagnostic::{Code, Diagnostic, Diagnostics, Span},
+    tr,
 };
 
-#[derive(Default, Debug)]
+#[derive(Default, Debug, Serialize)]
 pub struct NoteRaw {
     file_path: PathBuf,
     lines: Vec<Line>,
@@ -17,9 +18,10 @@ impl NoteRaw {
     fn add_line(&mut self, new_line: Line) {
         self.lines.push(new_line);
     }
-    pub fn from_file(file_path: &Path) -> Result<Self, String> {
-        let contents =
-            std::fs::read_to_string(&file_path).expect("Something went wrong reading the file");
+    pub fn from_file(file_path: &Path) -> Result<Self, Diagnostic> {
+        let contents = std::fs::read_to_string(file_path).map_err(|e| {
+            Diagnostic::error(Code::Io, tr!("Cannot read note file: {}", e)).with_path(file_path)
+        })?;
 
         let mut note_raw: NoteRaw = NoteRaw::default();
         note_raw.file_path = file_path.to_owned();
@@ -27,8 +29,29 @@ impl NoteRaw {
             let new_line = Line::from_raw_line(LineRaw::new(line.0, line.1.to_string()));
             note_raw.add_line(new_line);
         }
+        note_raw.is_signed = note_raw
+            .lines
+            .iter()
+            .rev()
+            .find(|line| !line.tokens.is_empty())
+            .is_some_and(Line::is_signature);
         Ok(note_raw)
     }
+    /// Lines till the end of the given signed revision, 1 based
+    pub fn until_revision(mut self, revision: usize) -> Self {
+        let end = self
+            .lines
+            .iter()
+            .enumerate()
+            .filter(|(_, line)| line.is_signature())
+            .nth(revision.saturating_sub(1))
+            .map(|(i, _)| i + 1);
+        if let Some(end) = end {
+            self.lines.truncate(end);
+            self.is_signed = true;
+        }
+        self
+    }
     pub fn is_signed(&self) -> bool {
         self.is_signed
     }
@@ -42,6 +65,16 @@ impl NoteRaw {
     pub fn lines_ref(&self) -> &Vec<Line> {
         &self.lines
     }
+    /// Tokenizer errors of all lines
+    pub fn diagnostics(&self) -> Diagnostics {
+        let mut res = Diagnostics::new();
+        for line in &self.lines {
+            if let Some(error) = line.error() {
+                res.push(error.clone().with_path(&self.file_path));
+            }
+        }
+        res
+    }
     pub fn lines(self) -> Vec<Line> {
         self.lines
     }
@@ -59,42 +92,166 @@ impl LineRaw {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
 pub struct Line {
     raw: String,
     tokens: Vec<Token>,
+    comment: Option<Comment>,
+    error: Option<Diagnostic>,
 }
 
 impl Line {
     fn from_raw_line(raw_line: LineRaw) -> Self {
+        let raw = raw_line.raw.clone();
+        let (tokens, comment, error) = tokenize_line(raw_line);
         Self {
-            raw: raw_line.raw.clone(),
-            tokens: tokenize_line(raw_line),
+            raw,
+            tokens,
+            comment,
+            error,
         }
     }
+    /// Line starts with SIGNATURE or !
+    pub fn is_signature(&self) -> bool {
+        matches!(
+            self.tokens.first().map(|t| t.token_kind_ref()),
+            Some(TokenKind::Command(Command::Signature))
+        )
+    }
     pub fn raw(&self) -> &str {
         &self.raw
     }
+    pub fn comment(&self) -> Option<&Comment> {
+        self.comment.as_ref()
+    }
+    /// Tokenizer error e.g. unterminated string
+    pub fn error(&self) -> Option<&Diagnostic> {
+        self.error.as_ref()
+    }
     pub fn tokens_ref(&self) -> &Vec<Token> {
         &self.tokens
     }
     pub fn tokens(self) -> Vec<Token> {
         self.tokens
     }
+    /// Tokens as statement parameters, leading command turned back to text
+    ///
+    /// Used for continuation lines of multi-line statements.
+    pub fn param_tokens(&self) -> Vec<Token> {
+        let mut tokens = self.tokens.clone();
+        if let Some(first_token) = tokens.first_mut() {
+            if let TokenKind::Command(_) = first_token.token_kind {
+                let span = first_token.span;
+                let text = self
+                    .raw
+                    .chars()
+                    .skip(span.column - 1)
+                    .take(span.len)
+                    .collect();
+                first_token.token_kind = TokenKind::Text(text);
+            }
+        }
+        tokens
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CommentKind {
+    /// Comment line starting with #
+    Block,
+    /// Comment after // till the end of the line
+    Line,
 }
 
-fn tokenize_line(raw_line: LineRaw) -> Vec<Token> {
+/// Comment on a line
+///
+/// Comments are not part of the tokens, but kept for formatters and tools.
+#[derive(Debug, Clone, Serialize)]
+pub struct Comment {
+    kind: CommentKind,
+    text: String,
+    span: Span,
+}
+
+impl Comment {
+    pub fn kind(&self) -> CommentKind {
+        self.kind
+    }
+    /// Comment text without the comment marker
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+    /// Span of the whole comment including its marker
+    pub fn span(&self) -> Span {
+        self.span
+    }
+}
+
+fn tokenize_line(raw_line: LineRaw) -> (Vec<Token>, Option<Comment>, Option<Diagnostic>) {
     let mut tokens: Vec<Token> = vec![];
     let mut temp_token: Option<Token> = None;
     let mut token_is_inner = false;
+    let mut is_escaped = false;
+    let mut quote_start = 0;
+    let mut comment: Option<Comment> = None;
+    let mut error: Option<Diagnostic> = None;
+
+    let chars = raw_line.raw.chars().collect::<Vec<char>>();
+    let line_number = raw_line.line_number + 1;
+
+    // Block comment line
+    let trimmed = raw_line.raw.trim_start();
+    if let Some(text) = trimmed.strip_prefix('#') {
+        let column = chars.len() - trimmed.chars().count() + 1;
+        let comment = Comment {
+            kind: CommentKind::Block,
+            text: text.to_string(),
+            span: Span::new(line_number, column, chars.len() + 1 - column),
+        };
+        return (tokens, Some(comment), error);
+    }
 
-    for (char_pos, ch) in raw_line.raw.chars().enumerate() {
+    for (char_pos, &ch) in chars.iter().enumerate() {
+        // Escape sequence inside quoted text
+        if is_escaped {
+            let t = temp_token.as_mut().unwrap();
+            if ch != '"' && ch != '\\' {
+                t.append_char('\\');
+            }
+            t.append_char(ch);
+            is_escaped = false;
+            continue;
+        }
+        if ch == '\\' && token_is_inner {
+            is_escaped = true;
+            continue;
+        }
+        // Line comment till the end of the line, it starts only at a token
+        // boundary, so URLs and accounts like K454//x are kept
+        let at_boundary = temp_token.is_none()
+            || char_pos
+                .checked_sub(1)
+                .is_some_and(|prev| chars[prev].is_whitespace() || chars[prev] == '"');
+        if ch == '/' && !token_is_inner && at_boundary && chars.get(char_pos + 1) == Some(&'/') {
+            comment = Some(Comment {
+                kind: CommentKind::Line,
+                text: chars[char_pos + 2..].iter().collect(),
+                span: Span::new(line_number, char_pos + 1, chars.len() - char_pos),
+            });
+            if let Some(mut t) = temp_token.take() {
+                t.close(char_pos);
+                tokens.push(t);
+            }
+            break;
+        }
         match ch {
             ' ' => match token_is_inner {
                 true => temp_token.as_mut().unwrap().append_char(ch),
                 false => {
                     let t = temp_token.take();
-                    if let Some(t) = t {
+                    if let Some(mut t) = t {
+                        t.close(char_pos);
                         tokens.push(t);
                     }
                 }
@@ -102,10 +259,11 @@ fn tokenize_line(raw_line: LineRaw) -> Vec<Token> {
             '"' => match token_is_inner {
                 false => {
                     token_is_inner = true;
-                    temp_token = Some(Token::new(
-                        (raw_line.line_number + 1, char_pos + 1),
-                        TokenKind::Text(String::new()),
-                    ));
+                    quote_start = char_pos + 1;
+                    let t = temp_token.get_or_insert_with(|| {
+                        Token::new((line_number, char_pos + 1), TokenKind::Text(String::new()))
+                    });
+                    t.quoted = true;
                     continue;
                 }
                 true => {
@@ -113,6 +271,16 @@ fn tokenize_line(raw_line: LineRaw) -> Vec<Token> {
                     continue;
                 }
             },
+            // Statement terminator is always a token on its own
+            ';' if !token_is_inner => {
+                if let Some(mut t) = temp_token.take() {
+                    t.close(char_pos);
+                    tokens.push(t);
+                }
+                let mut t = Token::new((line_number, char_pos + 1), TokenKind::Semicolon);
+                t.close(char_pos + 1);
+                tokens.push(t);
+            }
             x => match token_is_inner {
                 false => match &mut temp_token {
                     Some(t) => {
@@ -121,10 +289,8 @@ fn tokenize_line(raw_line: LineRaw) -> Vec<Token> {
                     None => {
                         let mut s = String::new();
                         s.push(x);
-                        temp_token = Some(Token::new(
-                            (raw_line.line_number + 1, char_pos + 1),
-                            TokenKind::Text(s),
-                        ));
+                        temp_token =
+                            Some(Token::new((line_number, char_pos + 1), TokenKind::Text(s)));
                     }
                 },
                 true => temp_token.as_mut().unwrap().append_char(x),
@@ -132,16 +298,30 @@ fn tokenize_line(raw_line: LineRaw) -> Vec<Token> {
         }
     }
 
+    // Quoted text must be closed in the same line
+    if token_is_inner {
+        error = Some(
+            Diagnostic::error(Code::UnterminatedString, tr!("Unterminated string"))
+                .with_span(Span::new(line_number, quote_start, 1))
+                .with_hint(tr!(
+                    "Close the text with \", use \\\" to write a quote inside"
+                )),
+        );
+    }
+
     let t = temp_token.take();
-    if let Some(t) = t {
+    if let Some(mut t) = t {
+        t.close(chars.len());
         tokens.push(t);
     }
 
     if let Some(first_token) = tokens.get_mut(0) {
         match &mut first_token.token_kind {
-            TokenKind::Command(_) => (),
+            TokenKind::Command(_) | TokenKind::Semicolon => (),
+            // Quoted text is never a command
+            TokenKind::Text(_) if first_token.quoted => (),
             TokenKind::Text(t) => {
-                let c = Command::parse(&t);
+                let c = Command::parse(t);
                 match c {
                     Command::Unknown => (),
                     x => first_token.token_kind = TokenKind::Command(x),
@@ -150,30 +330,46 @@ fn tokenize_line(raw_line: LineRaw) -> Vec<Token> {
         }
     }
 
-    tokens
+    (tokens, comment, error)
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize)]
 pub struct Token {
-    position: (usize, usize),
+    span: Span,
     token_kind: TokenKind,
+    quoted: bool,
 }
 
 impl Token {
     fn new(position: (usize, usize), token_kind: TokenKind) -> Self {
         Self {
-            position,
+            span: Span::new(position.0, position.1, 0),
             token_kind,
+            quoted: false,
         }
     }
+    // Set token length by its end char position (exclusive, 0 based)
+    fn close(&mut self, end_char_pos: usize) {
+        self.span.len = end_char_pos + 1 - self.span.column;
+    }
     fn append_char(&mut self, ch: char) {
         match &mut self.token_kind {
-            TokenKind::Command(_) => (),
+            TokenKind::Command(_) | TokenKind::Semicolon => (),
             TokenKind::Text(t) => t.push(ch),
         }
     }
     pub fn position(&self) -> (usize, usize) {
-        self.position
+        (self.span.line, self.span.column)
+    }
+    pub fn span(&self) -> Span {
+        self.span
+    }
+    /// Token (or part of it) was written between quotes
+    pub fn is_quoted(&self) -> bool {
+        self.quoted
+    }
+    pub fn is_semicolon(&self) -> bool {
+        matches!(self.token_kind, TokenKind::Semicolon)
     }
     pub fn token_kind_ref(&self) -> &TokenKind {
         &self.token_kind
@@ -181,30 +377,41 @@ impl Token {
     pub fn token_kind(self) -> TokenKind {
         self.token_kind
     }
+    /// Text token with the same quoting, used by template expansion
+    pub fn with_text(&self, text: String, span: Span) -> Self {
+        Self {
+            span,
+            token_kind: TokenKind::Text(text),
+            quoted: self.quoted,
+        }
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "kind", content = "value", rename_all = "lowercase")]
 pub enum TokenKind {
     Command(Command),
     Text(String),
+    /// Statement terminator ;
+    Semicolon,
 }
 
 impl TokenKind {
     pub fn take_text_string(self) -> Option<String> {
         match self {
-            TokenKind::Command(_) => None,
+            TokenKind::Command(_) | TokenKind::Semicolon => None,
             TokenKind::Text(text) => Some(text),
         }
     }
     pub fn take_text_string_ref(&self) -> Option<&String> {
         match self {
-            TokenKind::Command(_) => None,
+            TokenKind::Command(_) | TokenKind::Semicolon => None,
             TokenKind::Text(text) => Some(text),
         }
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Command {
     Alias,
     Id,
@@ -220,32 +427,113 @@ pub enum Command {
     Transaction,
     Signature,
     Account,
+    Reference,
+    Event,
+    Title,
+    TitleBlock,
+    Date,
+    Source,
+    Type,
+    Tag,
+    Include,
+    Template,
+    Use,
     Unknown,
 }
 
+// Keywords of the commands, the first one is the canonical form
+const KEYWORDS: &[(&str, Command)] = &[
+    ("ALIAS", Command::Alias),
+    ("ID", Command::Id),
+    ("DOCID", Command::Docid),
+    ("AUTHOR", Command::Author),
+    ("PAYMENT_KIND", Command::PaymentKind),
+    ("NET", Command::Net),
+    ("GROSS", Command::Gross),
+    ("VAT", Command::Vat),
+    ("ISSUE_DATE", Command::IssueDate),
+    ("COMPLETION_DATE", Command::CompletionDate),
+    ("DUEDATE", Command::DueDate),
+    ("TRANSACTION", Command::Transaction),
+    (">", Command::Transaction),
+    ("SIGNATURE", Command::Signature),
+    ("!", Command::Signature),
+    ("ACCOUNT", Command::Account),
+    ("%", Command::Account),
+    ("REFERENCE", Command::Reference),
+    ("EVENT", Command::Event),
+    ("TITLE", Command::Title),
+    ("---", Command::TitleBlock),
+    ("DATE", Command::Date),
+    ("SOURCE", Command::Source),
+    ("TYPE", Command::Type),
+    ("TAG", Command::Tag),
+    ("INCLUDE", Command::Include),
+    ("TEMPLATE", Command::Template),
+    ("USE", Command::Use),
+];
+
+// Hungarian keyword aliases, accepted besides the English keywords
+const HU_KEYWORDS: &[(&str, Command)] = &[
+    ("ÁLNÉV", Command::Alias),
+    ("AZONOSÍTÓ", Command::Id),
+    ("SZERZŐ", Command::Author),
+    ("FIZETÉSI_MÓD", Command::PaymentKind),
+    ("NETTÓ", Command::Net),
+    ("BRUTTÓ", Command::Gross),
+    ("ÁFA", Command::Vat),
+    ("KIÁLLÍTÁS", Command::IssueDate),
+    ("TELJESÍTÉS", Command::CompletionDate),
+    ("ESEDÉKESSÉG", Command::DueDate),
+    ("TRANZAKCIÓ", Command::Transaction),
+    ("ALÁÍRÁS", Command::Signature),
+    ("FŐKÖNYVI_SZÁMLA", Command::Account),
+    ("HIVATKOZÁS", Command::Reference),
+    ("ESEMÉNY", Command::Event),
+    ("CÍM", Command::Title),
+    ("DÁTUM", Command::Date),
+    ("FORRÁS", Command::Source),
+    ("TÍPUS", Command::Type),
+    ("CÍMKE", Command::Tag),
+];
+
+// Commands are serialized by their canonical keyword
+impl Serialize for Command {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.keyword())
+    }
+}
+
 impl Command {
+    // Aliases are accepted whatever the message language is, so a note
+    // parses the same for every user
     fn parse(f: &str) -> Self {
-        match f {
-            "ALIAS" => Self::Alias,
-            "ID" => Self::Id,
-            "DOCID" => Self::Docid,
-            "AUTHOR" => Self::Author,
-            "PAYMENT_KIND" => Self::PaymentKind,
-            "NET" => Self::Net,
-            "GROSS" => Self::Gross,
-            "VAT" => Self::Vat,
-            "ISSUE_DATE" => Self::IssueDate,
-            "COMPLETION_DATE" => Self::CompletionDate,
-            "DUEDATE" => Self::DueDate,
-            "TRANSACTION" | ">" => Self::Transaction,
-            "SIGNATURE" | "!" => Self::Signature,
-            "ACCOUNT" | "%" => Self::Account,
-            _ => Self::Unknown,
-        }
+        KEYWORDS
+            .iter()
+            .chain(HU_KEYWORDS)
+            .find(|(keyword, _)| *keyword == f)
+            .map(|(_, command)| *command)
+            .unwrap_or(Self::Unknown)
+    }
+    /// Canonical keywords of all the commands
+    pub fn keywords() -> Vec<&'static str> {
+        KEYWORDS
+            .iter()
+            .filter(|(keyword, command)| command.keyword() == *keyword)
+            .map(|(keyword, _)| *keyword)
+            .collect()
+    }
+    /// Canonical keyword of the command
+    pub fn keyword(&self) -> &'static str {
+        KEYWORDS
+            .iter()
+            .find(|(_, command)| command == self)
+            .map(|(keyword, _)| *keyword)
+            .unwrap_or_default()
     }
 }
 
-fn parse(note_string: &str) -> NoteRaw {
+pub(crate) fn parse(note_string: &str) -> NoteRaw {
     let mut note_raw: NoteRaw = NoteRaw::default();
     for line in note_string.lines().enumerate() {
         let new_line = Line::from_raw_line(LineRaw::new(line.0, line.1.to_string()));
@@ -259,6 +547,17 @@ mod tests {
     use super::*;
     use test::Bencher;
 
+    #[test]
+    fn test_keyword_aliases() {
+        assert_eq!(Command::parse("NETTÓ"), Command::Net);
+        assert_eq!(Command::parse("TELJESÍTÉS"), Command::CompletionDate);
+        assert_eq!(Command::parse("ÁFA"), Command::Vat);
+        assert_eq!(Command::parse("NET"), Command::Net);
+        assert_eq!(Command::parse("NETTO"), Command::Unknown);
+        assert_eq!(Command::Vat.keyword(), "VAT");
+        assert!(!Command::keywords().contains(&"BRUTTÓ"));
+    }
+
     #[test]
     fn test_preprocess() {
         let source = r#"---
@@ -325,4 +624,93 @@ DUEDATE 2022-01-31
         assert_eq!(true, true);
         println!("{:?}", parsed);
     }
+
+    #[test]
+    fn test_comments() {
+        let source = r#"# Block comment
+  # indented "block"
+> 161 3841 1_000 // Line comment
+% 3842 "Kriszti // Bank"//x"#;
+        let parsed = parse(source);
+        let lines = parsed.lines_ref();
+
+        assert!(lines[0].tokens_ref().is_empty());
+        let comment = lines[0].comment().unwrap();
+        assert_eq!(comment.kind(), CommentKind::Block);
+        assert_eq!(comment.text(), " Block comment");
+
+        assert!(lines[1].tokens_ref().is_empty());
+        assert_eq!(lines[1].comment().unwrap().span(), Span::new(2, 3, 18));
+
+        assert_eq!(lines[2].tokens_ref().len(), 4);
+        let comment = lines[2].comment().unwrap();
+        assert_eq!(comment.kind(), CommentKind::Line);
+        assert_eq!(comment.text(), " Line comment");
+        assert_eq!(comment.span(), Span::new(3, 18, 15));
+
+        let tokens = lines[3].tokens_ref();
+        assert_eq!(tokens.len(), 3);
+        assert_eq!(
+            tokens[2].token_kind_ref().take_text_string_ref().unwrap(),
+            "Kriszti // Bank"
+        );
+        assert_eq!(lines[3].comment().unwrap().text(), "x");
+
+        let parsed = parse("SOURCE https://example.com // web\n> K454//x 161 5");
+        let lines = parsed.lines_ref();
+        let texts = |line: &Line| {
+            line.tokens_ref()
+                .iter()
+                .filter_map(|t| t.token_kind_ref().take_text_string_ref().cloned())
+                .collect::<Vec<String>>()
+        };
+        assert_eq!(texts(&lines[0]).last().unwrap(), "https://example.com");
+        assert_eq!(lines[0].comment().unwrap().text(), " web");
+        assert_eq!(texts(&lines[1]), ["K454//x", "161", "5"]);
+        assert!(lines[1].comment().is_none());
+    }
+
+    #[test]
+    fn test_escaped_string() {
+        let parsed = parse(r#"% 3842 "Kriszti \"B\" Bank" "a\\b" "ID""#);
+        let tokens = parsed.lines_ref()[0].tokens_ref();
+        let texts = tokens[1..]
+            .iter()
+            .map(|t| t.token_kind_ref().take_text_string_ref().unwrap().as_str())
+            .collect::<Vec<&str>>();
+        assert_eq!(texts, vec!["3842", "Kriszti \"B\" Bank", "a\\b", "ID"]);
+        assert_eq!(tokens[2].span(), Span::new(1, 8, 20));
+        assert!(parsed.diagnostics().is_empty());
+    }
+
+    #[test]
+    fn test_semicolon() {
+        let parsed = parse("TRANSACTION\n  DEBIT 161 CREDIT \"a;b\";\nID x;");
+        let lines = parsed.lines_ref();
+        let last = lines[1].tokens_ref().last().unwrap();
+        assert!(last.is_semicolon());
+        assert_eq!(last.span(), Span::new(2, 25, 1));
+        assert_eq!(
+            lines[1].tokens_ref()[3]
+                .token_kind_ref()
+                .take_text_string_ref(),
+            Some(&"a;b".to_string())
+        );
+        // Continuation line command is text
+        let params = lines[2].param_tokens();
+        assert_eq!(
+            params[0].token_kind_ref().take_text_string_ref(),
+            Some(&"ID".to_string())
+        );
+        assert!(params[2].is_semicolon());
+    }
+
+    #[test]
+    fn test_unterminated_string() {
+        let parsed = parse(r#"% 3842 "Kriszti Bank"#);
+        let diagnostics = parsed.diagnostics();
+        let error = diagnostics.iter().next().unwrap();
+        assert_eq!(error.code(), Code::UnterminatedString);
+        assert_eq!(error.span(), Some(Span::new(1, 8, 1)));
+    }
 }
@@ -1,14 +1,20 @@
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeSet, HashMap},
     env,
     path::{Path, PathBuf},
 };
 
 use crate::{
+    chart::Chart,
+    diagnostic::{Code, Diagnostic, Diagnostics},
     fs::Doc,
-    ledger::{Account, Ledger, LedgerIndexItem},
-    note::Note,
+    i18n::set_language,
+    include::load_account_files,
+    ledger::{Account, Ledger},
+    note::{Note, NoteContext},
+    parser::Command,
     settings::Settings,
+    tr,
 };
 
 #[derive(Default, Debug)]
@@ -16,48 +22,176 @@ pub struct Project {
     project_root_path: PathBuf,
     settings: Settings,
     docs: Vec<Doc>,
-    accounts_index: HashSet<String>,
+    chart: Chart,
     pub accounts: Vec<Account>,
     notes: HashMap<String, Note>,
+    note_files: Vec<PathBuf>,
+    account_files: Vec<PathBuf>,
+    note_context: NoteContext,
     pub ledger: Ledger,
 }
 
 impl Project {
-    pub fn try_init() -> Result<Self, String> {
+    /// Init project and fail if it has any error
+    pub fn try_init() -> Result<Self, Diagnostics> {
+        let (project, diagnostics) = Self::inspect()?;
+        match diagnostics.has_errors() {
+            true => Err(diagnostics),
+            false => Ok(project),
+        }
+    }
+    /// Load project and collect every diagnostic
+    ///
+    /// Only workspace and config errors abort, all the account, doc,
+    /// note and ledger errors are collected and returned with the project.
+    pub fn inspect() -> Result<(Self, Diagnostics), Diagnostic> {
         // Try to get current working dir
-        let current_dir =
-            env::current_dir().map_err(|_| "Current working dir does not exist".to_string())?;
+        let current_dir = env::current_dir().map_err(|_| {
+            Diagnostic::error(Code::Config, tr!("Current working dir does not exist"))
+        })?;
 
         let mut project: Project = Project::default();
+        let mut diagnostics = Diagnostics::new();
 
         // Try to get project dir & set it
         project.project_root_path = get_project_dir(&current_dir)?;
 
         // Try to deserialize its contetn
         project.settings = crate::settings::Settings::try_read(&project.project_root_path)?;
+        // Messages of the project language
+        if let Some(language) = project.settings.language() {
+            set_language(language);
+        }
+        project.note_context = NoteContext {
+            money_format: project.settings.money_format(),
+            validation: project.settings.validation.clone(),
+            ..Default::default()
+        };
         // Init accounts
-        project.try_init_accounts()?;
+        if let Err(e) = project.try_init_accounts() {
+            diagnostics.extend(e);
+        }
         // Init docs
-        project.docs = crate::fs::get_files_recours(
+        match crate::fs::get_files_recours(
             Path::new(&project.settings.dependencies.docs_path),
             None,
-        )?;
+        ) {
+            Ok(docs) => project.docs = docs,
+            Err(e) => diagnostics.push(e),
+        }
         // Init ledger
-        project.ledger.init(&project.accounts_index);
+        project.ledger.init(&project.chart);
         // Init notes
         let note_docs = crate::fs::get_files_recours(
             Path::new(&project.settings.dependencies.notes_path),
             Some("bit"),
         )?;
+        let mut notes = Vec::new();
         for note_doc in note_docs {
-            let note: Note = Note::from_file(note_doc.relative_path(), false)?;
-            project.ledger.add_note(&note, &project.accounts_index)?;
+            project
+                .note_files
+                .push(note_doc.relative_path().to_path_buf());
+            match Note::from_file(note_doc.relative_path(), false, &project.note_context) {
+                Ok(mut note) => {
+                    note.warnings.drain(..).for_each(|w| diagnostics.push(w));
+                    notes.push(note)
+                }
+                Err(e) => diagnostics.extend(e),
+            }
+        }
+        // Notes with a known ID are left out of the ledger as well
+        let (notes, duplicates) = unique_notes(notes);
+        duplicates.into_iter().for_each(|d| diagnostics.push(d));
+        for e in project.settings.namespaces().check(notes.iter()) {
+            diagnostics.push(e);
+        }
+        // Create used sub-accounts before posting, so leaf checks
+        // see the whole chart
+        for note in &notes {
+            for transaction in &note.transactions {
+                for leg in &transaction.legs {
+                    project.chart.resolve(&leg.account);
+                }
+            }
+        }
+        for note in notes {
+            for transaction in &note.transactions {
+                if let Some(warning) = project.chart.check_transaction(transaction) {
+                    diagnostics.push(warning.with_path(&note.path));
+                }
+            }
+            if let Err(e) = project.ledger.add_note(&note, &project.chart) {
+                diagnostics.extend(e);
+            }
             project.notes.insert(note.id.clone().unwrap(), note);
         }
+        // Balances on the unusual side, e.g. negative cash
+        let acc_file = project
+            .project_root_path
+            .join(&project.settings.dependencies.accounts_path);
+        for warning in project.ledger.unusual_balances(&project.chart) {
+            diagnostics.push(warning.with_path(&acc_file));
+        }
 
-        Ok(project)
+        Ok((project, diagnostics))
+    }
+    /// Chart of accounts with the sub-accounts used by notes
+    pub fn chart(&self) -> &Chart {
+        &self.chart
+    }
+    pub fn notes(&self) -> &HashMap<String, Note> {
+        &self.notes
+    }
+    pub fn settings(&self) -> &Settings {
+        &self.settings
+    }
+    /// Note files, including the ones having errors
+    pub fn note_files(&self) -> &[PathBuf] {
+        &self.note_files
+    }
+    /// Account files with the included ones
+    pub fn account_files(&self) -> &[PathBuf] {
+        &self.account_files
+    }
+    /// Next free note ID, in a namespace or as a plain number
+    pub fn next_note_id(&self, namespace: Option<&str>) -> Result<String, Diagnostic> {
+        let namespaces = self.settings.namespaces();
+        let ids = self.notes.keys().map(String::as_str);
+        match namespace {
+            Some(namespace) => namespaces.next_id(namespace, ids),
+            None if namespaces.is_empty() => {
+                let last = ids.filter_map(|id| id.parse::<u64>().ok()).max();
+                Ok((last.unwrap_or(0) + 1).to_string())
+            }
+            None => Err(
+                Diagnostic::error(Code::Config, tr!("Note IDs must have a namespace"))
+                    .with_hint(tr!("Choose one by --namespace")),
+            ),
+        }
+    }
+    /// Create note template with the next free ID, namespaced notes
+    /// are placed in the directory of their namespace
+    pub fn create_note(&self, namespace: Option<&str>) -> Result<PathBuf, Diagnostic> {
+        let id = self.next_note_id(namespace)?;
+        let path = self
+            .project_root_path
+            .join(&self.settings.dependencies.notes_path)
+            .join(format!("{}.bit", id));
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).map_err(|e| {
+                Diagnostic::error(Code::Io, tr!("Cannot create directory: {}", e)).with_path(dir)
+            })?;
+        }
+        let mut note = Note::new_template(path.clone());
+        note.id = Some(id);
+        note.write_to_file()?;
+        Ok(path)
+    }
+    /// Context to parse notes outside of the project loading
+    pub fn note_context(&self) -> &NoteContext {
+        &self.note_context
     }
-    fn try_init_accounts(&mut self) -> Result<(), String> {
+    fn try_init_accounts(&mut self) -> Result<(), Diagnostics> {
         // Try load accounts file
         let acc_file = self
             .project_root_path
@@ -65,25 +199,163 @@ impl Project {
         // Check if it exist
         match acc_file.exists() || acc_file.is_file() {
             true => (),
-            false => return Err("No account file found!".to_string()),
+            false => {
+                return Err(
+                    Diagnostic::error(Code::Config, tr!("No account file found!"))
+                        .with_path(&acc_file)
+                        .into(),
+                )
+            }
         }
-        let note = Note::from_file(&acc_file, true)?;
-        note.accounts.into_iter().for_each(|account| {
-            self.accounts_index.insert(account.id.clone());
+        // Account file or directory with its included files
+        let files = load_account_files(&acc_file, &self.note_context);
+        let mut diagnostics = files.diagnostics;
+        self.account_files = files.files;
+        // Templates are used by the notes
+        for template in files.templates {
+            if let Err(e) = self.note_context.templates.add(template) {
+                diagnostics.push(e);
+            }
+        }
+        for account in files.accounts {
+            self.chart.add(&account);
             self.accounts.push(account);
-        });
-        Ok(())
+        }
+        for e in self.chart.link() {
+            diagnostics.push(e);
+        }
+        self.chart.leaf_only = self.settings.leaf_only;
+        match diagnostics.is_empty() {
+            true => Ok(()),
+            false => Err(diagnostics),
+        }
+    }
+}
+
+// Notes with unique IDs, notes with an already used ID are errors.
+// ALIAS and DOCID are warned when they repeat for the same partner.
+fn unique_notes(notes: Vec<Note>) -> (Vec<Note>, Vec<Diagnostic>) {
+    let mut res: Vec<Note> = Vec::new();
+    let mut diagnostics = Vec::new();
+    let duplicate = |note: &Note, first: &Note, command: Command, value: &str| {
+        let span = note.history.span(command).unwrap_or_default();
+        let first_span = first.history.span(command).unwrap_or_default();
+        let message = tr!("Duplicate note {} {}", command.keyword(), value);
+        let diagnostic = match command {
+            Command::Id => Diagnostic::error(Code::DuplicateId, message),
+            _ => Diagnostic::warning(Code::DuplicateId, message),
+        };
+        diagnostic
+            .with_path(&note.path)
+            .with_span(span)
+            .with_hint(tr!(
+                "First defined at {}:{}:{}",
+                first.path.display(),
+                first_span.line,
+                first_span.column
+            ))
+    };
+    for note in notes {
+        let id = note.id.as_deref().unwrap_or_default();
+        if let Some(first) = res.iter().find(|n| n.id.as_deref() == Some(id)) {
+            diagnostics.push(duplicate(&note, first, Command::Id, id));
+            continue;
+        }
+        for command in [Command::Alias, Command::Docid] {
+            let Some(value) = note_field(&note, command) else {
+                continue;
+            };
+            let first = res
+                .iter()
+                .find(|n| note_field(n, command) == Some(value) && same_partner(n, &note));
+            if let Some(first) = first {
+                diagnostics.push(duplicate(&note, first, command, value));
+            }
+        }
+        res.push(note);
+    }
+    (res, diagnostics)
+}
+
+fn note_field(note: &Note, command: Command) -> Option<&str> {
+    match command {
+        Command::Alias => note.alias.as_deref(),
+        Command::Docid => note.docid.as_deref(),
+        _ => None,
+    }
+}
+
+// Partners are the sub-accounts posted by the notes, e.g. 454/agroker.
+// Notes without sub-accounts have the same unknown partner.
+fn same_partner(a: &Note, b: &Note) -> bool {
+    let partners = |note: &Note| {
+        note.transactions
+            .iter()
+            .flat_map(|t| &t.legs)
+            .filter(|leg| leg.account.contains('/'))
+            .map(|leg| leg.account.clone())
+            .collect::<BTreeSet<String>>()
+    };
+    let (a, b) = (partners(a), partners(b));
+    match a.is_empty() && b.is_empty() {
+        true => true,
+        false => !a.is_disjoint(&b),
     }
 }
 
 // Try to get BIT project root path
-fn get_project_dir(dir: &Path) -> Result<PathBuf, String> {
+fn get_project_dir(dir: &Path) -> Result<PathBuf, Diagnostic> {
     let p = dir.join(".bit");
     match p.exists() && p.is_dir() {
         true => Ok(dir.to_path_buf()),
-        false => get_project_dir(
-            dir.parent()
-                .ok_or("Given directory is not a BIT working directory".to_string())?,
-        ),
+        false => get_project_dir(dir.parent().ok_or_else(|| {
+            Diagnostic::error(
+                Code::Config,
+                tr!("Given directory is not a BIT working directory"),
+            )
+        })?),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diagnostic::Span;
+
+    fn note(path: &str, text: &str) -> Note {
+        let raw = crate::parser::parse(text);
+        let mut note = Note::from_raw_note(raw, false, &NoteContext::default()).unwrap();
+        note.path = PathBuf::from(path);
+        note
+    }
+
+    #[test]
+    fn test_unique_notes() {
+        let notes = vec![
+            note(
+                "a.bit",
+                "ID 1\nALIAS x\nDOCID 7\n> 5 454/agroker 1 CDATE 2022-01-01\n",
+            ),
+            note(
+                "b.bit",
+                "ID 2\nALIAS x\nDOCID 7\n> 5 454/mol 1 CDATE 2022-01-01\n",
+            ),
+            note(
+                "c.bit",
+                "ID 3\nDOCID 7\n> 5 454/agroker 1 CDATE 2022-01-01\n",
+            ),
+            note("d.bit", "\nID 1\n"),
+        ];
+        let (notes, diagnostics) = unique_notes(notes);
+        assert_eq!(notes.len(), 3);
+        assert_eq!(diagnostics.len(), 2);
+        // b.bit has the same ALIAS and DOCID for another partner
+        assert_eq!(diagnostics[0].message(), "Duplicate note DOCID 7");
+        assert_eq!(diagnostics[0].path(), Some(Path::new("c.bit")));
+        assert!(!diagnostics[0].is_error());
+        assert_eq!(diagnostics[1].message(), "Duplicate note ID 1");
+        assert!(diagnostics[1].is_error());
+        assert_eq!(diagnostics[1].span(), Some(Span::new(2, 1, 2)));
+        assert_eq!(diagnostics[1].hints(), ["First defined at a.bit:1:1"]);
     }
 }
@@ -0,0 +1,169 @@
+use std::collections::HashMap;
+
+use crate::{
+    chart::Chart,
+    diagnostic::Diagnostic,
+    ledger::{overflow, AccountType, LedgerIndexItem},
+    money::Money,
+};
+
+/// Financial statements built from account types
+///
+/// Every section lists the accounts of one type in tree order, balances
+/// are shown on their normal side. Accounts without type are left out.
+#[derive(Debug)]
+pub struct Report {
+    pub sections: Vec<Section>,
+    /// Revenue minus expense
+    pub result: Money,
+}
+
+#[derive(Debug)]
+pub struct Section {
+    pub account_type: AccountType,
+    pub lines: Vec<ReportLine>,
+    pub total: Money,
+}
+
+#[derive(Debug)]
+pub struct ReportLine {
+    pub id: String,
+    pub name: String,
+    /// Depth inside the section, topmost accounts of the type have depth 0
+    pub depth: usize,
+    pub amount: Money,
+}
+
+impl Report {
+    /// Build report from ledger items of a single day
+    pub fn new(
+        chart: &Chart,
+        items: &HashMap<String, LedgerIndexItem>,
+        precision: u32,
+    ) -> Result<Self, Diagnostic> {
+        let rollup = chart.rollup(items)?;
+        let sections = AccountType::ALL
+            .into_iter()
+            .map(|account_type| -> Result<Section, Diagnostic> {
+                let mut section = Section {
+                    account_type,
+                    lines: Vec::new(),
+                    total: Money::zero(precision),
+                };
+                for (account, _) in chart.tree() {
+                    if chart.account_type(&account.id) != Some(account_type) {
+                        continue;
+                    }
+                    // Ancestors of the same type, they already contain this account
+                    let depth = chart
+                        .ancestors(&account.id)
+                        .iter()
+                        .take_while(|id| chart.account_type(id) == Some(account_type))
+                        .count();
+                    let amount = match rollup.get(&account.id) {
+                        Some(item) => item.natural_balance(account_type.normal_side())?,
+                        None => Money::zero(precision),
+                    };
+                    if depth == 0 {
+                        section.total = section.total.checked_add(&amount).ok_or_else(overflow)?;
+                    }
+                    section.lines.push(ReportLine {
+                        id: account.id.clone(),
+                        name: account.name.clone(),
+                        depth,
+                        amount,
+                    });
+                }
+                Ok(section)
+            })
+            .collect::<Result<Vec<Section>, Diagnostic>>()?;
+        let mut report = Self {
+            sections,
+            result: Money::zero(precision),
+        };
+        report.result = report
+            .section(AccountType::Revenue)
+            .total
+            .checked_sub(&report.section(AccountType::Expense).total)
+            .ok_or_else(overflow)?;
+        Ok(report)
+    }
+    pub fn section(&self, account_type: AccountType) -> &Section {
+        self.sections
+            .iter()
+            .find(|s| s.account_type == account_type)
+            .expect("Every account type has a section")
+    }
+    /// Liabilities, equity and the result of the year
+    pub fn total_sources(&self) -> Result<Money, Diagnostic> {
+        self.section(AccountType::Liability)
+            .total
+            .checked_add(&self.section(AccountType::Equity).total)
+            .and_then(|sum| sum.checked_add(&self.result))
+            .ok_or_else(overflow)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        ledger::{Account, Ledger},
+        note::{Note, Transaction},
+    };
+    use chrono::{Datelike, NaiveDate, Utc};
+
+    #[test]
+    fn test_report() {
+        let mut chart = Chart::default();
+        for (id, account_type) in [
+            ("3", Some(AccountType::Asset)),
+            ("38", None),
+            ("3841", None),
+            ("4", Some(AccountType::Equity)),
+            ("9", Some(AccountType::Revenue)),
+            ("5", Some(AccountType::Expense)),
+            ("T", None),
+        ] {
+            let mut account = Account::new(id.to_string(), String::new());
+            account.account_type = account_type;
+            chart.add(&account);
+        }
+        chart.link();
+        let mut ledger = Ledger::default();
+        ledger.init(&chart);
+        let day = NaiveDate::from_ymd_opt(Utc::now().year(), 1, 10).unwrap();
+        let note = Note {
+            completion_date: Some(day),
+            transactions: vec![
+                Transaction::simple("3841", "4", Money::new(1000, 0)),
+                Transaction::simple("3841", "9", Money::new(300, 0)),
+                Transaction::simple("5", "3841", Money::new(100, 0)),
+                Transaction::simple("T", "3841", Money::new(50, 0)),
+            ],
+            ..Default::default()
+        };
+        ledger.add_note(&note, &chart).unwrap();
+
+        let items = ledger.get_ledger_by_date(ledger.last_day()).unwrap();
+        let report = Report::new(&chart, &items, 0).unwrap();
+        let assets = report.section(AccountType::Asset);
+        let lines = assets
+            .lines
+            .iter()
+            .map(|l| (l.id.as_str(), l.depth, l.amount))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            lines,
+            [
+                ("3", 0, Money::new(1150, 0)),
+                ("38", 1, Money::new(1150, 0)),
+                ("3841", 2, Money::new(1150, 0))
+            ]
+        );
+        assert_eq!(assets.total, Money::new(1150, 0));
+        assert_eq!(report.result, Money::new(200, 0));
+        // Untyped account T is not part of the statements
+        assert_eq!(report.total_sources().unwrap(), Money::new(1200, 0));
+    }
+}
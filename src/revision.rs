@@ -0,0 +1,318 @@
+use serde::Serialize;
+use std::fmt::Display;
+
+use crate::{
+    diagnostic::{Code, Diagnostic, Span},
+    parser::Command,
+    signature::lines_signature,
+    tr,
+    validate::Rule,
+};
+
+/// Revision history of a note
+///
+/// A note is a sequence of revisions, each closed by a SIGNATURE. Later
+/// revisions override fields and add transactions, lines after the last
+/// signature form an unsigned revision. The note itself is the effective
+/// state after all of its revisions.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct History {
+    revisions: Vec<Revision>,
+}
+
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct Revision {
+    /// 1 based
+    pub number: usize,
+    pub changes: Vec<Change>,
+    pub signature: Option<Signature>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Signature {
+    pub timestamp: Option<String>,
+    pub hash: String,
+    pub span: Span,
+    /// Hash matches the lines before the signature
+    pub valid: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeKind {
+    /// Field set first
+    Set,
+    /// Field set by an earlier revision
+    Override,
+    /// New item, e.g. transaction or event
+    Add,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Change {
+    pub command: Command,
+    /// Parameters as written
+    pub text: String,
+    pub span: Span,
+    pub kind: ChangeKind,
+}
+
+impl Display for Change {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let kind = match self.kind {
+            ChangeKind::Set => "set",
+            ChangeKind::Override => "override",
+            ChangeKind::Add => "add",
+        };
+        write!(f, "{} {} {}", kind, self.command.keyword(), self.text)
+    }
+}
+
+impl History {
+    pub fn revisions(&self) -> &[Revision] {
+        &self.revisions
+    }
+    /// Span of the last change of a command, e.g. the effective NET
+    pub fn span(&self, command: Command) -> Option<Span> {
+        self.revisions
+            .iter()
+            .flat_map(|r| &r.changes)
+            .rev()
+            .find(|c| c.command == command)
+            .map(|c| c.span)
+    }
+    /// Record an applied command in the current revision
+    pub fn record(&mut self, command: Command, text: String, span: Span) {
+        let kind = match command {
+            Command::Transaction
+            | Command::Use
+            | Command::Reference
+            | Command::Event
+            | Command::Tag
+            | Command::Account
+            | Command::Include
+            | Command::Template => ChangeKind::Add,
+            _ if self.revisions.iter().any(|r| r.sets(command)) => ChangeKind::Override,
+            _ => ChangeKind::Set,
+        };
+        self.current().changes.push(Change {
+            command,
+            text,
+            span,
+            kind,
+        });
+    }
+    /// Close the current revision, the next changes start a new one
+    pub fn sign(&mut self, signature: Signature) {
+        self.current().signature = Some(signature);
+        let number = self.revisions.len() + 1;
+        self.revisions.push(Revision {
+            number,
+            ..Default::default()
+        });
+    }
+    // Open revision, the first one is created on demand
+    fn current(&mut self) -> &mut Revision {
+        if self.revisions.is_empty() {
+            self.revisions.push(Revision {
+                number: 1,
+                ..Default::default()
+            });
+        }
+        self.revisions.last_mut().unwrap()
+    }
+    /// Drop the empty unsigned revision after the last signature
+    pub fn close(&mut self) {
+        if self
+            .revisions
+            .last()
+            .is_some_and(|r| r.signature.is_none() && r.changes.is_empty())
+        {
+            self.revisions.pop();
+        }
+    }
+    /// Check signatures against the note lines, and fields set twice
+    /// in the same revision. Broken signatures are reported by the
+    /// signature rule.
+    pub fn check(&mut self, lines: &[&str], signature_rule: Rule) -> Vec<Diagnostic> {
+        let mut res = Vec::new();
+        for revision in &mut self.revisions {
+            for (i, change) in revision.changes.iter().enumerate() {
+                let is_field = change.kind != ChangeKind::Add;
+                if is_field
+                    && revision.changes[..i]
+                        .iter()
+                        .any(|c| c.command == change.command)
+                {
+                    res.push(
+                        Diagnostic::warning(
+                            Code::FieldRedefined,
+                            tr!(
+                                "{} is already set in this revision",
+                                change.command.keyword()
+                            ),
+                        )
+                        .with_span(change.span)
+                        .with_hint(tr!("Fields can be overridden after a SIGNATURE")),
+                    );
+                }
+            }
+            if let Some(signature) = &mut revision.signature {
+                // sign_note writes an empty line before the signature,
+                // it is not signed
+                let mut end = (signature.span.line - 1).min(lines.len());
+                if end > 0 && lines[end - 1].is_empty() {
+                    end -= 1;
+                }
+                signature.valid = lines_signature(&lines[..end]) == signature.hash;
+                if !signature.valid {
+                    let diagnostic = signature_rule.diagnostic(
+                        Code::InvalidSignature,
+                        tr!("Signature does not match the signed lines"),
+                        Some(signature.span),
+                    );
+                    res.extend(diagnostic.map(|d| {
+                        d.with_hint(tr!("Signed lines are changed, append corrections after it"))
+                    }));
+                }
+            }
+        }
+        res
+    }
+}
+
+impl Revision {
+    // Field is set in this revision
+    fn sets(&self, command: Command) -> bool {
+        self.changes.iter().any(|c| c.command == command)
+    }
+    pub fn is_signed(&self) -> bool {
+        self.signature.is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::note::{Note, NoteContext, PaymentKind};
+
+    #[test]
+    fn test_legacy_signatures() {
+        // Signatures written by earlier versions, some with spaces in
+        // the timestamp
+        let path = std::path::PathBuf::from("example/notes/demo.bit");
+        let err = Note::from_file(&path, false, &NoteContext::default()).unwrap_err();
+        let codes = err.iter().map(|d| d.code()).collect::<Vec<Code>>();
+        assert_eq!(codes, [Code::MissingId]);
+
+        let note = Note::from_file(&path, true, &NoteContext::default()).unwrap();
+        let signatures = note
+            .history
+            .revisions()
+            .iter()
+            .filter_map(|r| r.signature.as_ref())
+            .collect::<Vec<&Signature>>();
+        assert_eq!(signatures.len(), 13);
+        assert!(signatures.iter().all(|s| s.valid));
+        assert_eq!(signatures[0].timestamp, None);
+        assert_eq!(
+            signatures[5].timestamp.as_deref(),
+            Some("2022-05-19 15:24:01.896752 UTC")
+        );
+        assert_eq!(signatures[5].hash, "e793452ba277824883d65b5f63334d7a");
+        assert_eq!(
+            signatures[10].timestamp.as_deref(),
+            Some("2022-05-19 15:29:18.171480717")
+        );
+    }
+
+    #[test]
+    fn test_signed_lines() {
+        // Signed as sign_note does, the empty line it writes before the
+        // signature is not signed, earlier empty lines are
+        let hash = lines_signature(&["ID 1", ""]);
+        let text = format!("ID 1\n\n\nSIGNATURE {}\n", hash);
+        let note = Note::from_raw_note(crate::parser::parse(&text), false, &NoteContext::default())
+            .unwrap();
+        assert!(
+            note.history.revisions()[0]
+                .signature
+                .as_ref()
+                .unwrap()
+                .valid
+        );
+        let text = format!("ID 1\n\nSIGNATURE {}\n", hash);
+        let errors =
+            Note::from_raw_note(crate::parser::parse(&text), false, &NoteContext::default())
+                .unwrap_err();
+        assert!(errors.iter().any(|e| e.code() == Code::InvalidSignature));
+    }
+
+    #[test]
+    fn test_revisions() {
+        let first = "ID 1\nPAYMENT_KIND transfer\nCOMPLETION_DATE 2022-04-01\n> 5 454 100\n";
+        let hash = lines_signature(&first.lines().collect::<Vec<_>>());
+        let second = format!(
+            "{}\nSIGNATURE 2022-05-20T06:11:34+02:00 {}\nPAYMENT_KIND cash\n> 5 454 -100\n",
+            first, hash
+        );
+        let text = format!(
+            "{}! {}\nDOCID 1\nDOCID 2\n",
+            second,
+            lines_signature(&second.lines().collect::<Vec<_>>())
+        );
+        let note = Note::from_raw_note(crate::parser::parse(&text), false, &NoteContext::default())
+            .unwrap();
+        // Effective note
+        assert!(matches!(note.payment_kind, Some(PaymentKind::Cash)));
+        assert_eq!(note.transactions.len(), 2);
+
+        let revisions = note.history.revisions();
+        assert_eq!(revisions.len(), 3);
+        let signature = revisions[0].signature.as_ref().unwrap();
+        assert!(signature.valid);
+        assert_eq!(
+            signature.timestamp.as_deref(),
+            Some("2022-05-20T06:11:34+02:00")
+        );
+        assert!(revisions[1].signature.as_ref().unwrap().valid);
+        assert!(!revisions[2].is_signed());
+        let changes = revisions[1]
+            .changes
+            .iter()
+            .map(|c| c.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            changes,
+            ["override PAYMENT_KIND cash", "add TRANSACTION 5 454 -100"]
+        );
+        assert_eq!(revisions[0].changes[0].kind, ChangeKind::Set);
+
+        let raw = crate::parser::parse(&text).until_revision(1);
+        let note = Note::from_raw_note(raw, false, &NoteContext::default()).unwrap();
+        assert!(matches!(note.payment_kind, Some(PaymentKind::Transfer)));
+        assert_eq!(note.history.revisions().len(), 1);
+    }
+
+    #[test]
+    fn test_revision_warnings() {
+        let raw = crate::parser::parse("ID 1\nDOCID 1\nDOCID 2\nDOCID 3\n");
+        let note = Note::from_raw_note(raw, false, &NoteContext::default()).unwrap();
+        assert_eq!(note.docid.as_deref(), Some("3"));
+        let res = note
+            .warnings
+            .iter()
+            .map(|w| (w.code(), w.span().unwrap().line))
+            .collect::<Vec<_>>();
+        assert_eq!(res, [(Code::FieldRedefined, 3), (Code::FieldRedefined, 4)]);
+
+        // A signature not matching the signed lines is an error
+        let raw = crate::parser::parse("ID 1\nDOCID 1\n! abc\nDOCID 3\n");
+        let errors = Note::from_raw_note(raw, false, &NoteContext::default()).unwrap_err();
+        let res = errors
+            .iter()
+            .map(|e| (e.code(), e.span().unwrap().line))
+            .collect::<Vec<_>>();
+        assert_eq!(res, [(Code::InvalidSignature, 3)]);
+    }
+}
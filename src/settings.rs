@@ -1,5 +1,14 @@
 use serde::{Deserialize, Serialize};
-use std::path::Path;
+use std::{collections::BTreeMap, path::Path};
+
+use crate::{
+    diagnostic::{Code, Diagnostic},
+    i18n::Language,
+    money::{currency_precision, MoneyFormat, MAX_PRECISION},
+    namespace::{Namespace, Namespaces},
+    tr,
+    validate::Validation,
+};
 
 /// BIT Config
 #[derive(Serialize, Deserialize, Debug, Default)]
@@ -8,44 +17,115 @@ pub struct Settings {
     pub description: Option<String>,
     pub year: i32,
     pub currency: String,
+    /// Amount decimal separator, default is set by currency, . for most
+    pub decimal_separator: Option<char>,
+    /// Amount thousand separator, _ is always allowed. Default is set by
+    /// currency, . for HUF.
+    pub thousand_separator: Option<char>,
+    /// Currency minor unit digits, default is set by currency, at most 18
+    pub precision: Option<u32>,
     pub bit_version: u32,
+    /// Language of messages, e.g. hu. Default is
+    /// set by the LANG environment variable.
+    pub language: Option<String>,
+    /// Allow postings only to accounts without sub-accounts
+    #[serde(default)]
+    pub leaf_only: bool,
+    /// Invoice arithmetic rules
+    #[serde(default)]
+    pub validation: Validation,
+    /// Note ID namespaces by name
+    #[serde(default)]
+    pub namespaces: BTreeMap<String, Namespace>,
     pub dependencies: Dependencies,
 }
 
 #[derive(Serialize, Deserialize, Debug, Default)]
 pub struct Dependencies {
+    /// Account file, or directory of account files
     pub accounts_path: String,
     pub docs_path: String,
     pub notes_path: String,
 }
 
 impl Settings {
-    pub fn try_read(project_root_path: &Path) -> Result<Self, String> {
+    pub fn try_read(project_root_path: &Path) -> Result<Self, Diagnostic> {
         // Try load config file
         let cfg_file = project_root_path.join("Bit.toml");
+        let config_error =
+            |message: String| Diagnostic::error(Code::Config, message).with_path(&cfg_file);
         // Check if it exist
         match cfg_file.exists() || cfg_file.is_file() {
             true => (),
-            false => return Err("No config file found!".to_string()),
+            false => return Err(config_error(tr!("No config file found!"))),
         }
         // Try to read its content
         let cfg_content = std::fs::read_to_string(&cfg_file)
-            .map_err(|_| "Cannot read config.toml content".to_string())?;
+            .map_err(|_| config_error(tr!("Cannot read config.toml content")))?;
         // Try to deserialize its contetn
-        let settings: Settings = toml::from_str(&cfg_content)
-            .map_err(|_| "Error while deserialize config.toml".to_string())?;
+        let settings: Settings = toml::from_str(&cfg_content).map_err(|e| {
+            config_error(tr!("Error while deserialize config.toml")).with_hint(e.to_string())
+        })?;
+
+        // Amounts are i64 minor units, more digits do not fit
+        if settings
+            .precision
+            .is_some_and(|precision| precision > MAX_PRECISION)
+        {
+            return Err(config_error(tr!(
+                "Precision must be at most {}",
+                MAX_PRECISION
+            )));
+        }
+        // Check amount format
+        let money_format = settings.money_format();
+        if Some(money_format.decimal_separator) == money_format.thousand_separator {
+            return Err(config_error(tr!(
+                "Decimal and thousand separator must be different"
+            )));
+        }
+
+        if let Some(language) = &settings.language {
+            if Language::parse(language).is_none() {
+                return Err(config_error(tr!("Unknown language {}", language))
+                    .with_hint(tr!("Supported languages: en, hu")));
+            }
+        }
 
         let accounts_path = project_root_path.join(&settings.dependencies.accounts_path);
         // Check depdendencies
         if !accounts_path.exists() && !accounts_path.is_file() {
-            return Err("Accounts file does not exist!".to_string());
+            return Err(config_error(tr!(
+                "Accounts file or directory does not exist!"
+            )));
         }
 
         let notes_path = project_root_path.join(&settings.dependencies.notes_path);
         if !notes_path.exists() && !notes_path.is_dir() {
-            return Err("NOTEs path not exist or not a folder!".to_string());
+            return Err(config_error(tr!("NOTEs path not exist or not a folder!")));
         }
 
         Ok(settings)
     }
+    pub fn namespaces(&self) -> Namespaces {
+        Namespaces::new(self.namespaces.clone(), self.year)
+    }
+    /// Language set in the config
+    pub fn language(&self) -> Option<Language> {
+        self.language.as_deref().and_then(Language::parse)
+    }
+    pub fn money_format(&self) -> MoneyFormat {
+        let default = MoneyFormat::for_currency(&self.currency);
+        let decimal_separator = self.decimal_separator.unwrap_or(default.decimal_separator);
+        MoneyFormat {
+            precision: self
+                .precision
+                .unwrap_or_else(|| currency_precision(&self.currency)),
+            decimal_separator,
+            // A configured decimal separator wins over the default
+            thousand_separator: self.thousand_separator.or(default
+                .thousand_separator
+                .filter(|t| *t != decimal_separator)),
+        }
+    }
 }
@@ -17,6 +17,14 @@ fn create_signature(bytes: &[u8]) -> String {
     format!("{:x}", result)
 }
 
+/// Signature of note lines, the lines joined by newlines
+///
+/// Existing notes are signed this way, changing it breaks their
+/// signatures.
+pub fn lines_signature(lines: &[&str]) -> String {
+    create_signature(lines.join("\n").as_bytes())
+}
+
 pub fn sign_note(note: NoteRaw) {
     if !note.is_signed() {
         let mut file = OpenOptions::new()
@@ -25,7 +33,12 @@ pub fn sign_note(note: NoteRaw) {
             .open(note.file_path())
             .unwrap();
 
-        let signature = create_signature(&note.raw_bytes());
+        let lines = note
+            .lines_ref()
+            .iter()
+            .map(|i| i.raw())
+            .collect::<Vec<&str>>();
+        let signature = lines_signature(&lines);
 
         if let Err(e) = writeln!(
             file,
@@ -39,16 +52,8 @@ pub fn sign_note(note: NoteRaw) {
 }
 
 pub fn check_signature(lines_slice: Vec<&Line>, signature: &str) -> bool {
-    if let text_raw = lines_slice
-        .iter()
-        .map(|i| i.raw())
-        .collect::<Vec<&str>>()
-        .join("\n")
-        .as_bytes()
-    {
-        return create_signature(text_raw) == signature;
-    }
-    false
+    let lines = lines_slice.iter().map(|i| i.raw()).collect::<Vec<&str>>();
+    lines_signature(&lines) == signature
 }
 
 #[cfg(test)]
@@ -69,6 +74,18 @@ mod tests {
         assert_eq!(&res, "90937ae2b1c2fcb71e841fd0471226da");
     }
 
+    #[test]
+    fn test_lines_signature() {
+        assert_ne!(
+            lines_signature(&["a", "b", ""]),
+            lines_signature(&["a", "b"])
+        );
+        assert_eq!(
+            lines_signature(&["a", "b"]),
+            "90937ae2b1c2fcb71e841fd0471226da"
+        );
+    }
+
     #[test]
     fn test_sign_file() {
         let note = NoteRaw::from_file(&PathBuf::from("./example/notes/c.bit")).unwrap();
@@ -79,7 +96,16 @@ mod tests {
     #[test]
     fn test_note_file() {
         let note_raw = NoteRaw::from_file(&PathBuf::from("./example/notes/c.bit")).unwrap();
-        let note: crate::note::Note = crate::note::Note::from_raw_note(note_raw, false).unwrap();
+        // Legacy example note, its lines are changed after signing
+        let ctx = crate::note::NoteContext {
+            validation: crate::validate::Validation {
+                signature: crate::validate::Rule::Warning,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let note: crate::note::Note =
+            crate::note::Note::from_raw_note(note_raw, false, &ctx).unwrap();
         println!("{:?}", note);
         assert_eq!(1, 1);
     }
@@ -87,7 +113,8 @@ mod tests {
     #[test]
     fn test_accounts_note_file() {
         let note_raw = NoteRaw::from_file(&PathBuf::from("./example/notes/accounts.bit")).unwrap();
-        let note: crate::note::Note = crate::note::Note::from_raw_note(note_raw, true).unwrap();
+        let note: crate::note::Note =
+            crate::note::Note::from_raw_note(note_raw, true, &Default::default()).unwrap();
         println!("{:?}", note);
         assert_eq!(1, 1);
     }
@@ -0,0 +1,372 @@
+use serde::Serialize;
+use std::{collections::BTreeMap, path::PathBuf};
+
+use crate::{
+    diagnostic::{Code, Diagnostic, Span},
+    note::{is_key, Tag, TRANSACTION_KEYS},
+    parser::{Token, TokenKind},
+    tr,
+};
+
+/// Named transaction pattern, defined in account files
+///
+/// | TEMPLATE purchase(expense, supplier, net, vat)
+/// |   DEBIT  expense net
+/// |   DEBIT  466     vat
+/// |   CREDIT supplier net + vat;
+///
+/// Notes call it with USE purchase 5 454/agroker 11_811 3_189, and the
+/// body is expanded into the parameters of an ordinary transaction.
+#[derive(Debug, Clone, Serialize)]
+pub struct Template {
+    pub name: String,
+    pub params: Vec<String>,
+    /// Transaction parameters with the template parameters in them
+    pub body: Vec<Token>,
+    pub path: PathBuf,
+    /// Span of the signature
+    pub span: Span,
+}
+
+impl Template {
+    /// Parse signature and body, e.g. purchase(expense, supplier) DEBIT ..
+    pub fn parse(first_token: &Token, mut params: Vec<Token>) -> Result<Self, Diagnostic> {
+        let error = |token: &Token, message: String| {
+            Diagnostic::error(Code::InvalidTemplate, message)
+                .with_span(token.span())
+                .with_hint(tr!(
+                    "Template signature is name(param, ..), e.g. purchase(expense, net)"
+                ))
+        };
+        // Signature lasts till the token closing the parameter list
+        let end = params
+            .iter()
+            .position(|t| text(t).ends_with(')'))
+            .ok_or_else(|| error(first_token, tr!("TEMPLATE must have a signature")))?;
+        let body = params.split_off(end + 1);
+        let signature = params.iter().map(text).collect::<String>();
+        let span = Span::new(
+            params[0].span().line,
+            params[0].span().column,
+            params[end].span().column + params[end].span().len - params[0].span().column,
+        );
+        let (name, list) = signature
+            .strip_suffix(')')
+            .and_then(|s| s.split_once('('))
+            .ok_or_else(|| error(&params[0], tr!("Invalid template signature")))?;
+        if !is_identifier(name) {
+            return Err(error(&params[0], tr!("Invalid template name")));
+        }
+        let mut names: Vec<String> = Vec::new();
+        for param in list.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            if !is_identifier(param) || names.iter().any(|n| n == param) {
+                return Err(error(
+                    &params[0],
+                    tr!("Invalid or duplicate template parameter {}", param),
+                ));
+            }
+            names.push(param.to_string());
+        }
+        if body.is_empty() {
+            return Err(
+                error(first_token, tr!("TEMPLATE must have a transaction body")).with_hint(tr!(
+                    "e.g. TEMPLATE pay(supplier, bank, amount) supplier bank amount"
+                )),
+            );
+        }
+        Ok(Self {
+            name: name.to_string(),
+            params: names,
+            body,
+            path: PathBuf::new(),
+            span,
+        })
+    }
+    /// Transaction parameters of a call
+    ///
+    /// Parameters are replaced by the call arguments, other definition
+    /// tokens get the span of the call, so diagnostics point to the note.
+    /// Arguments after the template parameters are appended, e.g. EVENT_ID.
+    /// Arguments in expressions are grouped, so x/2 of GROSS-NET is
+    /// (GROSS-NET)/2.
+    pub fn expand(&self, call: &Token, args: &[Token]) -> Vec<Token> {
+        let (args, rest) = args.split_at(self.params.len().min(args.len()));
+        let arg = |name: &str| self.params.iter().position(|p| p == name).map(|i| &args[i]);
+        let accounts = account_positions(&self.body);
+        self.body
+            .iter()
+            .enumerate()
+            .map(|(i, token)| {
+                if token.is_quoted() {
+                    return token.with_text(text(token).to_string(), call.span());
+                }
+                if let Some(arg) = arg(text(token)) {
+                    // Operator tokens around, e.g. x / 2
+                    let in_expression = [i.wrapping_sub(1), i + 1]
+                        .iter()
+                        .filter_map(|i| self.body.get(*i))
+                        .any(|t| !t.is_quoted() && is_operator(text(t)));
+                    return match in_expression && !accounts.contains(&i) {
+                        true => arg.with_text(group(text(arg)), arg.span()),
+                        false => arg.clone(),
+                    };
+                }
+                // Account IDs are never expressions, e.g. 466/vat
+                if accounts.contains(&i) {
+                    return token.with_text(text(token).to_string(), call.span());
+                }
+                // Parameters inside expressions, e.g. net+vat
+                let mut res = String::new();
+                let mut word = String::new();
+                for ch in text(token).chars().chain(std::iter::once(' ')) {
+                    match ch.is_alphanumeric() || ch == '_' {
+                        true => word.push(ch),
+                        false => {
+                            match arg(&word) {
+                                Some(arg) => res.push_str(&group(text(arg))),
+                                None => res.push_str(&word),
+                            }
+                            word.clear();
+                            res.push(ch);
+                        }
+                    }
+                }
+                res.pop();
+                token.with_text(res, call.span())
+            })
+            .chain(rest.iter().cloned())
+            .collect()
+    }
+    pub fn signature(&self) -> String {
+        format!("{}({})", self.name, self.params.join(", "))
+    }
+    /// Location of the definition, path:line:column
+    pub fn location(&self) -> String {
+        format!(
+            "{}:{}:{}",
+            self.path.display(),
+            self.span.line,
+            self.span.column
+        )
+    }
+}
+
+/// Templates of a project by name
+#[derive(Debug, Default, Clone)]
+pub struct Templates {
+    templates: BTreeMap<String, Template>,
+}
+
+impl Templates {
+    /// Add template, names must be unique
+    pub fn add(&mut self, template: Template) -> Result<(), Diagnostic> {
+        if let Some(first) = self.templates.get(&template.name) {
+            return Err(Diagnostic::error(
+                Code::DuplicateId,
+                tr!("Template {} is defined more than once", template.name),
+            )
+            .with_path(&template.path)
+            .with_span(template.span)
+            .with_hint(tr!("First defined at {}", first.location())));
+        }
+        self.templates.insert(template.name.clone(), template);
+        Ok(())
+    }
+    pub fn get(&self, name: &str) -> Option<&Template> {
+        self.templates.get(name)
+    }
+}
+
+fn text(token: &Token) -> &str {
+    match token.token_kind_ref() {
+        TokenKind::Text(text) => text,
+        _ => "",
+    }
+}
+
+// Body tokens in account position, the first two of the simple form,
+// e.g. 5 454 net, and the ones after DEBIT and CREDIT
+fn account_positions(body: &[Token]) -> Vec<usize> {
+    let params = body
+        .iter()
+        .enumerate()
+        .filter(|(_, t)| t.is_quoted() || Tag::parse(text(t)).is_none())
+        .collect::<Vec<(usize, &Token)>>();
+    match params.first() {
+        Some((_, first)) if is_key(first, TRANSACTION_KEYS) => params
+            .windows(2)
+            .filter(|pair| is_key(pair[0].1, &["DEBIT", "CREDIT"]))
+            .map(|pair| pair[1].0)
+            .collect(),
+        _ => params.iter().take(2).map(|(i, _)| *i).collect(),
+    }
+}
+
+fn is_operator(text: &str) -> bool {
+    !text.is_empty() && text.chars().all(|c| "+-*/".contains(c))
+}
+
+// Argument in parentheses, unless it is a single number or name
+fn group(text: &str) -> String {
+    match text
+        .chars()
+        .all(|c| c.is_alphanumeric() || c == '_' || c == '.' || c == ',')
+    {
+        true => text.to_string(),
+        false => format!("({})", text),
+    }
+}
+
+fn is_identifier(text: &str) -> bool {
+    text.chars()
+        .next()
+        .is_some_and(|c| c.is_alphabetic() || c == '_')
+        && text.chars().all(|c| c.is_alphanumeric() || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::note::{Note, NoteContext, Side};
+
+    fn context(account_file: &str) -> NoteContext {
+        let raw = crate::parser::parse(account_file);
+        let note = Note::from_raw_note(raw, true, &NoteContext::default()).unwrap();
+        let mut ctx = NoteContext::default();
+        for template in note.templates {
+            ctx.templates.add(template).unwrap();
+        }
+        ctx
+    }
+
+    #[test]
+    fn test_template() {
+        let ctx = context(
+            "TEMPLATE purchase(expense, supplier, net, vat)\n  DEBIT  expense net\n  DEBIT  466 vat\n  CREDIT supplier net+vat;\nTEMPLATE pay( supplier, bank, amount ) supplier bank amount\n",
+        );
+        let purchase = ctx.templates.get("purchase").unwrap();
+        assert_eq!(
+            purchase.signature(),
+            "purchase(expense, supplier, net, vat)"
+        );
+        assert_eq!(purchase.span, Span::new(1, 10, 37));
+
+        let raw = crate::parser::parse(
+            "ID 1\nCOMPLETION_DATE 2022-01-10\nUSE purchase 5 454/agroker 11_811 3_189 #fuel\nUSE pay 454/agroker 384 15_000 CDATE 2022-02-01\n",
+        );
+        let note = Note::from_raw_note(raw, false, &ctx).unwrap();
+        let legs = &note.transactions[0].legs;
+        let res = legs
+            .iter()
+            .map(|l| (l.side, l.account.as_str(), l.amount.to_string()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            res,
+            [
+                (Side::Debit, "5", "11811".to_string()),
+                (Side::Debit, "466", "3189".to_string()),
+                (Side::Credit, "454/agroker", "15000".to_string())
+            ]
+        );
+        // Definition tokens point to the call
+        assert_eq!(legs[1].account_span, Span::new(3, 1, 3));
+        assert_eq!(legs[2].account_span, Span::new(3, 16, 11));
+        assert_eq!(note.transactions[0].tags.len(), 1);
+        assert_eq!(note.transactions[1].legs[1].account, "384");
+        assert!(note.transactions[1].completion_date.is_some());
+    }
+
+    #[test]
+    fn test_template_errors() {
+        let raw = crate::parser::parse(
+            "TEMPLATE bad(a, a) a b 1\nTEMPLATE (a) a b 1\nTEMPLATE empty(a)\n",
+        );
+        let err = Note::from_raw_note(raw, true, &NoteContext::default()).unwrap_err();
+        assert!(err.iter().all(|d| d.code() == Code::InvalidTemplate));
+        assert_eq!(err.len(), 3);
+
+        let ctx = context("TEMPLATE pay(supplier, bank, amount) supplier bank amount\n");
+        let raw = crate::parser::parse(
+            "ID 1\nUSE payment 454 384 1\nUSE pay 454 384\nUSE pay 454 384 1x\n",
+        );
+        let err = Note::from_raw_note(raw, false, &ctx).unwrap_err();
+        let res = err
+            .iter()
+            .map(|d| (d.code(), d.span().unwrap().line, d.hints().len()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            res,
+            [
+                (Code::UnknownTemplate, 2, 1),
+                (Code::ParameterCount, 3, 1),
+                (Code::InvalidNumber, 4, 2)
+            ]
+        );
+        assert_eq!(
+            err.iter().nth(2).unwrap().hints()[1],
+            "Template pay(supplier, bank, amount) is defined at :1:10"
+        );
+
+        // Unbalanced body is reported at the call with the definition
+        let ctx = context("TEMPLATE half(a, b, x) DEBIT a x CREDIT b x/2;\n");
+        let raw = crate::parser::parse("ID 1\nUSE half 1 2 10\n");
+        let err = Note::from_raw_note(raw, false, &ctx).unwrap_err();
+        let e = err.iter().next().unwrap();
+        assert_eq!(e.code(), Code::UnbalancedTransaction);
+        assert_eq!(e.span(), Some(Span::new(2, 1, 3)));
+        assert_eq!(e.hints()[1], "Template half(a, b, x) is defined at :1:10");
+    }
+
+    #[test]
+    fn test_template_expression_args() {
+        let ctx = context(
+            "TEMPLATE half(a, b, x) a b x/2
+TEMPLATE spaced(a, b, x) a b x / 2
+",
+        );
+        let raw = crate::parser::parse(
+            "ID 1
+NET 100
+GROSS 128
+COMPLETION_DATE 2022-01-10
+USE half 1 2 GROSS-NET
+USE spaced 1 2 GROSS-NET
+",
+        );
+        let note = Note::from_raw_note(raw, false, &ctx).unwrap();
+        let amounts = note
+            .transactions
+            .iter()
+            .map(|t| t.legs[0].amount.to_string())
+            .collect::<Vec<String>>();
+        assert_eq!(amounts, ["14", "14"]);
+    }
+
+    #[test]
+    fn test_template_account_ids() {
+        let ctx = context(
+            "TEMPLATE vat(expense, vat) DEBIT expense vat DEBIT 466/vat vat CREDIT 454/vat vat*2;
+",
+        );
+        let raw = crate::parser::parse(
+            "ID 1
+COMPLETION_DATE 2022-01-10
+USE vat 5 27
+",
+        );
+        let note = Note::from_raw_note(raw, false, &ctx).unwrap();
+        let res = note.transactions[0]
+            .legs
+            .iter()
+            .map(|l| (l.account.as_str(), l.amount.to_string()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            res,
+            [
+                ("5", "27".to_string()),
+                ("466/vat", "27".to_string()),
+                ("454/vat", "54".to_string())
+            ]
+        );
+    }
+}
@@ -0,0 +1,252 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    diagnostic::{Code, Diagnostic, Span},
+    money::Money,
+    note::{Note, Side},
+    parser::Command,
+    tr,
+};
+
+/// Severity of a validation rule
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Rule {
+    Error,
+    #[default]
+    Warning,
+    Off,
+}
+
+impl Rule {
+    // Diagnostic of a broken rule, None if the rule is off
+    pub(crate) fn diagnostic(
+        &self,
+        code: Code,
+        message: String,
+        span: Option<Span>,
+    ) -> Option<Diagnostic> {
+        let res = match self {
+            Rule::Error => Diagnostic::error(code, message),
+            Rule::Warning => Diagnostic::warning(code, message),
+            Rule::Off => return None,
+        };
+        Some(match span {
+            Some(span) => res.with_span(span),
+            None => res,
+        })
+    }
+}
+
+/// Invoice arithmetic rules, the [validation] table of Bit.toml
+///
+/// Every rule is error, warning or off, warning by default. Rules are
+/// checked only when the note amounts they compare are set. The
+/// signature rule is error by default, lenient only for legacy notes.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Validation {
+    /// NET + VAT equals GROSS
+    pub net_vat_gross: Rule,
+    /// Postings to the VAT accounts equal VAT
+    pub vat_postings: Rule,
+    /// Posted total equals GROSS, for the note types in gross_types
+    pub gross_postings: Rule,
+    /// NET, GROSS and the transaction amounts are not zero
+    pub nonzero: Rule,
+    /// VAT accounts, accounts starting with them are VAT accounts as well
+    pub vat_accounts: Vec<String>,
+    /// Note TYPEs whose postings must add up to GROSS, e.g. invoice
+    pub gross_types: Vec<String>,
+    /// Signatures match the lines they sign
+    pub signature: Rule,
+}
+
+impl Default for Validation {
+    fn default() -> Self {
+        Self {
+            net_vat_gross: Rule::default(),
+            vat_postings: Rule::default(),
+            gross_postings: Rule::default(),
+            nonzero: Rule::default(),
+            vat_accounts: Vec::new(),
+            gross_types: Vec::new(),
+            signature: Rule::Error,
+        }
+    }
+}
+
+impl Validation {
+    /// Check the amounts of a note against each other
+    pub fn check(&self, note: &Note) -> Vec<Diagnostic> {
+        let span = |command| note.history.span(command);
+        let mut res = Vec::new();
+        if let (Some(net), Some(vat), Some(gross)) = (note.net, note.vat, note.gross) {
+            let sum = net.checked_add(&vat);
+            if sum != Some(gross) {
+                let diagnostic = self.net_vat_gross.diagnostic(
+                    Code::TotalMismatch,
+                    tr!("NET + VAT does not equal GROSS"),
+                    span(Command::Gross),
+                );
+                res.extend(diagnostic.map(|d| match sum {
+                    Some(sum) => d.with_hint(tr!("NET + VAT is {}, GROSS is {}", sum, gross)),
+                    None => d,
+                }));
+            }
+        }
+        if let Some(vat) = note.vat.filter(|_| !self.vat_accounts.is_empty()) {
+            // Input VAT is debited, output VAT is credited
+            let posted = note
+                .transactions
+                .iter()
+                .flat_map(|t| &t.legs)
+                .filter(|leg| self.is_vat_account(&leg.account))
+                .try_fold(Money::default(), |sum, leg| match leg.side {
+                    Side::Debit => sum.checked_add(&leg.amount),
+                    Side::Credit => sum.checked_sub(&leg.amount),
+                })
+                .map(|posted| posted.abs());
+            if posted != Some(vat.abs()) {
+                let diagnostic = self.vat_postings.diagnostic(
+                    Code::VatMismatch,
+                    tr!("VAT postings do not equal VAT"),
+                    span(Command::Vat),
+                );
+                res.extend(diagnostic.map(|d| match posted {
+                    Some(posted) => d.with_hint(tr!(
+                        "Posted to {} is {}, VAT is {}",
+                        self.vat_accounts.join(", "),
+                        posted,
+                        vat
+                    )),
+                    None => d,
+                }));
+            }
+        }
+        let is_gross_type = note.note_type.as_deref().is_some_and(|note_type| {
+            self.gross_types
+                .iter()
+                .any(|t| t.eq_ignore_ascii_case(note_type))
+        });
+        if let Some(gross) = note.gross.filter(|_| is_gross_type) {
+            let posted = note
+                .transactions
+                .iter()
+                .try_fold(Money::default(), |sum, t| {
+                    sum.checked_add(&t.total(Side::Debit)?)
+                });
+            if posted != Some(gross.abs()) {
+                let diagnostic = self.gross_postings.diagnostic(
+                    Code::GrossMismatch,
+                    tr!("Posted total does not equal GROSS"),
+                    span(Command::Gross),
+                );
+                res.extend(diagnostic.map(|d| match posted {
+                    Some(posted) => {
+                        d.with_hint(tr!("Posted total is {}, GROSS is {}", posted, gross))
+                    }
+                    None => d,
+                }));
+            }
+        }
+        // Zero VAT is valid, e.g. on VAT exempt invoices
+        for (command, amount) in [(Command::Net, note.net), (Command::Gross, note.gross)] {
+            if amount.is_some_and(|amount| amount.is_zero()) {
+                res.extend(self.nonzero.diagnostic(
+                    Code::ZeroAmount,
+                    tr!("{} must not be zero", command.keyword()),
+                    span(command),
+                ));
+            }
+        }
+        for transaction in &note.transactions {
+            if transaction.legs.iter().any(|leg| leg.amount.is_zero()) {
+                res.extend(self.nonzero.diagnostic(
+                    Code::ZeroAmount,
+                    tr!("Transaction amount must not be zero"),
+                    Some(transaction.span),
+                ));
+            }
+        }
+        res
+    }
+    fn is_vat_account(&self, account: &str) -> bool {
+        self.vat_accounts
+            .iter()
+            .any(|vat| account.starts_with(vat.as_str()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::note::NoteContext;
+
+    fn check(text: &str, validation: Validation) -> Vec<Diagnostic> {
+        let ctx = NoteContext {
+            validation,
+            ..Default::default()
+        };
+        let raw = crate::parser::parse(text);
+        match Note::from_raw_note(raw, false, &ctx) {
+            Ok(note) => note.warnings,
+            Err(diagnostics) => diagnostics.into_iter().collect(),
+        }
+    }
+
+    #[test]
+    fn test_validation() {
+        let validation = || Validation {
+            net_vat_gross: Rule::Error,
+            vat_accounts: vec!["466".to_string()],
+            gross_types: vec!["invoice".to_string()],
+            ..Default::default()
+        };
+        let valid = "ID 1\nTYPE invoice\nNET 100\nVAT 27\nGROSS 127\n\
+            COMPLETION_DATE 2022-04-01\n> 5 454 NET\n> 4661 454 VAT\n";
+        assert!(check(valid, validation()).is_empty());
+
+        let note = "ID 1\nTYPE Invoice\nNET 100\nVAT 27\nGROSS 128\n\
+            COMPLETION_DATE 2022-04-01\n> 5 454 NET\n> 5 454 VAT\n> 5 454 0\n";
+        let res = check(note, validation());
+        let codes = res.iter().map(|d| d.code()).collect::<Vec<Code>>();
+        assert_eq!(
+            codes,
+            [
+                Code::TotalMismatch,
+                Code::VatMismatch,
+                Code::GrossMismatch,
+                Code::ZeroAmount
+            ]
+        );
+        assert!(res[0].is_error());
+        assert_eq!(res[0].span(), Some(Span::new(5, 1, 5)));
+        assert_eq!(res[0].hints(), ["NET + VAT is 127, GROSS is 128"]);
+        assert_eq!(res[1].hints(), ["Posted to 466 is 0, VAT is 27"]);
+        assert_eq!(res[2].hints(), ["Posted total is 127, GROSS is 128"]);
+        assert_eq!(res[3].span(), Some(Span::new(9, 1, 1)));
+
+        let off = Validation {
+            net_vat_gross: Rule::Off,
+            vat_postings: Rule::Off,
+            gross_postings: Rule::Off,
+            nonzero: Rule::Off,
+            ..validation()
+        };
+        assert!(check(note, off).is_empty());
+    }
+
+    #[test]
+    fn test_signature_rule() {
+        let note = "ID 1\n! abc\n";
+        let res = check(note, Validation::default());
+        assert_eq!(res[0].code(), Code::InvalidSignature);
+        assert!(res[0].is_error());
+
+        let legacy: Validation = toml::from_str("signature = \"warning\"").unwrap();
+        let res = check(note, legacy);
+        assert_eq!(res[0].code(), Code::InvalidSignature);
+        assert!(!res[0].is_error());
+    }
+}